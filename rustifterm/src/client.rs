@@ -1,8 +1,19 @@
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
 use std::path::Path;
 
 use crate::errors::IFtResult;
-use rustifzm::ZMachine;
+use crate::terminal::IFTerminalIo;
+use rustifzm::{ZInput, ZInterpreterCapabilities, ZMachine, ZMachineEvent};
+
+/// Number of instructions executed between two checks for the player's input.
+const RUN_BUDGET: usize = 100_000;
+
+/// Default name of the Quetzal save files.
+const DEFAULT_SAVE_FILE: &str = "story.qzl";
+
+/// Default name of the auxiliary files saved by V5+ stories.
+const DEFAULT_AUXILIARY_FILE: &str = "story.aux";
 
 /// The Interactive Fiction Terminal Client is the frontend interface
 /// used to play a story file by managing user input and game output.
@@ -13,14 +24,118 @@ pub struct IFTerminalClient {
 impl IFTerminalClient {
     pub fn with_story_file(story_path: &Path) -> IFtResult<Self> {
         let mut story_file = File::open(story_path)?;
-        let vm = ZMachine::from_story_reader(&mut story_file)?;
+        let vm = ZMachine::from_story_reader(
+            &mut story_file,
+            ZInterpreterCapabilities::default(),
+            Box::new(IFTerminalIo::default()),
+        )?;
         Ok(IFTerminalClient { vm })
     }
 
     pub fn run(&mut self) -> IFtResult<()> {
-        for _ in 0..10 {
-            self.vm.step()?;
+        loop {
+            let input = match self.vm.run(RUN_BUDGET) {
+                // the player is gone once the input has ended, e.g. piped from a file
+                ZMachineEvent::NeedLine { max_len, .. } => match Self::read_line()? {
+                    Some(line) => ZInput::Line(line.chars().take(max_len).collect()),
+                    None => return Ok(()),
+                },
+                // without a raw terminal mode, a whole line is read and only its first character kept
+                ZMachineEvent::NeedChar { .. } => {
+                    let line = match Self::read_line()? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    };
+                    ZInput::Char(match line.chars().next() {
+                        Some(character) if character.is_ascii_graphic() || character == ' ' => {
+                            character as u16
+                        }
+                        _ => 13,
+                    })
+                }
+                ZMachineEvent::SaveRequested => ZInput::Saved(Self::report(self.save_game())),
+                // once restored, the story resumes from where it was saved
+                ZMachineEvent::RestoreRequested => {
+                    if Self::report(self.restore_game()) {
+                        continue;
+                    }
+                    ZInput::RestoreFailed
+                }
+                ZMachineEvent::SaveTableRequested { name, data } => {
+                    ZInput::Saved(Self::report(Self::write_file(name, &data)))
+                }
+                ZMachineEvent::RestoreTableRequested { name, .. } => {
+                    Self::read_file(name, DEFAULT_AUXILIARY_FILE)
+                        .map(ZInput::TableRestored)
+                        .unwrap_or_else(|error| {
+                            eprintln!("{}", error);
+                            ZInput::RestoreFailed
+                        })
+                }
+                ZMachineEvent::BudgetExhausted => continue,
+                ZMachineEvent::Quit => return Ok(()),
+                ZMachineEvent::Error(error) => return Err(error.into()),
+            };
+            self.vm.provide_input(input)?;
         }
+    }
+
+    fn save_game(&self) -> IFtResult<()> {
+        let data = self.vm.save()?;
+        let name = Self::file_name(None, DEFAULT_SAVE_FILE)?;
+        fs::write(name, data)?;
+        Ok(())
+    }
+
+    fn restore_game(&mut self) -> IFtResult<()> {
+        let data = Self::read_file(None, DEFAULT_SAVE_FILE)?;
+        self.vm.restore(&data)?;
+        Ok(())
+    }
+
+    fn write_file(name: Option<String>, data: &[u8]) -> IFtResult<()> {
+        let name = Self::file_name(name, DEFAULT_AUXILIARY_FILE)?;
+        fs::write(name, data)?;
         Ok(())
     }
+
+    fn read_file(name: Option<String>, default: &str) -> IFtResult<Vec<u8>> {
+        let name = Self::file_name(name, default)?;
+        Ok(fs::read(name)?)
+    }
+
+    /// Use the name suggested by the story, or else ask the player for one.
+    fn file_name(suggested: Option<String>, default: &str) -> IFtResult<String> {
+        if let Some(name) = suggested {
+            return Ok(name);
+        }
+        print!("File name [{}]: ", default);
+        let name = Self::read_line()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no file name given"))?;
+        Ok(match name.trim() {
+            "" => default.to_string(),
+            name => name.to_string(),
+        })
+    }
+
+    /// The story tells the player about failures: only their cause is shown.
+    fn report(result: IFtResult<()>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(error) => {
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    /// Read a line of the player's input, or `None` once it has ended.
+    fn read_line() -> IFtResult<Option<String>> {
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
 }
@@ -1,5 +1,6 @@
 mod client;
 mod errors;
+mod terminal;
 
 use std::path::{Path, PathBuf};
 
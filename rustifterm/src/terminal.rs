@@ -0,0 +1,65 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+use rustifzm::{
+    zio::{ZStatusLine, ZStatusProgress},
+    ZIoSystem,
+};
+
+/// File receiving the transcript (output stream 2).
+const TRANSCRIPT_FILE: &str = "transcript.txt";
+
+/// File receiving the record of the player's commands (output stream 4).
+const COMMANDS_FILE: &str = "commands.rec";
+
+/// The terminal's standard output as the Z-machine's screen.
+///
+/// Only plain text is supported: styles, windows and colours are ignored,
+/// and the status line is printed as a line of its own.
+/// The transcript and the commands record are appended to files of the current directory,
+/// opened the first time the story selects them.
+#[derive(Default)]
+pub struct IFTerminalIo {
+    transcript: Option<File>,
+    commands: Option<File>,
+}
+
+impl IFTerminalIo {
+    /// Appends the text to the given file, opening it if needed.
+    ///
+    /// Failures are ignored: the story has no way to deal with them.
+    fn append(file: &mut Option<File>, path: &str, text: &str) {
+        if file.is_none() {
+            *file = OpenOptions::new().create(true).append(true).open(path).ok();
+        }
+        if let Some(file) = file {
+            file.write_all(text.as_bytes()).ok();
+        }
+    }
+}
+
+impl ZIoSystem for IFTerminalIo {
+    fn print(&mut self, text: &str) {
+        print!("{}", text);
+    }
+
+    fn print_transcript(&mut self, text: &str) {
+        Self::append(&mut self.transcript, TRANSCRIPT_FILE, text);
+    }
+
+    fn record_input(&mut self, input: &str) {
+        Self::append(&mut self.commands, COMMANDS_FILE, &format!("{}\n", input));
+    }
+
+    fn show_status(&mut self, status: &ZStatusLine) {
+        let progress = match status.progress {
+            ZStatusProgress::Score { score, turns } => format!("Score: {} Turns: {}", score, turns),
+            ZStatusProgress::Time { hours, minutes } => format!("Time: {}:{:02}", hours, minutes),
+        };
+        println!("[{} - {}]", status.location, progress);
+    }
+
+    fn quit(&mut self) {
+        io::stdout().flush().ok();
+    }
+}
@@ -1,6 +1,7 @@
 use thiserror::Error;
 
-use crate::zmachine::header::ZMachineVersion;
+use crate::zcpu::ZOpcodeClass;
+use crate::zmachine::{header::ZMachineVersion, ZInput};
 use crate::zmemory::ZMemoryAddress;
 
 #[derive(Debug, Error)]
@@ -12,16 +13,75 @@ pub enum ZmError {
     MachineUnknownVersion(u8),
     #[error("Unsupported Z-machine version {0}")]
     MachineUnsupportedVersion(ZMachineVersion),
+    #[error("Story file of {0} bytes is too short to hold a header")]
+    MachineStoryTooShort(usize),
+    #[error("Unexpected input {0:?}: the machine is not waiting for it")]
+    MachineUnexpectedInput(ZInput),
+    #[error("Cannot save the game: the story is not executing a save instruction")]
+    MachineSaveNotRequested,
 
     #[error("Invalid memory access at address {0:#X}")]
     MemoryInvalidAccess(usize),
     #[error("Invalid or unexpected memory address {0}")]
     MemoryInvalidAddress(ZMemoryAddress),
+    #[error("Illegal write outside of dynamic memory at address {1:#X} (PC = {0:#X})")]
+    MemoryIllegalWrite(u32, u16),
+
+    #[error("Illegal opcode {0}:{1} for Z-machine version {2}")]
+    InstructionIllegalOpcode(ZOpcodeClass, u8, ZMachineVersion),
+    #[error("Unimplemented opcode {0}")]
+    InstructionUnimplementedOpcode(&'static str),
+    #[error("Missing operand for opcode {0}")]
+    InstructionMissingOperand(&'static str),
+
+    #[error("Stack overflow: more than {0} nested routine calls")]
+    CpuStackOverflow(usize),
+    #[error("Stack underflow: the current routine's evaluation stack is empty")]
+    CpuStackUnderflow,
+    #[error("Invalid local variable {0:#04X} for the current routine")]
+    CpuInvalidLocalVariable(u8),
+    #[error("Invalid routine header at address {0:#X}: {1} local variables")]
+    CpuInvalidRoutineLocalsCount(u32, u8),
+    #[error("Cannot return from the main routine")]
+    CpuReturnFromMainRoutine,
+    #[error("Invalid stack frame {0} to throw to")]
+    CpuInvalidStackFrame(u16),
+    #[error("Division by zero")]
+    CpuDivisionByZero,
+    #[error("Output stream 3 selected more than {0} times without being deselected")]
+    CpuOutputStreamOverflow(usize),
+
+    #[error("Invalid object number {0}")]
+    ObjectInvalidNumber(u16),
+    #[error("Invalid object attribute {0}")]
+    ObjectInvalidAttribute(u16),
+    #[error("Invalid object property {0}")]
+    ObjectInvalidProperty(u16),
+    #[error("Object {0} has no property {1}")]
+    ObjectMissingProperty(u16, u16),
+    #[error("Corrupted object tree: object {0} is not a child of its parent")]
+    ObjectCorruptedTree(u16),
+
+    #[error("Invalid dictionary entry length {0}: too short to hold the encoded text")]
+    DictionaryInvalidEntryLength(u8),
+    #[error("Invalid dictionary entry {0}")]
+    DictionaryInvalidEntry(u16),
+
+    #[error("Invalid Quetzal save file: {0}")]
+    QuetzalInvalidFile(&'static str),
+    #[error("The saved game belongs to another story file")]
+    QuetzalStoryMismatch,
 
     #[error("Invalid Alphabet shift character {0}")]
     StringInvalidAlphabetShiftCharacter(u8),
     #[error("Invalid ZSCII character {0}")]
     StringInvalidZSCIICharacterCode(u16),
+    #[error("Abbreviations cannot be used inside abbreviations")]
+    StringNestedAbbreviation,
+    #[error("Abbreviation used without an abbreviations table")]
+    StringMissingAbbreviationsTable,
+    #[error("Character {0:?} cannot be encoded in ZSCII")]
+    StringUnencodableCharacter(char),
 }
 
 pub type ZmResult<T> = Result<T, ZmError>;
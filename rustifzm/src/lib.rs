@@ -1,12 +1,18 @@
 pub mod errors;
 pub mod zcpu;
+pub mod zdictionary;
+pub mod zio;
 pub mod zmachine;
 pub mod zmemory;
 pub mod zobjects;
 pub mod zstring;
 
 pub use errors::{ZmError, ZmResult};
-pub use zmachine::{header::ZMachineVersion, ZMachine};
+pub use zio::ZIoSystem;
+pub use zmachine::{
+    header::ZMachineVersion, ZInput, ZInterpreterCapabilities, ZMachine, ZMachineEvent,
+    ZStoryValidation,
+};
 
 #[macro_use]
 extern crate bitflags;
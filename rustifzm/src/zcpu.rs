@@ -1,12 +1,76 @@
+mod frame;
 mod instructions;
 mod opcodes;
+mod random;
+mod streams;
+mod undo;
 
 use crate::{
-    zmachine::ZMachineHeader,
-    zmemory::{ZMemory, ZMemoryAddress::*},
+    zdictionary::ZDictionary,
+    zio::{ZColor, ZFont, ZIoSystem, ZStatusLine, ZStatusProgress, ZTextStyle, ZWindow},
+    zmachine::{
+        header::{ZMachineHeaderFlags1, ZMachineHeaderFlags2},
+        ZInput, ZMachineEvent, ZMachineHeader, ZQuetzal,
+    },
+    zmemory::{ZMemory, ZMemoryAddress, ZMemoryAddress::*},
+    zobjects::ZObjectsTable,
+    zstring::{ZString, ZStringCodec, ZSCII},
     ZMachineVersion, ZmError, ZmResult,
 };
-use instructions::Operation;
+pub use frame::{ZCallFrame, ZCALL_FRAME_MAX_LOCALS};
+use instructions::{InstructionBranchTarget, InstructionOperand, Operation};
+pub use opcodes::{ZOpcode, ZOpcodeClass};
+use random::ZRandom;
+use streams::ZOutputStreams;
+use undo::ZUndoRing;
+pub use undo::{ZUndoState, DEFAULT_UNDO_CAPACITY};
+
+/// Default maximum number of nested routine calls.
+pub const DEFAULT_STACK_DEPTH_LIMIT: usize = 1024;
+
+/// An instruction waiting for the host before it can complete.
+#[derive(Clone)]
+pub struct ZPendingRequest {
+    /// The waiting instruction, to store or branch once completed.
+    operation: Operation,
+    kind: ZRequestKind,
+}
+
+#[derive(Clone)]
+enum ZRequestKind {
+    Line {
+        text_buffer: u16,
+        parse_buffer: u16,
+        max_length: u16,
+        interrupt: Option<ZInterrupt>,
+    },
+    Char {
+        interrupt: Option<ZInterrupt>,
+    },
+    Save {
+        /// Address of the instruction's branch data or store variable, saved as the game's PC.
+        result_pc: u32,
+    },
+    Restore,
+    /// (V5+) Saving the given bytes of a table to an auxiliary file.
+    SaveTable {
+        name: Option<String>,
+        data: Vec<u8>,
+    },
+    /// (V5+) Restoring at most `length` bytes of an auxiliary file into a table.
+    RestoreTable {
+        name: Option<String>,
+        table: u16,
+        length: u16,
+    },
+}
+
+/// (V4+) The routine to call every `time` tenths of a second during a timed input.
+#[derive(Copy, Clone)]
+struct ZInterrupt {
+    time: u16,
+    routine: u16,
+}
 
 /// The Z-machine's processing unit.
 ///
@@ -16,43 +80,2253 @@ pub struct ZCpu {
     /// The targeted Z-machine version.
     target: ZMachineVersion,
     /// The Program Counter points to the absolute address of the current instruction.
-    pc: u16,
+    pc: u32,
+    /// Address of the global variables table, 240 words referred to as variables 0x10 to 0xFF.
+    globals_address: u16,
+    /// (V6 and V7) Routines offset from the header, used to unpack routine addresses.
+    routines_offset: u16,
+    /// (V6 and V7) Strings offset from the header, used to unpack string addresses.
+    strings_offset: u16,
+    /// The call stack, with the currently executing routine on top.
+    ///
+    /// The bottom frame holds the "main" routine's evaluation stack: from V1 to V5 it has
+    /// no local variables and can never be returned from (R5.5).
+    frames: Vec<ZCallFrame>,
+    /// Maximum number of frames in the call stack.
+    stack_depth_limit: usize,
+    /// The story's objects table.
+    objects: ZObjectsTable,
+    /// The story's tables to decode and encode text.
+    codec: ZStringCodec,
+    /// Address of the main dictionary, parsed on demand by the lexical analysis.
+    dictionary_address: ZMemoryAddress,
+    /// (V1 to V3) Does the status line show the time rather than the score (R8.2.3.2)?
+    time_game: bool,
+    /// The window receiving the output.
+    window: ZWindow,
+    /// The current font (R8.1.3).
+    font: ZFont,
+    /// The output streams selected (R7).
+    streams: ZOutputStreams,
+    /// Width of a character in units, for the width of the text sent to output stream 3.
+    character_width: u16,
+    /// The random number generator used by the `random` opcode.
+    random: ZRandom,
+    /// Set once the story has quit, after which no instruction is executed anymore.
+    halted: bool,
+    /// Set by the `restart` opcode until the machine restarts the story,
+    /// no instruction being executed in the meantime.
+    restarting: bool,
+    /// Set by the `restore_undo` opcode until the machine restores the most recent undo state,
+    /// no instruction being executed in the meantime.
+    restoring_undo: bool,
+    /// The instruction waiting for the host, if any: nothing is executed until it completes.
+    request: Option<ZPendingRequest>,
+    /// Depth of the call stack while a timed input's interrupt routine is running.
+    interrupt_depth: Option<usize>,
+    /// Value returned by the last interrupt routine, telling whether to terminate the input.
+    interrupt_result: Option<u16>,
+    /// (V3+) Does the story file's checksum match its header's, for the `verify` opcode?
+    verified: bool,
+    /// The states to go back to with `restore_undo`.
+    undo: ZUndoRing,
 }
 
 impl ZCpu {
-    pub fn from_header(header: &ZMachineHeader) -> ZmResult<Self> {
+    pub fn from_header(header: &ZMachineHeader, memory: &ZMemory) -> ZmResult<Self> {
+        let mut cpu = ZCpu {
+            target: header.get_version(),
+            pc: 0,
+            globals_address: header.get_location_global_variables_table().as_byte()?,
+            routines_offset: header.get_routines_offset().unwrap_or(0),
+            strings_offset: header.get_strings_offset().unwrap_or(0),
+            frames: vec![],
+            stack_depth_limit: DEFAULT_STACK_DEPTH_LIMIT,
+            objects: ZObjectsTable::from_memory_and_header(memory, header)?,
+            codec: ZStringCodec::from_memory_and_header(memory, header)?,
+            dictionary_address: header.get_location_dictionary(),
+            time_game: header
+                .get_flags1_old()
+                .is_some_and(|flags| flags.contains(ZMachineHeaderFlags1::STATUS_LINE_TYPE)),
+            window: ZWindow::Lower,
+            font: ZFont::Normal,
+            streams: ZOutputStreams::default(),
+            character_width: header.get_font_width_units().unwrap_or(1) as u16,
+            random: ZRandom::default(),
+            halted: false,
+            restarting: false,
+            restoring_undo: false,
+            request: None,
+            interrupt_depth: None,
+            interrupt_result: None,
+            verified: header.get_checksum() == Some(memory.checksum()),
+            undo: ZUndoRing::new(DEFAULT_UNDO_CAPACITY),
+        };
         match header.get_initial_pc() {
-            Byte(pc) => Ok(ZCpu {
-                target: header.get_version(),
-                pc,
-            }),
-            _ => Err(ZmError::MemoryInvalidAddress(header.get_initial_pc())),
+            Byte(pc) => {
+                cpu.pc = pc as u32;
+                cpu.frames.push(ZCallFrame::new(0, vec![], None, 0));
+            }
+            // R5.5: in V6 the "main" routine is a real routine, which must never return
+            packed @ Packed(_) => {
+                let routine_address = packed
+                    .unpacked_routine(cpu.target, cpu.routines_offset)?
+                    .as_offset()?;
+                cpu.call_routine(memory, routine_address, &[], None)?;
+            }
+            initial_pc => return Err(ZmError::MemoryInvalidAddress(initial_pc)),
+        }
+        Ok(cpu)
+    }
+
+    /// Has the story executed the `quit` opcode?
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Has the story executed the `restart` opcode, the machine having to restart it?
+    pub fn is_restarting(&self) -> bool {
+        self.restarting
+    }
+
+    /// Has the story executed the `restore_undo` opcode, the machine having to restore
+    /// the most recent undo state?
+    pub fn is_restoring_undo(&self) -> bool {
+        self.restoring_undo
+    }
+
+    /// Start the story again from its initial state, once dynamic memory has been reloaded.
+    ///
+    /// The call stack is rebuilt from scratch, whatever its depth was. Only the stack depth limit,
+    /// the undo states and the random number generator survive.
+    pub fn restart(&mut self, header: &ZMachineHeader, memory: &ZMemory) -> ZmResult<()> {
+        let mut cpu = ZCpu::from_header(header, memory)?;
+        cpu.set_stack_depth_limit(self.stack_depth_limit);
+        std::mem::swap(&mut cpu.undo, &mut self.undo);
+        std::mem::swap(&mut cpu.random, &mut self.random);
+        *self = cpu;
+        Ok(())
+    }
+
+    pub fn get_stack_depth_limit(&self) -> usize {
+        self.stack_depth_limit
+    }
+
+    /// Set the maximum number of nested routine calls, after which calling
+    /// another routine fails with `ZmError::CpuStackOverflow`.
+    pub fn set_stack_depth_limit(&mut self, limit: usize) {
+        self.stack_depth_limit = limit.max(1);
+    }
+
+    pub fn get_undo_capacity(&self) -> usize {
+        self.undo.get_capacity()
+    }
+
+    /// Set the number of undo states kept, the oldest being discarded first.
+    ///
+    /// With a capacity of 0, the `save_undo` opcode tells the story that undo is unavailable.
+    pub fn set_undo_capacity(&mut self, capacity: usize) {
+        self.undo.set_capacity(capacity);
+    }
+
+    /// Number of undo states available.
+    pub fn get_undo_count(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Saves the current state of the machine, for a later `restore_undo_state`.
+    ///
+    /// Returns false if undo is disabled.
+    pub fn save_undo_state(&mut self, memory: &ZMemory) -> bool {
+        self.push_undo_state(memory, None)
+    }
+
+    /// Takes the most recent undo state, to go back to with `resume_undo_state`
+    /// once dynamic memory has been reloaded from it.
+    pub fn take_undo_state(&mut self) -> Option<ZUndoState> {
+        self.restoring_undo = false;
+        self.undo.pop()
+    }
+
+    /// Resumes the execution from the given undo state, once dynamic memory has been reloaded.
+    ///
+    /// R15 restore_undo: if the state was saved by the `save_undo` opcode, it then stores 2.
+    pub fn resume_undo_state(&mut self, memory: &mut ZMemory, state: ZUndoState) -> ZmResult<()> {
+        self.pc = state.pc;
+        self.frames = state.frames;
+        self.request = state.request;
+        self.halted = false;
+        self.interrupt_depth = None;
+        self.interrupt_result = None;
+        match state.save_undo {
+            Some(save_undo) => self.store(memory, &save_undo, 2),
+            None => Ok(()),
         }
     }
 
+    fn push_undo_state(&mut self, memory: &ZMemory, save_undo: Option<&Operation>) -> bool {
+        if self.undo.get_capacity() == 0 {
+            return false;
+        }
+        self.undo.push(ZUndoState {
+            memory: ZQuetzal::compressed(
+                memory.get_dynamic_memory(),
+                memory.get_original_dynamic_memory(),
+            ),
+            pc: self.pc,
+            frames: self.frames.clone(),
+            request: self.request.clone(),
+            save_undo: save_undo.cloned(),
+        })
+    }
+
+    /// Is an instruction waiting for the host to provide some input?
+    pub fn is_waiting(&self) -> bool {
+        self.request.is_some() && self.interrupt_depth.is_none() && self.interrupt_result.is_none()
+    }
+
+    /// Returns the event describing what the processor waits for, if anything.
+    pub fn get_waiting_event(&self) -> Option<ZMachineEvent> {
+        if !self.is_waiting() {
+            return None;
+        }
+        let timeout = |interrupt: &Option<ZInterrupt>| interrupt.map(|interrupt| interrupt.time);
+        self.request.as_ref().map(|request| match &request.kind {
+            ZRequestKind::Line {
+                max_length,
+                interrupt,
+                ..
+            } => ZMachineEvent::NeedLine {
+                max_len: *max_length as usize,
+                timeout: timeout(interrupt),
+            },
+            ZRequestKind::Char { interrupt } => ZMachineEvent::NeedChar {
+                timeout: timeout(interrupt),
+            },
+            ZRequestKind::Save { .. } => ZMachineEvent::SaveRequested,
+            ZRequestKind::Restore => ZMachineEvent::RestoreRequested,
+            ZRequestKind::SaveTable { name, data } => ZMachineEvent::SaveTableRequested {
+                name: name.clone(),
+                data: data.clone(),
+            },
+            ZRequestKind::RestoreTable { name, length, .. } => {
+                ZMachineEvent::RestoreTableRequested {
+                    name: name.clone(),
+                    max_len: *length as usize,
+                }
+            }
+        })
+    }
+
+    /// Returns the PC to save along with the game, if a `save` instruction waits for the host.
+    ///
+    /// Quetzal 5.8: it is the address of the instruction's branch data in V1 to V3,
+    /// and of its store variable in V4+.
+    pub fn get_save_pc(&self) -> Option<u32> {
+        match &self.request {
+            Some(ZPendingRequest {
+                kind: ZRequestKind::Save { result_pc },
+                ..
+            }) if self.is_waiting() => Some(*result_pc),
+            _ => None,
+        }
+    }
+
+    /// Check that a saved game can be resumed from the given call stack and PC,
+    /// before anything is restored: returns the `save` instruction the PC points to.
+    ///
+    /// Its branch data or store variable is read from the saved dynamic memory if found there,
+    /// and from the story's static or high memory otherwise.
+    pub fn decoded_restored_save(
+        &self,
+        memory: &ZMemory,
+        dynamic_memory: &[u8],
+        pc: u32,
+        frames: &[ZCallFrame],
+    ) -> ZmResult<Operation> {
+        if frames.len() > self.stack_depth_limit {
+            return Err(ZmError::CpuStackOverflow(self.stack_depth_limit));
+        }
+        let mut address = pc;
+        Operation::decoded_save(self.target, || {
+            let next = match dynamic_memory.get(address as usize) {
+                Some(&byte) => byte,
+                None => memory.read_byte(High(address))?,
+            };
+            address = address.wrapping_add(1);
+            Ok(next)
+        })
+    }
+
+    /// Resume the execution of a restored game, from the given call stack and PC,
+    /// once checked by `decoded_restored_save`.
+    ///
+    /// The PC points to the branch data or store variable of the `save` instruction
+    /// which saved the game: R15 restore, the instruction then completes with 2 in V4+
+    /// (or branches in V1 to V3) to let the story know it has been restored.
+    pub fn restore(
+        &mut self,
+        memory: &mut ZMemory,
+        save: &Operation,
+        pc: u32,
+        frames: Vec<ZCallFrame>,
+    ) -> ZmResult<()> {
+        self.frames = frames;
+        self.pc = pc.wrapping_add(save.get_length() as u32);
+        self.halted = false;
+        self.request = None;
+        self.interrupt_depth = None;
+        self.interrupt_result = None;
+        self.complete_save_or_restore(memory, save, 2)
+    }
+
+    /// Completes the waiting instruction with the host's input.
+    ///
+    /// Fails with `ZmError::MachineUnexpectedInput` if the input does not answer the request,
+    /// in which case the instruction keeps waiting.
+    pub fn provide_input(
+        &mut self,
+        memory: &mut ZMemory,
+        io: &mut dyn ZIoSystem,
+        input: ZInput,
+    ) -> ZmResult<()> {
+        if !self.is_waiting() {
+            return Err(ZmError::MachineUnexpectedInput(input));
+        }
+        let ZPendingRequest { operation, kind } = self
+            .request
+            .take()
+            .expect("ZCpu should have a request to wait for");
+        match (kind, input) {
+            (
+                ZRequestKind::Line {
+                    text_buffer,
+                    parse_buffer,
+                    ..
+                },
+                ZInput::Line(text),
+            ) => {
+                self.complete_line(memory, &operation, text_buffer, parse_buffer, &text, 13)?;
+                self.echo_input(memory, io, &text)
+            }
+            (ZRequestKind::Char { .. }, ZInput::Char(code)) => self.store(memory, &operation, code),
+            (
+                kind @ (ZRequestKind::Line {
+                    interrupt: Some(interrupt),
+                    ..
+                }
+                | ZRequestKind::Char {
+                    interrupt: Some(interrupt),
+                }),
+                ZInput::Timeout,
+            ) => {
+                self.request = Some(ZPendingRequest { operation, kind });
+                let routine_address = Packed(interrupt.routine)
+                    .unpacked_routine(self.target, self.routines_offset)?
+                    .as_offset()?;
+                self.call_routine(memory, routine_address, &[], None)?;
+                self.interrupt_depth = Some(self.frames.len());
+                Ok(())
+            }
+            (ZRequestKind::Save { .. } | ZRequestKind::SaveTable { .. }, ZInput::Saved(saved)) => {
+                self.complete_save_or_restore(memory, &operation, saved as u16)
+            }
+            (ZRequestKind::Restore | ZRequestKind::RestoreTable { .. }, ZInput::RestoreFailed) => {
+                self.complete_save_or_restore(memory, &operation, 0)
+            }
+            (ZRequestKind::RestoreTable { table, length, .. }, ZInput::TableRestored(data)) => {
+                let length = data.len().min(length as usize);
+                for (offset, byte) in data.into_iter().take(length).enumerate() {
+                    memory.store_byte(Byte(table.wrapping_add(offset as u16)), byte)?;
+                }
+                self.store(memory, &operation, length as u16)
+            }
+            (kind, input) => {
+                self.request = Some(ZPendingRequest { operation, kind });
+                Err(ZmError::MachineUnexpectedInput(input))
+            }
+        }
+    }
+
+    /// Get the call stack, from the bottom "main" frame to the current routine's.
+    pub fn get_frames(&self) -> &[ZCallFrame] {
+        &self.frames
+    }
+
     /// Fetch, decode and execute the next instruction.
-    pub fn step(&mut self, memory: &mut ZMemory) -> ZmResult<()> {
+    pub fn step(&mut self, memory: &mut ZMemory, io: &mut dyn ZIoSystem) -> ZmResult<()> {
+        if let Some(result) = self.interrupt_result.take() {
+            // R15 read: an interrupt routine returning true terminates the input
+            if result != 0 {
+                self.terminate_request(memory)?;
+            }
+            return Ok(());
+        }
+        if self.halted || self.restarting || self.restoring_undo || self.is_waiting() {
+            return Ok(());
+        }
+        let pc = self.pc;
         let operation = self.fetch_decoded_instruction(memory)?;
-        self.execute_decoded_instruction(memory, &operation)?;
-        Ok(())
+        self.execute_decoded_instruction(memory, io, &operation)
+            .map_err(|error| match error {
+                ZmError::MemoryIllegalWrite(_, address) => ZmError::MemoryIllegalWrite(pc, address),
+                error => error,
+            })
     }
 
+    /// Decode the instruction at the PC, then move the PC past it.
     fn fetch_decoded_instruction(&mut self, memory: &ZMemory) -> ZmResult<Operation> {
-        Operation::decoded(self.target, || {
-            let next = memory.read_byte(Byte(self.pc))?;
-            self.pc = self.pc.wrapping_add(1);
+        let mut address = self.pc;
+        let operation = Operation::decoded(self.target, || {
+            let next = memory.read_byte(High(address))?;
+            address = address.wrapping_add(1);
             Ok(next)
-        })
+        })?;
+        self.pc = self.pc.wrapping_add(operation.get_length() as u32);
+        Ok(operation)
     }
 
     fn execute_decoded_instruction(
+        &mut self,
+        memory: &mut ZMemory,
+        io: &mut dyn ZIoSystem,
+        operation: &Operation,
+    ) -> ZmResult<()> {
+        use ZOpcode::*;
+        let operands = self.read_operands(memory, operation)?;
+        let a = operands.first().cloned().unwrap_or(0);
+        let b = operands.get(1).cloned().unwrap_or(0);
+        match operation.get_opcode() {
+            // routine calls (R6.4)
+            VAR_224 | VAR_236 | OP1_136 | OP2_25 => {
+                let routine = self.required_operand(operation, &operands)?;
+                self.call(memory, operation, routine, &operands[1..], true)
+            }
+            VAR_249 | VAR_250 | OP2_26 => {
+                let routine = self.required_operand(operation, &operands)?;
+                self.call(memory, operation, routine, &operands[1..], false)
+            }
+            OP1_143 if self.target >= ZMachineVersion::V5 => {
+                self.call(memory, operation, a, &[], false)
+            }
+            VAR_255 => {
+                let condition = a <= self.current_frame().get_arguments_count() as u16;
+                self.branch(memory, operation, condition)
+            }
+
+            // returns (R6.4.5)
+            OP1_139 => self.return_from_routine(memory, a),
+            OP0_176 => self.return_from_routine(memory, 1),
+            OP0_177 => self.return_from_routine(memory, 0),
+            OP0_184 => {
+                let value = self.read_variable(memory, 0x00)?;
+                self.return_from_routine(memory, value)
+            }
+            // R6.6.2: the stack frame token is the depth of the call stack
+            OP0_185 if self.target >= ZMachineVersion::V5 => {
+                self.store(memory, operation, self.frames.len() as u16)
+            }
+            OP2_28 => {
+                let frame = b as usize;
+                if frame == 0 || frame > self.frames.len() {
+                    return Err(ZmError::CpuInvalidStackFrame(b));
+                }
+                self.frames.truncate(frame);
+                self.return_from_routine(memory, a)
+            }
+
+            // jumps and branches
+            OP1_140 => {
+                self.pc = self.pc.wrapping_add_signed(a as i16 as i32).wrapping_sub(2);
+                Ok(())
+            }
+            OP1_128 => self.branch(memory, operation, a == 0),
+            OP0_191 => self.branch(memory, operation, true),
+
+            OP0_186 => {
+                self.halted = true;
+                io.quit();
+                Ok(())
+            }
+            OP0_180 => Ok(()),
+            OP0_183 => {
+                self.restarting = true;
+                Ok(())
+            }
+
+            // text output (R3, R7)
+            OP0_178 => self.print_string(memory, io, operation.get_text()),
+            OP0_179 => {
+                self.print_string(memory, io, operation.get_text())?;
+                self.print(memory, io, "\n")?;
+                self.return_from_routine(memory, 1)
+            }
+            OP1_135 => {
+                let string = ZString::new(memory, Byte(a))?;
+                self.print_string(memory, io, Some(&string))
+            }
+            OP1_141 => {
+                let address = Packed(a).unpacked_string(self.target, self.strings_offset)?;
+                let string = ZString::new(memory, address)?;
+                self.print_string(memory, io, Some(&string))
+            }
+            OP1_138 => {
+                let name = self.objects.get_short_name(memory, a)?;
+                self.print_string(memory, io, Some(&name))
+            }
+            OP0_187 => self.print(memory, io, "\n"),
+            // R3.8: an undefined code is printed as a question mark rather than halting the story
+            VAR_229 => match ZSCII(a).to_char(self.codec.get_unicode_table()) {
+                Ok(Some(character)) => self.print(memory, io, &String::from(character)),
+                Ok(None) => Ok(()),
+                Err(_) => self.print(memory, io, "?"),
+            },
+            VAR_230 => self.print(memory, io, &(a as i16).to_string()),
+            VAR_243 => self.select_output_stream(memory, &operands),
+
+            // text encoding (R3.7)
+            VAR_252 => {
+                let (length, from) = (b, operands.get(2).cloned().unwrap_or(0));
+                let coded_text = operands.get(3).cloned().unwrap_or(0);
+                let codes = (0..length)
+                    .map(|i| memory.read_byte(Byte(a.wrapping_add(from).wrapping_add(i))))
+                    .map(|code| code.map(u16::from))
+                    .collect::<ZmResult<Vec<u16>>>()?;
+                let encoded = ZString::from_zscii(&codes, &self.codec).to_bytes();
+                for (i, byte) in encoded.into_iter().enumerate() {
+                    memory.store_byte(Byte(coded_text.wrapping_add(i as u16)), byte)?;
+                }
+                Ok(())
+            }
+
+            // text input and lexical analysis (R13.6)
+            VAR_228 => {
+                // R8.2.3: the status line is redrawn before reading input in V1 to V3
+                if self.target <= ZMachineVersion::V3 {
+                    self.show_status(memory, io)?;
+                }
+                let (_, max_length) = self.input_capacity(memory, a)?;
+                let interrupt = self.timed_interrupt(operands.get(2..).unwrap_or_default());
+                self.wait_for(
+                    operation,
+                    ZRequestKind::Line {
+                        text_buffer: a,
+                        parse_buffer: b,
+                        max_length,
+                        interrupt,
+                    },
+                );
+                Ok(())
+            }
+            VAR_251 => {
+                let dictionary = operands.get(2).cloned().filter(|&address| address != 0);
+                let skip_unknown = operands.get(3).is_some_and(|&flag| flag != 0);
+                self.tokenise(memory, a, b, dictionary, skip_unknown)
+            }
+
+            VAR_246 => {
+                let interrupt = self.timed_interrupt(operands.get(1..).unwrap_or_default());
+                self.wait_for(operation, ZRequestKind::Char { interrupt });
+                Ok(())
+            }
+
+            // saving and restoring (R15 save, restore)
+            OP0_181 | EXT_0 if operands.is_empty() => {
+                let result_pc = self.pc.wrapping_sub(operation.get_result_length() as u32);
+                self.wait_for(operation, ZRequestKind::Save { result_pc });
+                Ok(())
+            }
+            OP0_182 | EXT_1 if operands.is_empty() => {
+                self.wait_for(operation, ZRequestKind::Restore);
+                Ok(())
+            }
+            // the table, its length in bytes and the file name (default chosen by the host)
+            EXT_0 => {
+                let data = (0..b)
+                    .map(|offset| memory.read_byte(Byte(a.wrapping_add(offset))))
+                    .collect::<ZmResult<Vec<u8>>>()?;
+                let name = self.read_file_name(memory, operands.get(2).cloned())?;
+                self.wait_for(operation, ZRequestKind::SaveTable { name, data });
+                Ok(())
+            }
+            EXT_1 => {
+                let name = self.read_file_name(memory, operands.get(2).cloned())?;
+                self.wait_for(
+                    operation,
+                    ZRequestKind::RestoreTable {
+                        name,
+                        table: a,
+                        length: b,
+                    },
+                );
+                Ok(())
+            }
+
+            // R15 save_undo: -1 tells the story that undo is unavailable
+            EXT_9 => {
+                let result = if self.push_undo_state(memory, Some(operation)) {
+                    1
+                } else {
+                    -1i16 as u16
+                };
+                self.store(memory, operation, result)
+            }
+            EXT_10 if self.undo.len() == 0 => self.store(memory, operation, 0),
+            EXT_10 => {
+                self.restoring_undo = true;
+                Ok(())
+            }
+
+            // screen model (R8)
+            OP0_188 if self.target <= ZMachineVersion::V3 => self.show_status(memory, io),
+            OP0_188 => Ok(()),
+            VAR_234 => {
+                io.split_window(a);
+                Ok(())
+            }
+            VAR_235 => {
+                self.window = if a == 1 {
+                    ZWindow::Upper
+                } else {
+                    ZWindow::Lower
+                };
+                io.set_window(self.window);
+                Ok(())
+            }
+            VAR_237 => {
+                // R8.7.3.3: erasing window -1 also unsplits the screen and selects window 0
+                if a as i16 == -1 {
+                    self.window = ZWindow::Lower;
+                }
+                io.erase_window(a as i16);
+                Ok(())
+            }
+            VAR_238 => {
+                if a == 1 {
+                    io.erase_line();
+                }
+                Ok(())
+            }
+            VAR_239 => {
+                io.set_cursor(a, b);
+                Ok(())
+            }
+            VAR_240 => {
+                let (line, column) = io.get_cursor();
+                memory.store_word(Word(a), line)?;
+                memory.store_word(Word(a.wrapping_add(2)), column)
+            }
+            VAR_241 => {
+                io.set_text_style(ZTextStyle::from_bits_truncate(a));
+                Ok(())
+            }
+            VAR_242 => {
+                io.set_buffer_mode(a != 0);
+                Ok(())
+            }
+            OP2_27 => {
+                io.set_colour(ZColor::from(a), ZColor::from(b));
+                Ok(())
+            }
+            EXT_4 => {
+                // R8.1.3: font 0 queries the current font, and unavailable fonts return 0
+                let font = ZFont::from(a);
+                let previous = if a == 0 {
+                    u16::from(self.font)
+                } else if io.set_font(font) {
+                    u16::from(std::mem::replace(&mut self.font, font))
+                } else {
+                    0
+                };
+                self.store(memory, operation, previous)
+            }
+            VAR_245 => {
+                // R9.2: without operands, a bleep is sounded
+                let number = operands.first().cloned().unwrap_or(1);
+                io.sound_effect(number, b, operands.get(2).cloned().unwrap_or(0));
+                Ok(())
+            }
+
+            // tables and Unicode output (R3.8.5.4)
+            VAR_254 => self.print_table(memory, io, operation, &operands),
+            EXT_11 => {
+                let character = char::from_u32(a as u32).unwrap_or('?');
+                self.print(memory, io, &String::from(character))
+            }
+            EXT_12 => {
+                let value = match char::from_u32(a as u32) {
+                    Some(character) => {
+                        let (output, input) = io.check_unicode(character);
+                        output as u16 | (input as u16) << 1
+                    }
+                    None => 0,
+                };
+                self.store(memory, operation, value)
+            }
+            VAR_253 => {
+                let size = operands.get(2).cloned().unwrap_or(0) as i16;
+                Self::copy_table(memory, a, b, size)
+            }
+            VAR_247 => {
+                let length = operands.get(2).cloned().unwrap_or(0);
+                let form = operands.get(3).cloned().unwrap_or(0x82);
+                let address = Self::scan_table(memory, a, b, length, form)?;
+                self.store(memory, operation, address.unwrap_or(0))?;
+                self.branch(memory, operation, address.is_some())
+            }
+
+            // story file verification (R11.1.6)
+            OP0_189 => self.branch(memory, operation, self.verified),
+
+            // object tree (R12)
+            OP1_131 => {
+                let parent = self.objects.get_parent(memory, a)?;
+                self.store(memory, operation, parent)
+            }
+            OP1_129 => {
+                let sibling = self.objects.get_sibling(memory, a)?;
+                self.store(memory, operation, sibling)?;
+                self.branch(memory, operation, sibling != 0)
+            }
+            OP1_130 => {
+                let child = self.objects.get_child(memory, a)?;
+                self.store(memory, operation, child)?;
+                self.branch(memory, operation, child != 0)
+            }
+            OP2_6 => {
+                let condition = self.objects.get_parent(memory, a)? == b;
+                self.branch(memory, operation, condition)
+            }
+            OP2_10 => {
+                let condition = self.objects.get_attribute(memory, a, b)?;
+                self.branch(memory, operation, condition)
+            }
+            OP2_11 => self.objects.set_attribute(memory, a, b, true),
+            OP2_12 => self.objects.set_attribute(memory, a, b, false),
+            OP2_14 => self.objects.insert_object(memory, a, b),
+            OP1_137 => self.objects.remove_object(memory, a),
+
+            // object properties (R12.4)
+            OP2_17 => {
+                let value = self.objects.get_property_value(memory, a, b)?;
+                self.store(memory, operation, value)
+            }
+            VAR_227 => {
+                let value = operands.get(2).cloned().unwrap_or(0);
+                self.objects.put_property_value(memory, a, b, value)
+            }
+            OP2_18 => {
+                let address = self.objects.get_property_address(memory, a, b)?;
+                self.store(memory, operation, address)
+            }
+            OP1_132 => {
+                let length = self.objects.get_property_length(memory, a)?;
+                self.store(memory, operation, length)
+            }
+            OP2_19 => {
+                let next = self.objects.get_next_property(memory, a, b)?;
+                self.store(memory, operation, next)
+            }
+
+            // comparisons (R15: je, jl, jg, test)
+            OP2_1 => {
+                let condition = operands.len() > 1 && operands[1..].contains(&a);
+                self.branch(memory, operation, condition)
+            }
+            OP2_2 => self.branch(memory, operation, (a as i16) < (b as i16)),
+            OP2_3 => self.branch(memory, operation, (a as i16) > (b as i16)),
+            OP2_7 => self.branch(memory, operation, a & b == b),
+
+            // signed 16-bit arithmetic (R2.2)
+            OP2_20 => self.store(memory, operation, a.wrapping_add(b)),
+            OP2_21 => self.store(memory, operation, a.wrapping_sub(b)),
+            OP2_22 => self.store(memory, operation, a.wrapping_mul(b)),
+            OP2_23 => {
+                let quotient = Self::signed_division(a, b, i16::wrapping_div)?;
+                self.store(memory, operation, quotient)
+            }
+            OP2_24 => {
+                let remainder = Self::signed_division(a, b, i16::wrapping_rem)?;
+                self.store(memory, operation, remainder)
+            }
+            EXT_2 => self.store(memory, operation, Self::logical_shift(a, b as i16)),
+            EXT_3 => self.store(memory, operation, Self::arithmetic_shift(a, b as i16)),
+
+            // bitwise logic
+            OP2_8 => self.store(memory, operation, a | b),
+            OP2_9 => self.store(memory, operation, a & b),
+            OP1_143 if self.target <= ZMachineVersion::V4 => self.store(memory, operation, !a),
+            VAR_248 => self.store(memory, operation, !a),
+
+            // variables (R6.3.4: opcodes taking a variable by reference)
+            OP1_133 => self.increment_variable(memory, a as u8, 1).map(|_| ()),
+            OP1_134 => self.increment_variable(memory, a as u8, -1).map(|_| ()),
+            OP2_5 => {
+                let value = self.increment_variable(memory, a as u8, 1)?;
+                self.branch(memory, operation, value > b as i16)
+            }
+            OP2_4 => {
+                let value = self.increment_variable(memory, a as u8, -1)?;
+                self.branch(memory, operation, value < b as i16)
+            }
+            OP1_142 => {
+                let value = self.read_variable_indirect(memory, a as u8)?;
+                self.store(memory, operation, value)
+            }
+            OP2_13 => self.write_variable_indirect(memory, a as u8, b),
+            VAR_232 => self.write_variable(memory, 0x00, a),
+            VAR_233 => {
+                let value = self.read_variable(memory, 0x00)?;
+                match operation.get_store_variable() {
+                    // V6 stores the pulled value instead
+                    Some(_) => self.store(memory, operation, value),
+                    None => self.write_variable_indirect(memory, a as u8, value),
+                }
+            }
+            OP0_185 if self.target <= ZMachineVersion::V4 => {
+                self.read_variable(memory, 0x00).map(|_| ())
+            }
+
+            // memory arrays
+            OP2_15 => {
+                let value = memory.read_word(Word(a.wrapping_add(b.wrapping_mul(2))))?;
+                self.store(memory, operation, value)
+            }
+            OP2_16 => {
+                let value = memory.read_byte(Byte(a.wrapping_add(b)))?;
+                self.store(memory, operation, value as u16)
+            }
+            VAR_225 => {
+                let value = operands.get(2).cloned().unwrap_or(0);
+                memory.store_word(Word(a.wrapping_add(b.wrapping_mul(2))), value)
+            }
+            VAR_226 => {
+                let value = operands.get(2).cloned().unwrap_or(0);
+                memory.store_byte(Byte(a.wrapping_add(b)), value as u8)
+            }
+
+            // random numbers (R2.4)
+            VAR_231 => {
+                let range = a as i16;
+                let value = match range {
+                    0 => {
+                        self.random.seed_randomly();
+                        0
+                    }
+                    _ if range < 0 => {
+                        self.random.seed(range.unsigned_abs());
+                        0
+                    }
+                    _ => self.random.next_in_range(range as u16),
+                };
+                self.store(memory, operation, value)
+            }
+
+            opcode => Err(ZmError::InstructionUnimplementedOpcode(
+                opcode.mnemonic(self.target).unwrap_or_default(),
+            )),
+        }
+    }
+
+    /// Calls the routine at the given packed address, storing its result if `store` is set.
+    ///
+    /// R6.4.3: calling packed address 0 does nothing and returns false.
+    fn call(
+        &mut self,
+        memory: &mut ZMemory,
+        operation: &Operation,
+        packed_address: u16,
+        arguments: &[u16],
+        store: bool,
+    ) -> ZmResult<()> {
+        let store_variable = if store {
+            operation.get_store_variable()
+        } else {
+            None
+        };
+        if packed_address == 0 {
+            return match store_variable {
+                Some(variable) => self.write_variable(memory, variable, 0),
+                None => Ok(()),
+            };
+        }
+        let routine_address = Packed(packed_address)
+            .unpacked_routine(self.target, self.routines_offset)?
+            .as_offset()?;
+        self.call_routine(memory, routine_address, arguments, store_variable)
+    }
+
+    /// Decodes and prints the given string.
+    fn print_string(
+        &mut self,
+        memory: &mut ZMemory,
+        io: &mut dyn ZIoSystem,
+        string: Option<&ZString>,
+    ) -> ZmResult<()> {
+        match string {
+            Some(string) => {
+                let text = string.decode(memory, &self.codec)?;
+                self.print(memory, io, &text)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the given text to the selected output streams (R7.1.2).
+    ///
+    /// While stream 3 is selected, the text only goes to its table as ZSCII,
+    /// characters which cannot be encoded becoming question marks. Otherwise, it goes to
+    /// the screen and, unless printed in the upper window, to the transcript.
+    fn print(&mut self, memory: &mut ZMemory, io: &mut dyn ZIoSystem, text: &str) -> ZmResult<()> {
+        if let Some(stream) = self.streams.get_memory_stream_mut() {
+            let unicode = self.codec.get_unicode_table();
+            for character in text.chars() {
+                let code = ZSCII::from_char(character, unicode).map_or(b'?' as u16, |code| code.0);
+                stream.write(memory, code)?;
+            }
+            return Ok(());
+        }
+        if self.streams.is_screen_selected() {
+            io.print(text);
+        }
+        if self.window == ZWindow::Lower && Self::is_transcripting(memory)? {
+            io.print_transcript(text);
+        }
+        Ok(())
+    }
+
+    /// Is output stream 2 selected, as given by Flags 2 which the story may set itself?
+    fn is_transcripting(memory: &ZMemory) -> ZmResult<bool> {
+        let flags2 = ZMachineHeaderFlags2::from_bits_truncate(memory.read_word(Word(0x10))?);
+        Ok(flags2.contains(ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING))
+    }
+
+    /// Selects or deselects the output stream of the given number, if positive or negative.
+    ///
+    /// Stream 3 takes the table to redirect the output to and in V6, the width to format
+    /// the text to. When deselected, V6 stories find the width of its text in the header.
+    fn select_output_stream(&mut self, memory: &mut ZMemory, operands: &[u16]) -> ZmResult<()> {
+        let number = operands.first().cloned().unwrap_or(0) as i16;
+        match number {
+            1 | -1 => self.streams.set_screen_selected(number > 0),
+            2 | -2 => {
+                let mut flags2 =
+                    ZMachineHeaderFlags2::from_bits_truncate(memory.read_word(Word(0x10))?);
+                flags2.set(ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING, number > 0);
+                memory.write_word(Word(0x10), flags2.bits())?;
+            }
+            3 => {
+                let table = operands.get(1).cloned().unwrap_or(0);
+                let width = match operands.get(2) {
+                    Some(&width) if self.target == ZMachineVersion::V6 => Some(width),
+                    _ => None,
+                };
+                self.streams.open_memory_stream(table, width)?;
+            }
+            -3 => {
+                if let Some(stream) = self.streams.close_memory_stream() {
+                    let width = stream.close(memory, self.character_width)?;
+                    if self.target == ZMachineVersion::V6 {
+                        memory.write_word(Word(0x30), width)?;
+                    }
+                }
+            }
+            4 | -4 => self.streams.set_commands_selected(number > 0),
+            // R7.1.1: stream 0 selects nothing
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Copies the player's input line to the transcript and the commands record, if selected.
+    fn echo_input(&self, memory: &ZMemory, io: &mut dyn ZIoSystem, input: &str) -> ZmResult<()> {
+        if Self::is_transcripting(memory)? {
+            io.print_transcript(&format!("{}\n", input));
+        }
+        if self.streams.is_commands_selected() {
+            io.record_input(input);
+        }
+        Ok(())
+    }
+
+    /// Prints a rectangle of ZSCII text from the given table, row by row.
+    ///
+    /// Operands are the table's address, its width and height (default 1) in characters,
+    /// and the number of bytes to skip at the end of each row (default 0).
+    fn print_table(
+        &mut self,
+        memory: &mut ZMemory,
+        io: &mut dyn ZIoSystem,
+        operation: &Operation,
+        operands: &[u16],
+    ) -> ZmResult<()> {
+        let mut address = self.required_operand(operation, operands)?;
+        let width = operands.get(1).cloned().unwrap_or(0);
+        let height = operands.get(2).cloned().unwrap_or(1);
+        let skip = operands.get(3).cloned().unwrap_or(0);
+        let (line, column) = io.get_cursor();
+        for row in 0..height {
+            if row > 0 {
+                match self.window {
+                    ZWindow::Upper => io.set_cursor(line.wrapping_add(row), column),
+                    ZWindow::Lower => self.print(memory, io, "\n")?,
+                }
+            }
+            let mut text = String::with_capacity(width as usize);
+            for offset in 0..width {
+                let code = memory.read_byte(Byte(address.wrapping_add(offset)))?;
+                text.extend(ZSCII(code as u16).to_char(self.codec.get_unicode_table())?);
+            }
+            self.print(memory, io, &text)?;
+            address = address.wrapping_add(width).wrapping_add(skip);
+        }
+        Ok(())
+    }
+
+    /// (V5+) Reads the name of an auxiliary file: a length byte followed by the characters.
+    fn read_file_name(&self, memory: &ZMemory, address: Option<u16>) -> ZmResult<Option<String>> {
+        let address = match address {
+            Some(address) if address != 0 => address,
+            _ => return Ok(None),
+        };
+        let length = memory.read_byte(Byte(address))? as u16;
+        let mut name = String::with_capacity(length as usize);
+        for offset in 1..=length {
+            let code = memory.read_byte(Byte(address.wrapping_add(offset)))?;
+            name.extend(ZSCII(code as u16).to_char(self.codec.get_unicode_table())?);
+        }
+        Ok(Some(name))
+    }
+
+    /// (V1 to V3) Shows the status line, from the first three global variables (R8.2.2).
+    fn show_status(&mut self, memory: &ZMemory, io: &mut dyn ZIoSystem) -> ZmResult<()> {
+        let location = match self.read_variable(memory, 0x10)? {
+            0 => String::new(),
+            object => self
+                .objects
+                .get_short_name(memory, object)?
+                .decode(memory, &self.codec)?,
+        };
+        let (first, second) = (
+            self.read_variable(memory, 0x11)?,
+            self.read_variable(memory, 0x12)?,
+        );
+        let progress = if self.time_game {
+            ZStatusProgress::Time {
+                hours: first,
+                minutes: second,
+            }
+        } else {
+            ZStatusProgress::Score {
+                score: first as i16,
+                turns: second,
+            }
+        };
+        io.show_status(&ZStatusLine { location, progress });
+        Ok(())
+    }
+
+    /// Copies `size` bytes from the first table to the second (R15 `copy_table`).
+    ///
+    /// If the second table is 0, the first one is zeroed instead. A positive size copies
+    /// without corrupting overlapping tables, while a negative one forces copying forwards.
+    fn copy_table(memory: &mut ZMemory, first: u16, second: u16, size: i16) -> ZmResult<()> {
+        let length = size.unsigned_abs();
+        if second == 0 {
+            for offset in 0..length {
+                memory.store_byte(Byte(first.wrapping_add(offset)), 0)?;
+            }
+        } else if size < 0 {
+            for offset in 0..length {
+                let byte = memory.read_byte(Byte(first.wrapping_add(offset)))?;
+                memory.store_byte(Byte(second.wrapping_add(offset)), byte)?;
+            }
+        } else {
+            let bytes = (0..length)
+                .map(|offset| memory.read_byte(Byte(first.wrapping_add(offset))))
+                .collect::<ZmResult<Vec<_>>>()?;
+            for (offset, byte) in bytes.into_iter().enumerate() {
+                memory.store_byte(Byte(second.wrapping_add(offset as u16)), byte)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks for the given value in a table of `length` fields, returning the address
+    /// of the first matching field if any (R15 `scan_table`).
+    ///
+    /// The form's top bit tells whether words rather than bytes are compared,
+    /// and its other bits give the length of each field.
+    fn scan_table(
+        memory: &ZMemory,
+        value: u16,
+        table: u16,
+        length: u16,
+        form: u16,
+    ) -> ZmResult<Option<u16>> {
+        let field_length = form & 0x7F;
+        for index in 0..length {
+            let address = table.wrapping_add(index.wrapping_mul(field_length));
+            let field = if form & 0x80 != 0 {
+                memory.read_word(Word(address))?
+            } else {
+                memory.read_byte(Byte(address))? as u16
+            };
+            if field == value {
+                return Ok(Some(address));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the offset in the given text buffer at which the input starts,
+    /// along with the maximum number of characters it may hold.
+    fn input_capacity(&self, memory: &ZMemory, text_buffer: u16) -> ZmResult<(u16, u16)> {
+        let capacity = memory.read_byte(Byte(text_buffer))? as u16;
+        Ok(if self.target <= ZMachineVersion::V4 {
+            (1, capacity.saturating_sub(1))
+        } else {
+            let existing = memory.read_byte(Byte(text_buffer.wrapping_add(1)))? as u16;
+            (2 + existing, capacity.saturating_sub(existing))
+        })
+    }
+
+    /// Suspends the execution until the host completes the given instruction.
+    fn wait_for(&mut self, operation: &Operation, kind: ZRequestKind) {
+        self.request = Some(ZPendingRequest {
+            operation: operation.clone(),
+            kind,
+        });
+    }
+
+    /// (V4+) Returns the interrupt routine of a timed input, from its time and routine operands.
+    fn timed_interrupt(&self, operands: &[u16]) -> Option<ZInterrupt> {
+        match operands {
+            [time, routine, ..]
+                if self.target >= ZMachineVersion::V4 && *time != 0 && *routine != 0 =>
+            {
+                Some(ZInterrupt {
+                    time: *time,
+                    routine: *routine,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Completes a read instruction with the given line of input and terminating character.
+    fn complete_line(
+        &mut self,
+        memory: &mut ZMemory,
+        operation: &Operation,
+        text_buffer: u16,
+        parse_buffer: u16,
+        text: &str,
+        terminator: u16,
+    ) -> ZmResult<()> {
+        self.store_input(memory, text_buffer, text)?;
+        if parse_buffer != 0 {
+            self.tokenise(memory, text_buffer, parse_buffer, None, false)?;
+        }
+        if self.target >= ZMachineVersion::V5 {
+            self.store(memory, operation, terminator)?;
+        }
+        Ok(())
+    }
+
+    /// Completes a save or restore instruction: the result is a branch from V1 to V3,
+    /// and a stored value from V4 on (0 for failure, 1 for success, 2 after a restore).
+    fn complete_save_or_restore(
+        &mut self,
+        memory: &mut ZMemory,
+        operation: &Operation,
+        result: u16,
+    ) -> ZmResult<()> {
+        if self.target <= ZMachineVersion::V3 {
+            self.branch(memory, operation, result != 0)
+        } else {
+            self.store(memory, operation, result)
+        }
+    }
+
+    /// Terminates the waiting read instruction after its interrupt routine returned true:
+    /// the input is then empty, and its terminating character 0.
+    fn terminate_request(&mut self, memory: &mut ZMemory) -> ZmResult<()> {
+        let request = match self.request.take() {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        match request.kind {
+            ZRequestKind::Line {
+                text_buffer,
+                parse_buffer,
+                ..
+            } => self.complete_line(memory, &request.operation, text_buffer, parse_buffer, "", 0),
+            ZRequestKind::Char { .. } => self.store(memory, &request.operation, 0),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores the player's input into the given text buffer.
+    ///
+    /// The input is converted to lower case, and characters which cannot be typed are dropped.
+    /// From V1 to V4, byte 0 of the buffer holds the maximum number of characters plus 1,
+    /// and the text is stored from byte 1 on, followed by a zero terminator.
+    /// In V5+, byte 0 holds the maximum number of characters and byte 1 the number of characters
+    /// already in the buffer, which are left in place: the input is appended from there on.
+    fn store_input(&self, memory: &mut ZMemory, text_buffer: u16, input: &str) -> ZmResult<()> {
+        let unicode = self.codec.get_unicode_table();
+        let codes = input
+            .trim_end_matches(['\n', '\r'])
+            .chars()
+            .flat_map(char::to_lowercase)
+            .filter_map(|character| ZSCII::from_char(character, unicode).ok())
+            .map(|code| code.0)
+            .filter(|code| matches!(code, 32..=126 | 155..=251));
+        let (start, max_length) = self.input_capacity(memory, text_buffer)?;
+        let mut length = 0;
+        for code in codes.take(max_length as usize) {
+            memory.store_byte(Byte(text_buffer.wrapping_add(start + length)), code as u8)?;
+            length += 1;
+        }
+        if self.target <= ZMachineVersion::V4 {
+            memory.store_byte(Byte(text_buffer.wrapping_add(start + length)), 0)?;
+        } else {
+            memory.store_byte(
+                Byte(text_buffer.wrapping_add(1)),
+                (start - 2 + length) as u8,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Splits the text buffer into words and writes the parse buffer (R13.6).
+    ///
+    /// Byte 0 of the parse buffer holds the maximum number of words, and byte 1 receives the
+    /// number of words found. Each word is then described by a 4-byte block: the address of its
+    /// dictionary entry (0 if not found), its length and its position in the text buffer.
+    /// When `skip_unknown` is set, the blocks of the words missing from the dictionary are left
+    /// untouched.
+    fn tokenise(
+        &self,
+        memory: &mut ZMemory,
+        text_buffer: u16,
+        parse_buffer: u16,
+        dictionary: Option<u16>,
+        skip_unknown: bool,
+    ) -> ZmResult<()> {
+        let address = dictionary.map(Byte).unwrap_or(self.dictionary_address);
+        let dictionary = ZDictionary::from_memory_and_address(memory, address, self.target)?;
+        let (start, text) = if self.target <= ZMachineVersion::V4 {
+            let mut text = vec![];
+            loop {
+                let address = text_buffer.wrapping_add(1 + text.len() as u16);
+                match memory.read_byte(Byte(address))? {
+                    0 => break,
+                    code => text.push(code as u16),
+                }
+            }
+            (1, text)
+        } else {
+            let length = memory.read_byte(Byte(text_buffer.wrapping_add(1)))? as u16;
+            let text = (0..length)
+                .map(|offset| {
+                    Ok(memory.read_byte(Byte(text_buffer.wrapping_add(2 + offset)))? as u16)
+                })
+                .collect::<ZmResult<Vec<_>>>()?;
+            (2, text)
+        };
+        let words = dictionary.tokenise(memory, &text, &self.codec)?;
+        let max_words = memory.read_byte(Byte(parse_buffer))? as usize;
+        let count = words.len().min(max_words);
+        memory.store_byte(Byte(parse_buffer.wrapping_add(1)), count as u8)?;
+        for (index, word) in words.iter().take(count).enumerate() {
+            if skip_unknown && word.get_entry().is_none() {
+                continue;
+            }
+            let block = parse_buffer.wrapping_add(2 + 4 * index as u16);
+            memory.store_word(Word(block), word.get_entry().unwrap_or(0))?;
+            memory.store_byte(Byte(block.wrapping_add(2)), word.get_length() as u8)?;
+            let position = start + word.get_position() as u16;
+            memory.store_byte(Byte(block.wrapping_add(3)), position as u8)?;
+        }
+        Ok(())
+    }
+
+    /// Evaluates the operands of the given operation, reading variables as needed.
+    fn read_operands(&mut self, memory: &ZMemory, operation: &Operation) -> ZmResult<Vec<u16>> {
+        let mut values = Vec::with_capacity(operation.get_operands().len());
+        for operand in operation.get_operands() {
+            values.push(match operand {
+                InstructionOperand::ConstantLarge(value) => *value,
+                InstructionOperand::ConstantSmall(value) => *value as u16,
+                InstructionOperand::Variable(variable) => self.read_variable(memory, *variable)?,
+                InstructionOperand::Omitted => continue,
+            });
+        }
+        Ok(values)
+    }
+
+    /// Returns the first operand of the given operation, which the story may not omit.
+    fn required_operand(&self, operation: &Operation, operands: &[u16]) -> ZmResult<u16> {
+        operands.first().cloned().ok_or_else(|| {
+            ZmError::InstructionMissingOperand(
+                operation
+                    .get_opcode()
+                    .mnemonic(self.target)
+                    .unwrap_or_default(),
+            )
+        })
+    }
+
+    /// Stores the result of the given operation into its store variable (R4.6).
+    fn store(&mut self, memory: &mut ZMemory, operation: &Operation, value: u16) -> ZmResult<()> {
+        match operation.get_store_variable() {
+            Some(variable) => self.write_variable(memory, variable, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Branches according to the given operation's branch data if the condition matches (R4.7).
+    ///
+    /// R4.7.1: an offset of 0 or 1 means returning false or true from the current routine.
+    /// Otherwise, the destination is the address after the branch data plus the offset minus 2.
+    fn branch(
         &mut self,
         memory: &mut ZMemory,
         operation: &Operation,
+        condition: bool,
     ) -> ZmResult<()> {
+        let branch = match operation.get_branch() {
+            Some(branch) if branch.on_true() == condition => branch,
+            _ => return Ok(()),
+        };
+        match branch.target() {
+            InstructionBranchTarget::ReturnFalse => self.return_from_routine(memory, 0),
+            InstructionBranchTarget::ReturnTrue => self.return_from_routine(memory, 1),
+            InstructionBranchTarget::Offset(offset) => {
+                self.pc = self.pc.wrapping_add_signed(*offset as i32).wrapping_sub(2);
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds the signed delta to the variable referenced by the given number, in place.
+    ///
+    /// Returns the new signed value.
+    fn increment_variable(
+        &mut self,
+        memory: &mut ZMemory,
+        variable: u8,
+        delta: i16,
+    ) -> ZmResult<i16> {
+        let value = (self.read_variable_indirect(memory, variable)? as i16).wrapping_add(delta);
+        self.write_variable_indirect(memory, variable, value as u16)?;
+        Ok(value)
+    }
+
+    /// R2.2: division and remainder truncate towards zero, and are illegal by zero.
+    fn signed_division(a: u16, b: u16, operation: fn(i16, i16) -> i16) -> ZmResult<u16> {
+        if b == 0 {
+            return Err(ZmError::CpuDivisionByZero);
+        }
+        Ok(operation(a as i16, b as i16) as u16)
+    }
+
+    /// Shifts left by the given number of places if positive, or logically right if negative.
+    fn logical_shift(value: u16, places: i16) -> u16 {
+        match places {
+            0..=15 => value << places,
+            -15..=-1 => value >> -places,
+            _ => 0,
+        }
+    }
+
+    /// Shifts left by the given number of places if positive, or right if negative
+    /// with the sign bit copied across.
+    fn arithmetic_shift(value: u16, places: i16) -> u16 {
+        match places {
+            0..=15 => value << places,
+            -15..=-1 => ((value as i16) >> -places) as u16,
+            _ if places < 0 => ((value as i16) >> 15) as u16,
+            _ => 0,
+        }
+    }
+
+    fn current_frame(&self) -> &ZCallFrame {
+        self.frames
+            .last()
+            .expect("ZCpu call stack should never be empty")
+    }
+
+    fn current_frame_mut(&mut self) -> &mut ZCallFrame {
+        self.frames
+            .last_mut()
+            .expect("ZCpu call stack should never be empty")
+    }
+
+    fn global_variable_address(&self, variable: u8) -> u16 {
+        debug_assert!(variable >= 0x10);
+        self.globals_address
+            .wrapping_add(2 * (variable as u16 - 0x10))
+    }
+
+    /// Read the given variable (R6.3).
+    ///
+    /// Reading variable 0x00 pops the value from the top of the current routine's stack.
+    pub fn read_variable(&mut self, memory: &ZMemory, variable: u8) -> ZmResult<u16> {
+        match variable {
+            0x00 => self.current_frame_mut().pop(),
+            0x01..=0x0F => self.current_frame().read_local(variable),
+            _ => memory.read_word(Word(self.global_variable_address(variable))),
+        }
+    }
+
+    /// Write the given variable (R6.3).
+    ///
+    /// Writing variable 0x00 pushes the value onto the current routine's stack.
+    pub fn write_variable(
+        &mut self,
+        memory: &mut ZMemory,
+        variable: u8,
+        value: u16,
+    ) -> ZmResult<()> {
+        match variable {
+            0x00 => {
+                self.current_frame_mut().push(value);
+                Ok(())
+            }
+            0x01..=0x0F => self.current_frame_mut().write_local(variable, value),
+            _ => memory.store_word(Word(self.global_variable_address(variable)), value),
+        }
+    }
+
+    /// Read the given variable from an opcode taking a variable by reference, like `load`.
+    ///
+    /// R6.3.4: in that case the stack pointer is read in place rather than popped.
+    pub fn read_variable_indirect(&mut self, memory: &ZMemory, variable: u8) -> ZmResult<u16> {
+        match variable {
+            0x00 => self.current_frame().peek(),
+            _ => self.read_variable(memory, variable),
+        }
+    }
+
+    /// Write the given variable from an opcode taking a variable by reference, like `store`.
+    ///
+    /// R6.3.4: in that case the top of the stack is replaced rather than pushed onto.
+    pub fn write_variable_indirect(
+        &mut self,
+        memory: &mut ZMemory,
+        variable: u8,
+        value: u16,
+    ) -> ZmResult<()> {
+        match variable {
+            0x00 => {
+                *self.current_frame_mut().peek_mut()? = value;
+                Ok(())
+            }
+            _ => self.write_variable(memory, variable, value),
+        }
+    }
+
+    /// Call the routine at the given byte address with the given arguments (R6.4).
+    ///
+    /// The routine header is a byte holding the number of local variables, followed in V1 to V4
+    /// by one word per local holding its initial value. Arguments are then written into the first
+    /// locals, any superfluous argument being discarded (R6.4.4).
+    ///
+    /// Execution resumes at the first instruction of the routine.
+    pub fn call_routine(
+        &mut self,
+        memory: &ZMemory,
+        routine_address: u32,
+        arguments: &[u16],
+        store_variable: Option<u8>,
+    ) -> ZmResult<()> {
+        if self.frames.len() >= self.stack_depth_limit {
+            return Err(ZmError::CpuStackOverflow(self.stack_depth_limit));
+        }
+
+        let locals_count = memory.read_byte(High(routine_address))?;
+        if locals_count as usize > ZCALL_FRAME_MAX_LOCALS {
+            return Err(ZmError::CpuInvalidRoutineLocalsCount(
+                routine_address,
+                locals_count,
+            ));
+        }
+        let mut pc = routine_address.wrapping_add(1);
+        let mut locals = Vec::with_capacity(locals_count as usize);
+        for _ in 0..locals_count {
+            if self.target <= ZMachineVersion::V4 {
+                locals.push(memory.read_word(High(pc))?);
+                pc = pc.wrapping_add(2);
+            } else {
+                locals.push(0);
+            }
+        }
+        for (local, argument) in locals.iter_mut().zip(arguments) {
+            *local = *argument;
+        }
+
+        self.frames.push(ZCallFrame::new(
+            self.pc,
+            locals,
+            store_variable,
+            arguments.len() as u8,
+        ));
+        self.pc = pc;
         Ok(())
     }
+
+    /// Return from the current routine with the given value (R6.4.5).
+    ///
+    /// The routine's frame is discarded along with its evaluation stack, execution resumes
+    /// in the caller and the value is stored if the call instruction asked for it.
+    pub fn return_from_routine(&mut self, memory: &mut ZMemory, value: u16) -> ZmResult<()> {
+        if self.frames.len() <= 1 {
+            return Err(ZmError::CpuReturnFromMainRoutine);
+        }
+        let frame = self
+            .frames
+            .pop()
+            .expect("ZCpu call stack should never be empty");
+        self.pc = frame.get_return_pc();
+        if self.interrupt_depth == Some(self.frames.len() + 1) {
+            self.interrupt_depth = None;
+            self.interrupt_result = Some(value);
+        }
+        match frame.get_store_variable() {
+            Some(variable) => self.write_variable(memory, variable, value),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::zmachine::ZInterpreterCapabilities;
+
+    /// Records the output and the screen model's state.
+    #[derive(Default)]
+    struct TestIo {
+        output: String,
+        transcript: String,
+        cursor: (u16, u16),
+        style: Option<ZTextStyle>,
+        status: Option<ZStatusLine>,
+    }
+
+    impl ZIoSystem for TestIo {
+        fn print(&mut self, text: &str) {
+            self.output.push_str(text);
+        }
+
+        fn print_transcript(&mut self, text: &str) {
+            self.transcript.push_str(text);
+        }
+
+        fn show_status(&mut self, status: &ZStatusLine) {
+            self.status = Some(status.clone());
+        }
+
+        fn set_text_style(&mut self, style: ZTextStyle) {
+            self.style = Some(style);
+        }
+
+        fn set_cursor(&mut self, line: u16, column: u16) {
+            self.cursor = (line, column);
+        }
+
+        fn get_cursor(&mut self) -> (u16, u16) {
+            self.cursor
+        }
+    }
+
+    /// Build a minimal story with the globals at 0x40, a main entry point at 0x200
+    /// and a 3-locals routine at 0x300.
+    fn init_cpu_and_memory(version: u8) -> (ZCpu, ZMemory) {
+        let mut story = vec![0u8; 0x400];
+        story[0x00] = version;
+        story[0x06] = 0x02; // initial PC = 0x200
+        story[0x0C] = 0x00; // globals at 0x40
+        story[0x0D] = 0x40;
+        story[0x0E] = 0x03; // static memory at 0x300
+        story[0x300] = 3; // 3 locals
+        story[0x301..0x307].copy_from_slice(&[0x11, 0x11, 0x22, 0x22, 0x33, 0x33]);
+        let mut memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        let mut header = ZMachineHeader::from_memory(&memory).unwrap();
+        header
+            .reset(&mut memory, &ZInterpreterCapabilities::default())
+            .unwrap();
+        memory.set_regions(0x300, 0x300);
+        (ZCpu::from_header(&header, &memory).unwrap(), memory)
+    }
+
+    /// Write the given program at the initial PC and execute its instructions.
+    fn run_program(cpu: &mut ZCpu, memory: &mut ZMemory, program: &[u8], steps: usize) -> TestIo {
+        let mut io = TestIo::default();
+        for (offset, byte) in program.iter().enumerate() {
+            memory
+                .write_byte(Byte(0x200 + offset as u16), *byte)
+                .unwrap();
+        }
+        for _ in 0..steps {
+            cpu.step(memory, &mut io).unwrap();
+        }
+        io
+    }
+
+    #[test]
+    fn test_variables() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        cpu.write_variable(&mut memory, 0x10, 0xCAFE).unwrap();
+        cpu.write_variable(&mut memory, 0xFF, 0xBEEF).unwrap();
+        assert_eq!(memory.read_word(Word(0x40)).unwrap(), 0xCAFE);
+        assert_eq!(memory.read_word(Word(0x40 + 2 * 0xEF)).unwrap(), 0xBEEF);
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 0xCAFE);
+        // no locals in the main routine
+        assert!(cpu.read_variable(&memory, 0x01).is_err());
+
+        cpu.write_variable(&mut memory, 0x00, 1).unwrap();
+        cpu.write_variable(&mut memory, 0x00, 2).unwrap();
+        cpu.write_variable_indirect(&mut memory, 0x00, 3).unwrap();
+        assert_eq!(cpu.read_variable_indirect(&memory, 0x00).unwrap(), 3);
+        assert_eq!(cpu.read_variable(&memory, 0x00).unwrap(), 3);
+        assert_eq!(cpu.read_variable(&memory, 0x00).unwrap(), 1);
+        assert!(matches!(
+            cpu.read_variable(&memory, 0x00),
+            Err(ZmError::CpuStackUnderflow)
+        ));
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        for (version, expected_locals, expected_pc) in [
+            (3, vec![0xAAAA, 0x2222, 0x3333], 0x307),
+            (5, vec![0xAAAA, 0, 0], 0x301),
+        ] {
+            let (mut cpu, mut memory) = init_cpu_and_memory(version);
+            cpu.write_variable(&mut memory, 0x00, 0x1234).unwrap();
+            cpu.call_routine(&memory, 0x300, &[0xAAAA], Some(0x00))
+                .unwrap();
+            assert_eq!(cpu.pc, expected_pc);
+            assert_eq!(cpu.current_frame().get_locals(), &expected_locals[..]);
+            assert_eq!(cpu.current_frame().get_arguments_count(), 1);
+            // the stack is empty at the start of each routine
+            assert!(cpu.read_variable(&memory, 0x00).is_err());
+            cpu.write_variable(&mut memory, 0x03, 0x4242).unwrap();
+            assert_eq!(cpu.read_variable(&memory, 0x03).unwrap(), 0x4242);
+
+            cpu.return_from_routine(&mut memory, 0x5678).unwrap();
+            assert_eq!(cpu.pc, 0x200);
+            assert_eq!(cpu.read_variable(&memory, 0x00).unwrap(), 0x5678);
+            assert_eq!(cpu.read_variable(&memory, 0x00).unwrap(), 0x1234);
+            assert!(matches!(
+                cpu.return_from_routine(&mut memory, 0),
+                Err(ZmError::CpuReturnFromMainRoutine)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_stack_depth_limit() {
+        let (mut cpu, memory) = init_cpu_and_memory(5);
+        cpu.set_stack_depth_limit(4);
+        for _ in 0..3 {
+            cpu.call_routine(&memory, 0x300, &[], None).unwrap();
+        }
+        assert!(matches!(
+            cpu.call_routine(&memory, 0x300, &[], None),
+            Err(ZmError::CpuStackOverflow(4))
+        ));
+    }
+
+    #[test]
+    fn test_signed_arithmetic() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        #[rustfmt::skip]
+        let program = [
+            0xD4, 0x1F, 0xFF, 0xFD, 0x05, 0x10, // add -3 5 -> G00
+            0xD7, 0x1F, 0xFF, 0xF9, 0x02, 0x11, // div -7 2 -> G01
+            0xD8, 0x1F, 0xFF, 0xF9, 0x02, 0x12, // mod -7 2 -> G02
+            0xD6, 0x0F, 0x01, 0x2C, 0x01, 0x2C, 0x13, // mul 300 300 -> G03
+            0xBE, 0x03, 0x0F, 0xFF, 0xF0, 0xFF, 0xFE, 0x14, // art_shift -16 -2 -> G04
+            0xBE, 0x02, 0x0F, 0xFF, 0xF0, 0xFF, 0xFE, 0x15, // log_shift -16 -2 -> G05
+            0x17, 0x01, 0x00, 0x00, // div 1 0 -> sp
+        ];
+        run_program(&mut cpu, &mut memory, &program, 6);
+        let globals: Vec<u16> = (0x10..=0x15)
+            .map(|variable| cpu.read_variable(&memory, variable).unwrap())
+            .collect();
+        assert_eq!(globals, [2, 0xFFFD, 0xFFFF, 24464, 0xFFFC, 0x3FFC]);
+        assert!(matches!(
+            cpu.step(&mut memory, &mut TestIo::default()),
+            Err(ZmError::CpuDivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_comparisons_and_branches() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        // je 1 2 3 1 ?+10
+        run_program(
+            &mut cpu,
+            &mut memory,
+            &[0xC1, 0x55, 0x01, 0x02, 0x03, 0x01, 0xCA],
+            1,
+        );
+        assert_eq!(cpu.pc, 0x207 + 10 - 2);
+
+        // inc_chk G00 5 ?+10, with G00 = 5 then 6
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        cpu.write_variable(&mut memory, 0x10, 5).unwrap();
+        run_program(&mut cpu, &mut memory, &[0x05, 0x10, 0x05, 0xCA], 1);
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 6);
+        assert_eq!(cpu.pc, 0x204 + 10 - 2);
+
+        // jl -1 1 ?~+10 does not branch
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        run_program(
+            &mut cpu,
+            &mut memory,
+            &[0xC2, 0x1F, 0xFF, 0xFF, 0x01, 0x4A],
+            1,
+        );
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn test_static_memory_is_read_only() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        // storeb 0x2FF 0 0x42 ; storeb 0x300 0 0x42
+        let program = [
+            0xE2, 0x17, 0x02, 0xFF, 0x00, 0x42, 0xE2, 0x17, 0x03, 0x00, 0x00, 0x42,
+        ];
+        run_program(&mut cpu, &mut memory, &program, 1);
+        assert_eq!(memory.read_byte(Byte(0x2FF)).unwrap(), 0x42);
+        assert!(matches!(
+            cpu.step(&mut memory, &mut TestIo::default()),
+            Err(ZmError::MemoryIllegalWrite(0x206, 0x300))
+        ));
+        assert_eq!(memory.read_byte(Byte(0x300)).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_high_memory_routine() {
+        let mut story = vec![0u8; 0x10010];
+        story[0x00] = 5;
+        story[0x06] = 0x02; // initial PC = 0x200
+        story[0x0D] = 0x40; // globals at 0x40
+        story[0x0E] = 0x03; // static memory at 0x300
+                            // call_vn 0x4000 (i.e. 0x10000 unpacked)
+        story[0x200..0x204].copy_from_slice(&[0xF9, 0x3F, 0x40, 0x00]);
+        // no locals, rtrue
+        story[0x10000..0x10002].copy_from_slice(&[0x00, 0xB0]);
+        let mut memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let mut cpu = ZCpu::from_header(&header, &memory).unwrap();
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.pc, 0x10001);
+        assert_eq!(cpu.get_frames().len(), 2);
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(cpu.get_frames().len(), 1);
+    }
+
+    #[test]
+    fn test_verify() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        // verify ?+5
+        run_program(&mut cpu, &mut memory, &[0xBD, 0xC5], 1);
+        assert_eq!(cpu.pc, 0x202);
+
+        let checksum = memory.checksum();
+        memory.write_word(Word(0x1C), checksum).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let mut cpu = ZCpu::from_header(&header, &memory).unwrap();
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.pc, 0x202 + 5 - 2);
+    }
+
+    #[test]
+    fn test_encode_text() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        for (offset, byte) in b" hello".iter().enumerate() {
+            memory
+                .write_byte(Byte(0x100 + offset as u16), *byte)
+                .unwrap();
+        }
+        // encode_text 0x100 5 1 0x180
+        run_program(
+            &mut cpu,
+            &mut memory,
+            &[0xFC, 0x14, 0x01, 0x00, 0x05, 0x01, 0x01, 0x80],
+            1,
+        );
+        let encoded: Vec<u8> = (0..6)
+            .map(|i| memory.read_byte(Byte(0x180 + i)).unwrap())
+            .collect();
+        assert_eq!(encoded, vec![0x35, 0x51, 0x46, 0x85, 0x94, 0xA5]);
+    }
+
+    #[test]
+    fn test_routine_calls() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        // routine at 0x300 (packed 0xC0): check_arg_count 1 ?~rfalse ; ret 42
+        for (offset, byte) in [0xFF, 0x7F, 0x01, 0x40, 0x9B, 0x2A].iter().enumerate() {
+            memory
+                .write_byte(Byte(0x301 + offset as u16), *byte)
+                .unwrap();
+        }
+        cpu.write_variable(&mut memory, 0x11, 0xFFFF).unwrap();
+        cpu.write_variable(&mut memory, 0x12, 0xFFFF).unwrap();
+        #[rustfmt::skip]
+        let program = [
+            0xE0, 0x5F, 0xC0, 0x05, 0x10, // call_vs 0xC0 5 -> G00
+            0x98, 0xC0, 0x11, // call_1s 0xC0 -> G01
+            0xE0, 0x7F, 0x00, 0x12, // call_vs 0 -> G02
+        ];
+        run_program(&mut cpu, &mut memory, &program, 3);
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 42);
+        assert_eq!(cpu.pc, 0x205);
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.get_frames().len(), 2);
+        assert_eq!(cpu.current_frame().get_arguments_count(), 0);
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x11).unwrap(), 0);
+        assert_eq!(cpu.pc, 0x208);
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x12).unwrap(), 0);
+        assert_eq!(cpu.pc, 0x20C);
+        assert_eq!(cpu.get_frames().len(), 1);
+
+        // call_vn with every operand omitted
+        memory.write_byte(Byte(0x20C), 0xF9).unwrap();
+        memory.write_byte(Byte(0x20D), 0xFF).unwrap();
+        assert!(matches!(
+            cpu.step(&mut memory, &mut TestIo::default()),
+            Err(ZmError::InstructionMissingOperand("call_vn"))
+        ));
+    }
+
+    #[test]
+    fn test_catch_and_throw() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        // routine at 0x300 (packed 0xC0): catch -> sp ; call_1n 0xC0
+        // and on the second call: throw 7 L01
+        for (offset, byte) in [0xB9, 0x00, 0x9F, 0xC0].iter().enumerate() {
+            memory
+                .write_byte(Byte(0x301 + offset as u16), *byte)
+                .unwrap();
+        }
+        // call_vs 0xC0 -> G00
+        run_program(&mut cpu, &mut memory, &[0xE0, 0x7F, 0xC0, 0x10], 3);
+        assert_eq!(cpu.get_frames().len(), 3);
+        let token = cpu.frames[1].get_stack()[0];
+        assert_eq!(token, 2);
+        cpu.frames[2].write_local(1, token).unwrap();
+        // throw 7 L01, from the nested routine
+        for (offset, byte) in [0x3C, 0x07, 0x01].iter().enumerate() {
+            memory
+                .write_byte(Byte(0x301 + offset as u16), *byte)
+                .unwrap();
+        }
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert_eq!(cpu.get_frames().len(), 1);
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 7);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    /// Write a dictionary at 0x100 with the words "," "lamp" and "take", 1 data byte each.
+    fn write_dictionary(cpu: &ZCpu, memory: &mut ZMemory) {
+        let mut table = vec![1, b',', 0, 0, 3];
+        table[2] = ZString::dictionary_length(cpu.target) as u8 + 1;
+        for word in [",", "lamp", "take"] {
+            let codes: Vec<u16> = word.bytes().map(u16::from).collect();
+            table.extend(ZString::from_zscii(&codes, &cpu.codec).to_bytes());
+            table.push(0xDA);
+        }
+        for (offset, byte) in table.iter().enumerate() {
+            memory
+                .write_byte(Byte(0x100 + offset as u16), *byte)
+                .unwrap();
+        }
+    }
+
+    fn read_bytes(memory: &ZMemory, address: u16, length: u16) -> Vec<u8> {
+        (0..length)
+            .map(|offset| memory.read_byte(Byte(address + offset)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_store_input() {
+        let (cpu, mut memory) = init_cpu_and_memory(3);
+        memory.write_byte(Byte(0x180), 6).unwrap();
+        cpu.store_input(&mut memory, 0x180, "Take LAMP\n").unwrap();
+        assert_eq!(read_bytes(&memory, 0x180, 8), b"\x06take \0\0");
+
+        // existing text is kept in V5+
+        let (cpu, mut memory) = init_cpu_and_memory(5);
+        memory.write_byte(Byte(0x180), 8).unwrap();
+        memory.write_byte(Byte(0x181), 2).unwrap();
+        memory.write_byte(Byte(0x182), b'g').unwrap();
+        memory.write_byte(Byte(0x183), b'o').unwrap();
+        cpu.store_input(&mut memory, 0x180, " North\r\n").unwrap();
+        assert_eq!(read_bytes(&memory, 0x180, 10), b"\x08\x08go north");
+    }
+
+    #[test]
+    fn test_tokenise() {
+        let (cpu, mut memory) = init_cpu_and_memory(3);
+        write_dictionary(&cpu, &mut memory);
+        // text buffer at 0x180, parse buffer at 0x1C0 for 2 words
+        for (offset, byte) in b"\x10take lamp,xyzzy\0".iter().enumerate() {
+            memory
+                .write_byte(Byte(0x180 + offset as u16), *byte)
+                .unwrap();
+        }
+        memory.write_byte(Byte(0x1C0), 2).unwrap();
+        cpu.tokenise(&mut memory, 0x180, 0x1C0, Some(0x100), false)
+            .unwrap();
+        #[rustfmt::skip]
+        assert_eq!(
+            read_bytes(&memory, 0x1C0, 14),
+            [2, 2, 0x01, 0x0F, 4, 1, 0x01, 0x0A, 4, 6, 0, 0, 0, 0]
+        );
+
+        // tokenise 0x180 0x1C0 0x100 1, with unknown words left untouched
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        write_dictionary(&cpu, &mut memory);
+        for (offset, byte) in b"\x10\x0bxyzzy, take".iter().enumerate() {
+            memory
+                .write_byte(Byte(0x180 + offset as u16), *byte)
+                .unwrap();
+        }
+        memory.write_byte(Byte(0x1C0), 4).unwrap();
+        memory.write_word(Word(0x1C2), 0xFFFF).unwrap();
+        run_program(
+            &mut cpu,
+            &mut memory,
+            &[0xFB, 0x00, 0x01, 0x80, 0x01, 0xC0, 0x01, 0x00, 0x00, 0x01],
+            1,
+        );
+        #[rustfmt::skip]
+        assert_eq!(
+            read_bytes(&memory, 0x1C0, 14),
+            [4, 3, 0xFF, 0xFF, 0, 0, 0x01, 0x05, 1, 7, 0x01, 0x13, 4, 9]
+        );
+    }
+
+    #[test]
+    fn test_read_with_status_line() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(3);
+        write_dictionary(&cpu, &mut memory);
+        cpu.dictionary_address = Byte(0x100);
+        cpu.write_variable(&mut memory, 0x11, 0xFFFB).unwrap();
+        cpu.write_variable(&mut memory, 0x12, 12).unwrap();
+        memory.write_byte(Byte(0x180), 0x10).unwrap();
+        memory.write_byte(Byte(0x1C0), 2).unwrap();
+        // sread 0x180 0x1C0, waiting for the input
+        let program = [0xE4, 0x0F, 0x01, 0x80, 0x01, 0xC0];
+        let io = run_program(&mut cpu, &mut memory, &program, 2);
+        assert!(matches!(
+            cpu.get_waiting_event(),
+            Some(ZMachineEvent::NeedLine {
+                max_len: 15,
+                timeout: None
+            })
+        ));
+        assert!(matches!(
+            cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Char(13)),
+            Err(ZmError::MachineUnexpectedInput(ZInput::Char(13)))
+        ));
+        cpu.provide_input(
+            &mut memory,
+            &mut TestIo::default(),
+            ZInput::Line(String::from("Take the lamp")),
+        )
+        .unwrap();
+        assert!(!cpu.is_waiting());
+        assert_eq!(cpu.pc, 0x206);
+        assert_eq!(
+            io.status,
+            Some(ZStatusLine {
+                location: String::new(),
+                progress: ZStatusProgress::Score {
+                    score: -5,
+                    turns: 12
+                },
+            })
+        );
+        assert_eq!(read_bytes(&memory, 0x181, 14), b"take the lamp\0");
+        assert_eq!(
+            read_bytes(&memory, 0x1C0, 10),
+            [2, 2, 0x01, 0x0F, 4, 1, 0, 0, 3, 6]
+        );
+    }
+
+    #[test]
+    fn test_screen_and_tables() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        for (offset, byte) in b"abcdef".iter().enumerate() {
+            memory
+                .write_byte(Byte(0x100 + offset as u16), *byte)
+                .unwrap();
+        }
+        #[rustfmt::skip]
+        let program = [
+            0xFD, 0x07, 0x01, 0x00, 0x01, 0x02, 0x04, // copy_table 0x100 0x102 4
+            0xF7, 0x45, 0x63, 0x01, 0x00, 0x06, 0x01, 0x10, 0xC2, // scan_table 'c' 0x100 6 1 -> G00
+            0xFE, 0x15, 0x01, 0x00, 0x03, 0x02, 0x00, // print_table 0x100 3 2 0
+            0xF1, 0x7F, 0x02, // set_text_style 2
+            0xBE, 0x0B, 0x7F, 0xE9, // print_unicode 0xE9
+            0xBE, 0x0C, 0x7F, 0xE9, 0x11, // check_unicode 0xE9 -> G01
+            0xEF, 0x5F, 0x03, 0x07, // set_cursor 3 7
+            0xF0, 0x3F, 0x01, 0x80, // get_cursor 0x180
+            0xBE, 0x04, 0x7F, 0x04, 0x12, // set_font 4 -> G02
+            0xBE, 0x04, 0x7F, 0x00, 0x13, // set_font 0 -> G03
+        ];
+        let io = run_program(&mut cpu, &mut memory, &program, 10);
+        assert_eq!(read_bytes(&memory, 0x100, 6), b"ababcd");
+        assert_eq!(io.output, "aba\nbcd\u{e9}");
+        assert_eq!(io.style, Some(ZTextStyle::BOLD));
+        assert_eq!(memory.read_word(Word(0x180)).unwrap(), 3);
+        assert_eq!(memory.read_word(Word(0x182)).unwrap(), 7);
+        let globals: Vec<u16> = (0x10..=0x13)
+            .map(|variable| cpu.read_variable(&memory, variable).unwrap())
+            .collect();
+        assert_eq!(globals, [0x104, 3, 0, 1]);
+
+        // print_table with every operand omitted
+        memory.write_byte(Byte(cpu.pc as u16), 0xFE).unwrap();
+        memory.write_byte(Byte(cpu.pc as u16 + 1), 0xFF).unwrap();
+        assert!(matches!(
+            cpu.step(&mut memory, &mut TestIo::default()),
+            Err(ZmError::InstructionMissingOperand("print_table"))
+        ));
+    }
+
+    #[test]
+    fn test_timed_input() {
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        memory.write_byte(Byte(0x180), 0x10).unwrap();
+        // interrupt routine at 0x300 (packed 0xC0): rfalse
+        memory.write_byte(Byte(0x301), 0xB1).unwrap();
+        // aread 0x180 0 10 0xC0 -> G00
+        let program = [0xE4, 0x15, 0x01, 0x80, 0x00, 0x0A, 0xC0, 0x10];
+        run_program(&mut cpu, &mut memory, &program, 1);
+        assert!(matches!(
+            cpu.get_waiting_event(),
+            Some(ZMachineEvent::NeedLine {
+                max_len: 16,
+                timeout: Some(10)
+            })
+        ));
+
+        // the read goes on after the routine returns false
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Timeout)
+            .unwrap();
+        assert!(cpu.get_waiting_event().is_none());
+        assert!(cpu
+            .provide_input(&mut memory, &mut TestIo::default(), ZInput::Timeout)
+            .is_err());
+        let mut io = TestIo::default();
+        cpu.step(&mut memory, &mut io).unwrap();
+        cpu.step(&mut memory, &mut io).unwrap();
+        assert!(cpu.is_waiting());
+
+        // and is terminated when it returns true
+        memory.write_byte(Byte(0x301), 0xB0).unwrap();
+        cpu.write_variable(&mut memory, 0x10, 0xFFFF).unwrap();
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Timeout)
+            .unwrap();
+        cpu.step(&mut memory, &mut io).unwrap();
+        cpu.step(&mut memory, &mut io).unwrap();
+        assert!(!cpu.is_waiting());
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 0);
+        assert_eq!(cpu.pc, 0x208);
+    }
+
+    #[test]
+    fn test_save_and_restore_requests() {
+        // save ?+5
+        let (mut cpu, mut memory) = init_cpu_and_memory(3);
+        run_program(&mut cpu, &mut memory, &[0xB5, 0xC5], 1);
+        assert!(matches!(
+            cpu.get_waiting_event(),
+            Some(ZMachineEvent::SaveRequested)
+        ));
+        assert_eq!(cpu.get_save_pc(), Some(0x201));
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Saved(true))
+            .unwrap();
+        assert_eq!(cpu.pc, 0x202 + 5 - 2);
+        assert_eq!(cpu.get_save_pc(), None);
+        // the restored save branches again
+        let frames = cpu.get_frames().to_vec();
+        let save = cpu
+            .decoded_restored_save(&memory, memory.get_dynamic_memory(), 0x201, &frames)
+            .unwrap();
+        cpu.restore(&mut memory, &save, 0x201, frames).unwrap();
+        assert_eq!(cpu.pc, 0x202 + 5 - 2);
+
+        // save -> G00 ; restore -> G01
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        let program = [0xBE, 0x00, 0xFF, 0x10, 0xBE, 0x01, 0xFF, 0x11];
+        run_program(&mut cpu, &mut memory, &program, 1);
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Saved(true))
+            .unwrap();
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert!(matches!(
+            cpu.get_waiting_event(),
+            Some(ZMachineEvent::RestoreRequested)
+        ));
+        assert!(cpu
+            .provide_input(&mut memory, &mut TestIo::default(), ZInput::Saved(true))
+            .is_err());
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::RestoreFailed)
+            .unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 1);
+        assert_eq!(cpu.read_variable(&memory, 0x11).unwrap(), 0);
+        // the restored save stores 2
+        let frames = vec![ZCallFrame::new(0, vec![], None, 0).with_stack(vec![7])];
+        let save = cpu
+            .decoded_restored_save(&memory, memory.get_dynamic_memory(), 0x203, &frames)
+            .unwrap();
+        cpu.restore(&mut memory, &save, 0x203, frames).unwrap();
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 2);
+        assert_eq!(cpu.read_variable(&memory, 0x00).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_output_streams() {
+        // output_stream 3 0x50 ; print "hi" ; output_stream -3
+        // output_stream 2 ; print "hi" ; output_stream -1 ; print "hi"
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        let program = [
+            0xF3, 0x5F, 0x03, 0x50, 0xB2, 0xB5, 0xC5, 0xF3, 0x3F, 0xFF, 0xFD, 0xF3, 0x7F, 0x02,
+            0xB2, 0xB5, 0xC5, 0xF3, 0x3F, 0xFF, 0xFF, 0xB2, 0xB5, 0xC5,
+        ];
+        let io = run_program(&mut cpu, &mut memory, &program, 7);
+        assert_eq!(read_bytes(&memory, 0x50, 4), b"\0\x02hi".to_vec());
+        assert_eq!(io.output, "hi");
+        assert_eq!(io.transcript, "hihi");
+        assert_eq!(memory.read_word(Word(0x10)).unwrap() & 1, 1);
+
+        // the story turns the transcript off through Flags 2
+        memory.write_word(Word(0x10), 0).unwrap();
+        let mut io = TestIo::default();
+        cpu.print(&mut memory, &mut io, "hi").unwrap();
+        assert_eq!(io.transcript, "");
+
+        // print_char 'a' ; print_char 252 ; print_char 0x100: undefined codes print '?'
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        let program = [0xE5, 0x7F, 0x61, 0xE5, 0x7F, 0xFC, 0xE5, 0x3F, 0x01, 0x00];
+        let io = run_program(&mut cpu, &mut memory, &program, 3);
+        assert_eq!(io.output, "a??");
+    }
+
+    /// Go back to the most recent undo state, as the machine does.
+    fn restore_undo(cpu: &mut ZCpu, memory: &mut ZMemory) -> bool {
+        let state = match cpu.take_undo_state() {
+            Some(state) => state,
+            None => return false,
+        };
+        let dynamic_memory = ZQuetzal::decompressed(
+            &state.memory,
+            memory.get_original_dynamic_memory(),
+            memory.get_dynamic_memory().len(),
+        )
+        .unwrap();
+        memory.write_dynamic_memory(&dynamic_memory).unwrap();
+        cpu.resume_undo_state(memory, state).unwrap();
+        true
+    }
+
+    #[test]
+    fn test_undo() {
+        // save_undo -> G00 ; store G01 5 ; restore_undo -> G02
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        let program = [
+            0xBE, 0x09, 0xFF, 0x10, 0x0D, 0x11, 0x05, 0xBE, 0x0A, 0xFF, 0x12,
+        ];
+        run_program(&mut cpu, &mut memory, &program, 3);
+        assert!(cpu.is_restoring_undo());
+        assert!(restore_undo(&mut cpu, &mut memory));
+        assert!(!cpu.is_restoring_undo());
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 2);
+        assert_eq!(cpu.read_variable(&memory, 0x11).unwrap(), 0);
+        assert_eq!(cpu.pc, 0x204);
+        assert_eq!(cpu.get_undo_count(), 0);
+        let mut io = TestIo::default();
+        cpu.step(&mut memory, &mut io).unwrap();
+        cpu.step(&mut memory, &mut io).unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x12).unwrap(), 0);
+        assert_eq!(cpu.read_variable(&memory, 0x11).unwrap(), 5);
+
+        // undo is unavailable
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        cpu.set_undo_capacity(0);
+        run_program(&mut cpu, &mut memory, &program, 1);
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 0xFFFF);
+
+        // the host goes back to a read_char waiting for its input
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        cpu.set_undo_capacity(1);
+        run_program(&mut cpu, &mut memory, &[0xF6, 0x7F, 0x01, 0x10], 1);
+        assert!(cpu.save_undo_state(&memory));
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Char(65))
+            .unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 65);
+        assert!(restore_undo(&mut cpu, &mut memory));
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 0);
+        assert!(matches!(
+            cpu.get_waiting_event(),
+            Some(ZMachineEvent::NeedChar { timeout: None })
+        ));
+        assert!(!restore_undo(&mut cpu, &mut memory));
+    }
+
+    #[test]
+    fn test_save_and_restore_tables() {
+        // save 0x50 3 0x60 -> G00 ; restore 0x70 4 0x60 -> G01
+        let (mut cpu, mut memory) = init_cpu_and_memory(5);
+        for (offset, byte) in b"\x02ab".iter().enumerate() {
+            memory
+                .write_byte(Byte(0x60 + offset as u16), *byte)
+                .unwrap();
+        }
+        memory.write_byte(Byte(0x51), 0x42).unwrap();
+        let program = [
+            0xBE, 0x00, 0x57, 0x50, 0x03, 0x60, 0x10, 0xBE, 0x01, 0x57, 0x70, 0x04, 0x60, 0x11,
+        ];
+        run_program(&mut cpu, &mut memory, &program, 1);
+        assert_eq!(cpu.get_save_pc(), None);
+        match cpu.get_waiting_event() {
+            Some(ZMachineEvent::SaveTableRequested { name, data }) => {
+                assert_eq!(name.as_deref(), Some("ab"));
+                assert_eq!(data, vec![0x00, 0x42, 0x00]);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        cpu.provide_input(&mut memory, &mut TestIo::default(), ZInput::Saved(true))
+            .unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x10).unwrap(), 1);
+
+        cpu.step(&mut memory, &mut TestIo::default()).unwrap();
+        assert!(matches!(
+            cpu.get_waiting_event(),
+            Some(ZMachineEvent::RestoreTableRequested { max_len: 4, .. })
+        ));
+        cpu.provide_input(
+            &mut memory,
+            &mut TestIo::default(),
+            ZInput::TableRestored(vec![1, 2, 3, 4, 5]),
+        )
+        .unwrap();
+        assert_eq!(cpu.read_variable(&memory, 0x11).unwrap(), 4);
+        assert_eq!(read_bytes(&memory, 0x70, 5), vec![1, 2, 3, 4, 0]);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{ZmError, ZmResult};
+
+/// Maximum number of local variables a routine can declare (R5.2).
+pub const ZCALL_FRAME_MAX_LOCALS: usize = 15;
+
+/// A routine call frame of the `ZCpu` call stack.
+///
+/// Reference: sections 5 and 6.3 of the Standards Document.
+/// http://inform-fiction.org/zmachine/standards/z1point1/sect05.html
+/// http://inform-fiction.org/zmachine/standards/z1point1/sect06.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZCallFrame {
+    /// Address of the instruction to resume at in the caller when the routine returns.
+    return_pc: u32,
+    /// The routine's local variables, referred to as variables 0x01 to 0x0F.
+    ///
+    /// R5.2.1: in V1 to V4 their initial values are given in the routine header,
+    /// in V5+ they all start at zero.
+    locals: Vec<u16>,
+    /// Variable of the caller receiving the value returned by the routine, if any.
+    store_variable: Option<u8>,
+    /// Number of arguments actually supplied by the caller (see `check_arg_count`).
+    arguments_count: u8,
+    /// The routine's own evaluation stack (R6.3.2), referred to as variable 0x00.
+    stack: Vec<u16>,
+}
+
+impl ZCallFrame {
+    pub fn new(
+        return_pc: u32,
+        locals: Vec<u16>,
+        store_variable: Option<u8>,
+        arguments_count: u8,
+    ) -> Self {
+        debug_assert!(locals.len() <= ZCALL_FRAME_MAX_LOCALS);
+        ZCallFrame {
+            return_pc,
+            locals,
+            store_variable,
+            arguments_count,
+            stack: vec![],
+        }
+    }
+
+    /// Set the routine's evaluation stack, e.g. when restoring a saved game.
+    pub fn with_stack(mut self, stack: Vec<u16>) -> Self {
+        self.stack = stack;
+        self
+    }
+
+    pub fn get_return_pc(&self) -> u32 {
+        self.return_pc
+    }
+
+    pub fn get_store_variable(&self) -> Option<u8> {
+        self.store_variable
+    }
+
+    pub fn get_arguments_count(&self) -> u8 {
+        self.arguments_count
+    }
+
+    pub fn get_locals(&self) -> &[u16] {
+        &self.locals
+    }
+
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// NB: parameter `variable` is the variable number, starting at 0x01.
+    pub fn read_local(&self, variable: u8) -> ZmResult<u16> {
+        self.locals
+            .get((variable as usize).wrapping_sub(1))
+            .cloned()
+            .ok_or(ZmError::CpuInvalidLocalVariable(variable))
+    }
+
+    /// NB: parameter `variable` is the variable number, starting at 0x01.
+    pub fn write_local(&mut self, variable: u8, value: u16) -> ZmResult<()> {
+        self.locals
+            .get_mut((variable as usize).wrapping_sub(1))
+            .map(|local| {
+                *local = value;
+            })
+            .ok_or(ZmError::CpuInvalidLocalVariable(variable))
+    }
+
+    pub fn push(&mut self, value: u16) {
+        self.stack.push(value);
+    }
+
+    /// R6.3.1: the stack is considered empty at the start of each routine,
+    /// so popping past the routine's own values is an error.
+    pub fn pop(&mut self) -> ZmResult<u16> {
+        self.stack.pop().ok_or(ZmError::CpuStackUnderflow)
+    }
+
+    pub fn peek(&self) -> ZmResult<u16> {
+        self.stack.last().cloned().ok_or(ZmError::CpuStackUnderflow)
+    }
+
+    pub fn peek_mut(&mut self) -> ZmResult<&mut u16> {
+        self.stack.last_mut().ok_or(ZmError::CpuStackUnderflow)
+    }
+}
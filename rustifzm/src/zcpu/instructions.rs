@@ -1,5 +1,6 @@
-use crate::zmemory::{ZMemory, ZMemoryAddress, ZMemoryAddress::*};
-use crate::{ZMachineVersion, ZMachineVersion::*, ZmError, ZmResult};
+use crate::zcpu::opcodes::ZOpcode;
+use crate::zstring::ZString;
+use crate::{ZMachineVersion, ZMachineVersion::*, ZmResult};
 
 /// The different types of operand for an operation.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -17,6 +18,38 @@ pub enum InstructionOperand {
     Omitted,
 }
 
+impl InstructionOperand {
+    /// Decodes an operand type from its 2-bit field (R4.2).
+    ///
+    /// The returned operand holds a dummy value to be replaced by the actual one.
+    fn from_type_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => InstructionOperand::ConstantLarge(0),
+            0b01 => InstructionOperand::ConstantSmall(0),
+            0b10 => InstructionOperand::Variable(0),
+            _ => InstructionOperand::Omitted,
+        }
+    }
+
+    /// Reads the actual value of an operand whose type is already known.
+    fn read_value<F>(self, next_byte: &mut F) -> ZmResult<Self>
+    where
+        F: FnMut() -> ZmResult<u8>,
+    {
+        use InstructionOperand::*;
+        Ok(match self {
+            ConstantLarge(_) => {
+                let msb = next_byte()? as u16;
+                let lsb = next_byte()? as u16;
+                ConstantLarge((msb << 8) | lsb)
+            }
+            ConstantSmall(_) => ConstantSmall(next_byte()?),
+            Variable(_) => Variable(next_byte()?),
+            Omitted => Omitted,
+        })
+    }
+}
+
 /// The expected number of operands for an operation.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum InstructionOperandCount {
@@ -49,6 +82,63 @@ impl InstructionForm {
     }
 }
 
+/// Where to jump when a branch condition is met (R4.7).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstructionBranchTarget {
+    /// An offset of 0 means "return false from the current routine".
+    ReturnFalse,
+    /// An offset of 1 means "return true from the current routine".
+    ReturnTrue,
+    /// Any other offset is relative to the address following the branch data:
+    /// the destination is `address after branch data + offset - 2`.
+    Offset(i16),
+}
+
+/// The decoded branch information of an instruction (R4.7).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstructionBranch {
+    /// Branch if the condition evaluates to this value.
+    on_true: bool,
+    target: InstructionBranchTarget,
+}
+
+impl InstructionBranch {
+    /// R4.7: if bit 7 of the first byte is clear, the branch occurs when the condition was false.
+    ///
+    /// If bit 6 is set, the branch occupies 1 byte only and the offset is in the range 0 to 63,
+    /// given in the bottom 6 bits. If bit 6 is clear, the offset is a signed 14-bit number
+    /// given in bits 0 to 5 of the first byte followed by all 8 of the second.
+    fn decoded<F>(next_byte: &mut F) -> ZmResult<Self>
+    where
+        F: FnMut() -> ZmResult<u8>,
+    {
+        let first = next_byte()?;
+        let on_true = first & 0b_1000_0000 != 0;
+        let offset = if first & 0b_0100_0000 != 0 {
+            (first & 0b_0011_1111) as i16
+        } else {
+            let second = next_byte()?;
+            let raw = (((first & 0b_0011_1111) as u16) << 8) | second as u16;
+            // sign-extend the 14-bit value
+            ((raw << 2) as i16) >> 2
+        };
+        let target = match offset {
+            0 => InstructionBranchTarget::ReturnFalse,
+            1 => InstructionBranchTarget::ReturnTrue,
+            _ => InstructionBranchTarget::Offset(offset),
+        };
+        Ok(InstructionBranch { on_true, target })
+    }
+
+    pub fn on_true(&self) -> bool {
+        self.on_true
+    }
+
+    pub fn target(&self) -> &InstructionBranchTarget {
+        &self.target
+    }
+}
+
 /// A decoded instruction for the `ZCpu` to execute.
 ///
 /// An instruction is described in memory according to the following layout,
@@ -67,9 +157,17 @@ impl InstructionForm {
 /// http://inform-fiction.org/zmachine/standards/z1point1/sect04.html
 #[derive(Clone, Debug)]
 pub struct Operation {
-    form: InstructionForm,
-    opcode_number: u8,
+    opcode: ZOpcode,
     operands: Vec<InstructionOperand>,
+    /// Variable receiving the result of the instruction, if any (R4.6).
+    store_variable: Option<u8>,
+    branch: Option<InstructionBranch>,
+    /// Inline text of `print` and `print_ret` (R4.8).
+    text: Option<ZString>,
+    /// Total length of the encoded instruction, in bytes.
+    length: usize,
+    /// Length of the store variable and branch data, in bytes.
+    result_length: usize,
 }
 
 impl Operation {
@@ -77,13 +175,19 @@ impl Operation {
     where
         F: FnMut() -> ZmResult<u8>,
     {
+        let mut length = 0;
+        let mut next_byte = || {
+            length += 1;
+            next_byte()
+        };
+
         let opcode_msb = next_byte()?;
         let form = InstructionForm::from_opcode(opcode_msb, target);
         let (opcode_number, operands_count) = match form {
             InstructionForm::Short => {
                 // R4.3.1
                 let operands_count = match (opcode_msb & 0b_0011_0000) >> 4 {
-                    0b00 => InstructionOperandCount::Fixed(0),
+                    0b11 => InstructionOperandCount::Fixed(0),
                     _ => InstructionOperandCount::Fixed(1),
                 };
                 (opcode_msb & 0b_0000_1111, operands_count)
@@ -91,19 +195,320 @@ impl Operation {
             InstructionForm::Long => (opcode_msb & 0b_0001_1111, InstructionOperandCount::Fixed(2)), // R4.3.2
             InstructionForm::Variable => {
                 // R4.3.3
-                let operands_count = match (opcode_msb & 0b_0010_0000) >> 5 {
-                    0b0 => InstructionOperandCount::Fixed(2),
-                    0b1 => InstructionOperandCount::Variable,
-                    _ => unreachable!(),
+                let operands_count = if opcode_msb & 0b_0010_0000 == 0 {
+                    InstructionOperandCount::Fixed(2)
+                } else {
+                    InstructionOperandCount::Variable
                 };
                 (opcode_msb & 0b_0001_1111, operands_count)
             }
             InstructionForm::Extended => (next_byte()?, InstructionOperandCount::Variable), // R4.3.4
         };
+        let opcode = ZOpcode::from_instruction(&form, &operands_count, opcode_number, target)?;
+
+        // operand types (R4.4)
+        let operand_types = match form {
+            InstructionForm::Short => match operands_count {
+                InstructionOperandCount::Fixed(0) => vec![],
+                _ => vec![InstructionOperand::from_type_bits(opcode_msb >> 4)],
+            },
+            // R4.4.2: bits 6 and 5 give the types of the first and second operands,
+            // 0 meaning a small constant and 1 a variable
+            InstructionForm::Long => [0b_0100_0000, 0b_0010_0000]
+                .iter()
+                .map(|mask| {
+                    if opcode_msb & mask == 0 {
+                        InstructionOperand::ConstantSmall(0)
+                    } else {
+                        InstructionOperand::Variable(0)
+                    }
+                })
+                .collect(),
+            // R4.4.3: a byte of 4 operand types, or 2 bytes of 8 types for the
+            // double variable call opcodes (R4.4.3.1)
+            InstructionForm::Variable | InstructionForm::Extended => {
+                let types_bytes_count = if opcode.has_double_operand_types() {
+                    2
+                } else {
+                    1
+                };
+                let mut types = Vec::with_capacity(4 * types_bytes_count);
+                for _ in 0..types_bytes_count {
+                    let types_byte = next_byte()?;
+                    for shift in [6, 4, 2, 0] {
+                        types.push(InstructionOperand::from_type_bits(types_byte >> shift));
+                    }
+                }
+                // once one type has been given as 'omitted', all subsequent ones must be
+                types
+                    .into_iter()
+                    .take_while(|operand_type| *operand_type != InstructionOperand::Omitted)
+                    .collect()
+            }
+        };
+        let mut operands = Vec::with_capacity(operand_types.len());
+        for operand_type in operand_types {
+            operands.push(operand_type.read_value(&mut next_byte)?);
+        }
+
+        let mut result_length = 0;
+        let (store_variable, branch) = Self::decoded_result(target, opcode, || {
+            result_length += 1;
+            next_byte()
+        })?;
+        let text = if opcode.has_text() {
+            Some(ZString::read_with(&mut next_byte)?)
+        } else {
+            None
+        };
+
+        Ok(Operation {
+            opcode,
+            operands,
+            store_variable,
+            branch,
+            text,
+            length,
+            result_length,
+        })
+    }
+
+    /// Decodes the `save` instruction from its store variable or branch data, as pointed to
+    /// by the PC of a saved game (Quetzal 5.8): its opcode and lack of operands are implied.
+    pub fn decoded_save<F>(target: ZMachineVersion, mut next_byte: F) -> ZmResult<Self>
+    where
+        F: FnMut() -> ZmResult<u8>,
+    {
+        let opcode = if target >= ZMachineVersion::V5 {
+            ZOpcode::EXT_0
+        } else {
+            ZOpcode::OP0_181
+        };
+        let mut result_length = 0;
+        let (store_variable, branch) = Self::decoded_result(target, opcode, || {
+            result_length += 1;
+            next_byte()
+        })?;
         Ok(Operation {
-            form,
-            opcode_number,
+            opcode,
             operands: vec![],
+            store_variable,
+            branch,
+            text: None,
+            length: result_length,
+            result_length,
         })
     }
+
+    /// Decodes the store variable and branch data following the operands (R4.6, R4.7).
+    fn decoded_result<F>(
+        target: ZMachineVersion,
+        opcode: ZOpcode,
+        mut next_byte: F,
+    ) -> ZmResult<(Option<u8>, Option<InstructionBranch>)>
+    where
+        F: FnMut() -> ZmResult<u8>,
+    {
+        let store_variable = if opcode.stores(target) {
+            Some(next_byte()?)
+        } else {
+            None
+        };
+        let branch = if opcode.branches(target) {
+            Some(InstructionBranch::decoded(&mut next_byte)?)
+        } else {
+            None
+        };
+        Ok((store_variable, branch))
+    }
+
+    pub fn get_opcode(&self) -> ZOpcode {
+        self.opcode
+    }
+
+    pub fn get_operands(&self) -> &[InstructionOperand] {
+        &self.operands
+    }
+
+    pub fn get_store_variable(&self) -> Option<u8> {
+        self.store_variable
+    }
+
+    pub fn get_branch(&self) -> Option<&InstructionBranch> {
+        self.branch.as_ref()
+    }
+
+    pub fn get_text(&self) -> Option<&ZString> {
+        self.text.as_ref()
+    }
+
+    /// Get the size of the encoded instruction, in bytes.
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+
+    /// Get the size of the store variable and branch data, in bytes.
+    pub fn get_result_length(&self) -> usize {
+        self.result_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zcpu::opcodes::ZOpcodeClass;
+    use crate::ZmError;
+
+    /// Decode the instruction, which must span all of the given bytes.
+    fn decode(target: ZMachineVersion, bytes: &[u8]) -> Operation {
+        let mut iter = bytes.iter();
+        let operation = Operation::decoded(target, || {
+            iter.next().cloned().ok_or(ZmError::MemoryInvalidAccess(0))
+        })
+        .unwrap();
+        assert_eq!(iter.len(), 0, "should decode every byte");
+        operation
+    }
+
+    #[test]
+    fn test_decode_long_form_with_store() {
+        // add L01 #05 -> sp
+        let operation = decode(V3, &[0x54, 0x01, 0x05, 0x00]);
+        assert_eq!(operation.get_opcode(), ZOpcode::OP2_20);
+        assert_eq!(
+            operation.get_operands(),
+            &[
+                InstructionOperand::Variable(0x01),
+                InstructionOperand::ConstantSmall(0x05)
+            ]
+        );
+        assert_eq!(operation.get_store_variable(), Some(0x00));
+        assert!(operation.get_branch().is_none());
+        assert_eq!(operation.get_length(), 4);
+    }
+
+    #[test]
+    fn test_decode_short_form_with_branch() {
+        // jz L02 ?~(+0x1234 as 14-bit signed)
+        let operation = decode(V5, &[0xA0, 0x02, 0x12, 0x34]);
+        assert_eq!(operation.get_opcode(), ZOpcode::OP1_128);
+        assert_eq!(
+            operation.get_operands(),
+            &[InstructionOperand::Variable(0x02)]
+        );
+        let branch = operation.get_branch().unwrap();
+        assert!(!branch.on_true());
+        assert_eq!(branch.target(), &InstructionBranchTarget::Offset(0x1234));
+
+        // jz #00 ?rtrue and negative 14-bit offset
+        let operation = decode(V5, &[0x90, 0x00, 0xC1]);
+        let branch = operation.get_branch().unwrap();
+        assert!(branch.on_true());
+        assert_eq!(branch.target(), &InstructionBranchTarget::ReturnTrue);
+        let operation = decode(V5, &[0x90, 0x00, 0xBF, 0xFE]);
+        assert_eq!(
+            operation.get_branch().unwrap().target(),
+            &InstructionBranchTarget::Offset(-2)
+        );
+    }
+
+    #[test]
+    fn test_decode_variable_form() {
+        // call_vs2 with 5 operands: two types bytes
+        let operation = decode(
+            V5,
+            &[0xEC, 0x15, 0xBF, 0x12, 0x34, 0x01, 0x02, 0x03, 0x10, 0x03],
+        );
+        assert_eq!(operation.get_opcode(), ZOpcode::VAR_236);
+        assert_eq!(
+            operation.get_operands(),
+            &[
+                InstructionOperand::ConstantLarge(0x1234),
+                InstructionOperand::ConstantSmall(0x01),
+                InstructionOperand::ConstantSmall(0x02),
+                InstructionOperand::ConstantSmall(0x03),
+                InstructionOperand::Variable(0x10),
+            ]
+        );
+        assert_eq!(operation.get_store_variable(), Some(0x03));
+
+        // je with 3 operands in variable form
+        let operation = decode(V3, &[0xC1, 0x57, 0x01, 0x02, 0x03, 0xC5]);
+        assert_eq!(operation.get_opcode(), ZOpcode::OP2_1);
+        assert_eq!(operation.get_operands().len(), 3);
+        assert!(operation.get_branch().is_some());
+    }
+
+    #[test]
+    fn test_decode_extended_form() {
+        // log_shift #01 #02 -> L03
+        let operation = decode(V5, &[0xBE, 0x02, 0x5F, 0x01, 0x02, 0x03]);
+        assert_eq!(operation.get_opcode(), ZOpcode::EXT_2);
+        assert_eq!(operation.get_operands().len(), 2);
+        assert_eq!(operation.get_store_variable(), Some(0x03));
+        assert_eq!(operation.get_length(), 6);
+    }
+
+    #[test]
+    fn test_decode_version_specific_opcodes() {
+        // 1OP:143 is `not` (with store) in V1-4 and `call_1n` (no store) in V5+
+        let operation = decode(V4, &[0x8F, 0x12, 0x34, 0x05]);
+        assert_eq!(operation.get_opcode(), ZOpcode::OP1_143);
+        assert_eq!(operation.get_opcode().mnemonic(V4), Some("not"));
+        assert_eq!(operation.get_store_variable(), Some(0x05));
+        let operation = decode(V5, &[0x8F, 0x12, 0x34]);
+        assert_eq!(operation.get_opcode().mnemonic(V5), Some("call_1n"));
+        assert_eq!(operation.get_store_variable(), None);
+        assert_eq!(operation.get_length(), 3);
+
+        // VAR:248 (`not`) only exists from V5 onward
+        let mut bytes = [0xF8, 0x7F, 0x01, 0x02].iter();
+        assert!(matches!(
+            Operation::decoded(V4, || bytes
+                .next()
+                .cloned()
+                .ok_or(ZmError::MemoryInvalidAccess(0))),
+            Err(ZmError::InstructionIllegalOpcode(
+                ZOpcodeClass::Var,
+                248,
+                V4
+            ))
+        ));
+        // 0OP:190 is the extended form marker in V5+ only
+        let mut bytes = [0xBE, 0x00].iter();
+        assert!(matches!(
+            Operation::decoded(V3, || bytes
+                .next()
+                .cloned()
+                .ok_or(ZmError::MemoryInvalidAccess(0))),
+            Err(ZmError::InstructionIllegalOpcode(
+                ZOpcodeClass::Op0,
+                190,
+                V3
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_decode_save() {
+        // the branch data of save ?(+0x123), then the store variable of save -> sp
+        for (target, bytes, length) in [(V3, &[0x01, 0x23][..], 2), (V5, &[0x00][..], 1)] {
+            let mut iter = bytes.iter();
+            let operation = Operation::decoded_save(target, || {
+                iter.next().cloned().ok_or(ZmError::MemoryInvalidAccess(0))
+            })
+            .unwrap();
+            assert_eq!(operation.get_operands(), &[]);
+            assert_eq!(operation.get_length(), length);
+            assert_eq!(operation.get_result_length(), length);
+        }
+    }
+
+    #[test]
+    fn test_decode_inline_text() {
+        // print "hello" (2 words, end bit on the last one)
+        let operation = decode(V3, &[0xB2, 0x35, 0x51, 0xC6, 0x85]);
+        assert_eq!(operation.get_text().unwrap().len(), 6);
+        assert_eq!(operation.get_length(), 5);
+    }
 }
@@ -1,15 +1,672 @@
+use std::fmt;
+
+use crate::zcpu::instructions::{InstructionForm, InstructionOperandCount};
+use crate::{ZMachineVersion, ZMachineVersion::*, ZmError, ZmResult};
+use ZOpcode::*;
+
 /// The different instructions allowed by the Z-machine.
 ///
 /// This internal representation allows for efficient and human-readable dispatching,
 /// and will facilitate potential future tooling like a disassembler.
-#[derive(Clone, Debug, PartialEq, Eq)]
+///
+/// Variants are named after the opcode's class and number in the Standards Document's table,
+/// since the same opcode may be given different names (or meanings) across versions.
+///
+/// Reference: section 14 of the Standards Document.
+/// http://inform-fiction.org/zmachine/standards/z1point1/sect14.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
 pub enum ZOpcode {
+    /// 2OP:1 1 je
+    OP2_1,
+    /// 2OP:2 2 jl
+    OP2_2,
+    /// 2OP:3 3 jg
+    OP2_3,
+    /// 2OP:4 4 dec_chk
+    OP2_4,
+    /// 2OP:5 5 inc_chk
+    OP2_5,
+    /// 2OP:6 6 jin
+    OP2_6,
+    /// 2OP:7 7 test
+    OP2_7,
+    /// 2OP:8 8 or
+    OP2_8,
+    /// 2OP:9 9 and
+    OP2_9,
+    /// 2OP:10 A test_attr
+    OP2_10,
+    /// 2OP:11 B set_attr
+    OP2_11,
+    /// 2OP:12 C clear_attr
+    OP2_12,
+    /// 2OP:13 D store
+    OP2_13,
+    /// 2OP:14 E insert_obj
+    OP2_14,
+    /// 2OP:15 F loadw
+    OP2_15,
+    /// 2OP:16 10 loadb
+    OP2_16,
+    /// 2OP:17 11 get_prop
+    OP2_17,
+    /// 2OP:18 12 get_prop_addr
+    OP2_18,
+    /// 2OP:19 13 get_next_prop
+    OP2_19,
+    /// 2OP:20 14 add
+    OP2_20,
+    /// 2OP:21 15 sub
+    OP2_21,
+    /// 2OP:22 16 mul
+    OP2_22,
+    /// 2OP:23 17 div
+    OP2_23,
+    /// 2OP:24 18 mod
+    OP2_24,
+    /// 2OP:25 19 call_2s (V4+)
+    OP2_25,
+    /// 2OP:26 1A call_2n (V5+)
+    OP2_26,
+    /// 2OP:27 1B set_colour (V5+)
+    OP2_27,
+    /// 2OP:28 1C throw (V5+)
+    OP2_28,
+    /// 1OP:128 0 jz
+    OP1_128,
+    /// 1OP:129 1 get_sibling
+    OP1_129,
+    /// 1OP:130 2 get_child
+    OP1_130,
+    /// 1OP:131 3 get_parent
+    OP1_131,
+    /// 1OP:132 4 get_prop_len
+    OP1_132,
+    /// 1OP:133 5 inc
+    OP1_133,
+    /// 1OP:134 6 dec
+    OP1_134,
+    /// 1OP:135 7 print_addr
+    OP1_135,
+    /// 1OP:136 8 call_1s (V4+)
+    OP1_136,
+    /// 1OP:137 9 remove_obj
+    OP1_137,
+    /// 1OP:138 A print_obj
+    OP1_138,
+    /// 1OP:139 B ret
+    OP1_139,
+    /// 1OP:140 C jump
+    OP1_140,
+    /// 1OP:141 D print_paddr
+    OP1_141,
+    /// 1OP:142 E load
+    OP1_142,
+    /// 1OP:143 F not (V1-4) / call_1n (V5+)
+    OP1_143,
     /// 0OP:176 0 rtrue
     OP0_176,
     /// 0OP:177 1 rfalse
     OP0_177,
+    /// 0OP:178 2 print
+    OP0_178,
+    /// 0OP:179 3 print_ret
+    OP0_179,
+    /// 0OP:180 4 nop
+    OP0_180,
+    /// 0OP:181 5 save (V1-4)
+    OP0_181,
+    /// 0OP:182 6 restore (V1-4)
+    OP0_182,
+    /// 0OP:183 7 restart
+    OP0_183,
+    /// 0OP:184 8 ret_popped
+    OP0_184,
+    /// 0OP:185 9 pop (V1-4) / catch (V5+)
+    OP0_185,
+    /// 0OP:186 A quit
+    OP0_186,
+    /// 0OP:187 B new_line
+    OP0_187,
+    /// 0OP:188 C show_status (V3+)
+    OP0_188,
+    /// 0OP:189 D verify (V3+)
+    OP0_189,
+    /// 0OP:191 F piracy (V5+)
+    OP0_191,
+    /// VAR:224 0 call (V1-3) / call_vs (V4+)
+    VAR_224,
+    /// VAR:225 1 storew
+    VAR_225,
+    /// VAR:226 2 storeb
+    VAR_226,
+    /// VAR:227 3 put_prop
+    VAR_227,
+    /// VAR:228 4 sread (V1-4) / aread (V5+)
+    VAR_228,
+    /// VAR:229 5 print_char
+    VAR_229,
+    /// VAR:230 6 print_num
+    VAR_230,
+    /// VAR:231 7 random
+    VAR_231,
+    /// VAR:232 8 push
+    VAR_232,
+    /// VAR:233 9 pull
+    VAR_233,
+    /// VAR:234 A split_window (V3+)
+    VAR_234,
+    /// VAR:235 B set_window (V3+)
+    VAR_235,
+    /// VAR:236 C call_vs2 (V4+)
+    VAR_236,
+    /// VAR:237 D erase_window (V4+)
+    VAR_237,
+    /// VAR:238 E erase_line (V4+)
+    VAR_238,
+    /// VAR:239 F set_cursor (V4+)
+    VAR_239,
+    /// VAR:240 10 get_cursor (V4+)
+    VAR_240,
+    /// VAR:241 11 set_text_style (V4+)
+    VAR_241,
+    /// VAR:242 12 buffer_mode (V4+)
+    VAR_242,
+    /// VAR:243 13 output_stream (V3+)
+    VAR_243,
+    /// VAR:244 14 input_stream (V3+)
+    VAR_244,
+    /// VAR:245 15 sound_effect (V3+)
+    VAR_245,
+    /// VAR:246 16 read_char (V4+)
+    VAR_246,
+    /// VAR:247 17 scan_table (V4+)
+    VAR_247,
+    /// VAR:248 18 not (V5+)
+    VAR_248,
+    /// VAR:249 19 call_vn (V5+)
+    VAR_249,
+    /// VAR:250 1A call_vn2 (V5+)
+    VAR_250,
+    /// VAR:251 1B tokenise (V5+)
+    VAR_251,
+    /// VAR:252 1C encode_text (V5+)
+    VAR_252,
+    /// VAR:253 1D copy_table (V5+)
+    VAR_253,
+    /// VAR:254 1E print_table (V5+)
+    VAR_254,
+    /// VAR:255 1F check_arg_count (V5+)
+    VAR_255,
+    /// EXT:0 0 save (V5+)
+    EXT_0,
+    /// EXT:1 1 restore (V5+)
+    EXT_1,
+    /// EXT:2 2 log_shift (V5+)
+    EXT_2,
+    /// EXT:3 3 art_shift (V5+)
+    EXT_3,
+    /// EXT:4 4 set_font (V5+)
+    EXT_4,
+    /// EXT:5 5 draw_picture (V6)
+    EXT_5,
+    /// EXT:6 6 picture_data (V6)
+    EXT_6,
+    /// EXT:7 7 erase_picture (V6)
+    EXT_7,
+    /// EXT:8 8 set_margins (V6)
+    EXT_8,
+    /// EXT:9 9 save_undo (V5+)
+    EXT_9,
+    /// EXT:10 A restore_undo (V5+)
+    EXT_10,
+    /// EXT:11 B print_unicode (V5+)
+    EXT_11,
+    /// EXT:12 C check_unicode (V5+)
+    EXT_12,
+    /// EXT:13 D set_true_colour (V5+)
+    EXT_13,
+    /// EXT:16 10 move_window (V6)
+    EXT_16,
+    /// EXT:17 11 window_size (V6)
+    EXT_17,
+    /// EXT:18 12 window_style (V6)
+    EXT_18,
+    /// EXT:19 13 get_wind_prop (V6)
+    EXT_19,
+    /// EXT:20 14 scroll_window (V6)
+    EXT_20,
+    /// EXT:21 15 pop_stack (V6)
+    EXT_21,
+    /// EXT:22 16 read_mouse (V6)
+    EXT_22,
+    /// EXT:23 17 mouse_window (V6)
+    EXT_23,
+    /// EXT:24 18 push_stack (V6)
+    EXT_24,
+    /// EXT:25 19 put_wind_prop (V6)
+    EXT_25,
+    /// EXT:26 1A print_form (V6)
+    EXT_26,
+    /// EXT:27 1B make_menu (V6)
+    EXT_27,
+    /// EXT:28 1C picture_table (V6)
+    EXT_28,
+    /// EXT:29 1D buffer_screen (V6)
+    EXT_29,
+}
+
+/// The metadata of an opcode for a range of versions.
+struct ZOpcodeSpec {
+    /// The first and last versions allowing this opcode with this meaning.
+    versions: (ZMachineVersion, ZMachineVersion),
+    /// The name given to the opcode by Inform.
+    mnemonic: &'static str,
+    /// Does the opcode store a result (R4.6)?
+    store: bool,
+    /// Does the opcode branch on a condition (R4.7)?
+    branch: bool,
+}
+
+macro_rules! spec {
+    ($versions: expr, $mnemonic: expr, $store: expr, $branch: expr) => {
+        ZOpcodeSpec {
+            versions: $versions,
+            mnemonic: $mnemonic,
+            store: $store,
+            branch: $branch,
+        }
+    };
+}
+
+const ALL: (ZMachineVersion, ZMachineVersion) = (V1, V8);
+
+/// The classes of opcodes in the Standards Document's table, named after their operand count.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZOpcodeClass {
+    /// 2OP, numbered from 0 to 31.
+    Op2,
+    /// 1OP, numbered from 128 to 143.
+    Op1,
+    /// 0OP, numbered from 176 to 191.
+    Op0,
+    /// VAR, numbered from 224 to 255.
+    Var,
+    /// EXT (V5+), numbered from 0 to 255.
+    Ext,
 }
 
+impl fmt::Display for ZOpcodeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ZOpcodeClass::Op2 => "2OP",
+            ZOpcodeClass::Op1 => "1OP",
+            ZOpcodeClass::Op0 => "0OP",
+            ZOpcodeClass::Var => "VAR",
+            ZOpcodeClass::Ext => "EXT",
+        })
+    }
+}
+
+impl ZOpcodeClass {
+    /// Returns the class of an instruction from its form and operand count (R4.3),
+    /// along with the number of its opcode in the Standards Document's table
+    /// (for instance 1OP:143).
+    fn from_instruction(
+        form: &InstructionForm,
+        operands_count: &InstructionOperandCount,
+        opcode_number: u8,
+    ) -> (Self, u8) {
+        use ZOpcodeClass::*;
+        match (form, operands_count) {
+            (InstructionForm::Extended, _) => (Ext, opcode_number),
+            (_, InstructionOperandCount::Fixed(0)) => (Op0, 176 + opcode_number),
+            (_, InstructionOperandCount::Fixed(1)) => (Op1, 128 + opcode_number),
+            (_, InstructionOperandCount::Fixed(_)) => (Op2, opcode_number),
+            (_, InstructionOperandCount::Variable) => (Var, 224 + opcode_number),
+        }
+    }
+
+    /// Returns the number of the first opcode of the class, and the opcodes from then on,
+    /// `None` marking the numbers without an opcode.
+    fn opcodes(&self) -> (u8, &'static [Option<ZOpcode>]) {
+        match self {
+            ZOpcodeClass::Op2 => (0, &OPCODES_2OP),
+            ZOpcodeClass::Op1 => (128, &OPCODES_1OP),
+            ZOpcodeClass::Op0 => (176, &OPCODES_0OP),
+            ZOpcodeClass::Var => (224, &OPCODES_VAR),
+            ZOpcodeClass::Ext => (0, &OPCODES_EXT),
+        }
+    }
+
+    /// Returns the opcode of the given number in this class, if any.
+    fn opcode(&self, number: u8) -> Option<ZOpcode> {
+        let (first, opcodes) = self.opcodes();
+        number
+            .checked_sub(first)
+            .and_then(|index| opcodes.get(index as usize))
+            .copied()
+            .flatten()
+    }
+}
+
+/// 2OP:0 is unassigned.
+#[rustfmt::skip]
+const OPCODES_2OP: [Option<ZOpcode>; 29] = [
+    None, Some(OP2_1), Some(OP2_2), Some(OP2_3),
+    Some(OP2_4), Some(OP2_5), Some(OP2_6), Some(OP2_7),
+    Some(OP2_8), Some(OP2_9), Some(OP2_10), Some(OP2_11),
+    Some(OP2_12), Some(OP2_13), Some(OP2_14), Some(OP2_15),
+    Some(OP2_16), Some(OP2_17), Some(OP2_18), Some(OP2_19),
+    Some(OP2_20), Some(OP2_21), Some(OP2_22), Some(OP2_23),
+    Some(OP2_24), Some(OP2_25), Some(OP2_26), Some(OP2_27),
+    Some(OP2_28),
+];
+
+#[rustfmt::skip]
+const OPCODES_1OP: [Option<ZOpcode>; 16] = [
+    Some(OP1_128), Some(OP1_129), Some(OP1_130), Some(OP1_131),
+    Some(OP1_132), Some(OP1_133), Some(OP1_134), Some(OP1_135),
+    Some(OP1_136), Some(OP1_137), Some(OP1_138), Some(OP1_139),
+    Some(OP1_140), Some(OP1_141), Some(OP1_142), Some(OP1_143),
+];
+
+/// 0OP:190 is the extended form marker (R4.3.4), not an opcode.
+#[rustfmt::skip]
+const OPCODES_0OP: [Option<ZOpcode>; 16] = [
+    Some(OP0_176), Some(OP0_177), Some(OP0_178), Some(OP0_179),
+    Some(OP0_180), Some(OP0_181), Some(OP0_182), Some(OP0_183),
+    Some(OP0_184), Some(OP0_185), Some(OP0_186), Some(OP0_187),
+    Some(OP0_188), Some(OP0_189), None, Some(OP0_191),
+];
+
+#[rustfmt::skip]
+const OPCODES_VAR: [Option<ZOpcode>; 32] = [
+    Some(VAR_224), Some(VAR_225), Some(VAR_226), Some(VAR_227),
+    Some(VAR_228), Some(VAR_229), Some(VAR_230), Some(VAR_231),
+    Some(VAR_232), Some(VAR_233), Some(VAR_234), Some(VAR_235),
+    Some(VAR_236), Some(VAR_237), Some(VAR_238), Some(VAR_239),
+    Some(VAR_240), Some(VAR_241), Some(VAR_242), Some(VAR_243),
+    Some(VAR_244), Some(VAR_245), Some(VAR_246), Some(VAR_247),
+    Some(VAR_248), Some(VAR_249), Some(VAR_250), Some(VAR_251),
+    Some(VAR_252), Some(VAR_253), Some(VAR_254), Some(VAR_255),
+];
+
+/// EXT:14 and EXT:15 are unassigned.
+#[rustfmt::skip]
+const OPCODES_EXT: [Option<ZOpcode>; 30] = [
+    Some(EXT_0), Some(EXT_1), Some(EXT_2), Some(EXT_3),
+    Some(EXT_4), Some(EXT_5), Some(EXT_6), Some(EXT_7),
+    Some(EXT_8), Some(EXT_9), Some(EXT_10), Some(EXT_11),
+    Some(EXT_12), Some(EXT_13), None, None,
+    Some(EXT_16), Some(EXT_17), Some(EXT_18), Some(EXT_19),
+    Some(EXT_20), Some(EXT_21), Some(EXT_22), Some(EXT_23),
+    Some(EXT_24), Some(EXT_25), Some(EXT_26), Some(EXT_27),
+    Some(EXT_28), Some(EXT_29),
+];
+
 impl ZOpcode {
-    // pub fn disassemble()
+    /// Maps an instruction's form, operand count and opcode number to its typed opcode (R14).
+    ///
+    /// Fails with `ZmError::InstructionIllegalOpcode` if the opcode does not exist
+    /// or is not allowed in the target version.
+    pub fn from_instruction(
+        form: &InstructionForm,
+        operands_count: &InstructionOperandCount,
+        opcode_number: u8,
+        target: ZMachineVersion,
+    ) -> ZmResult<Self> {
+        let (class, number) = ZOpcodeClass::from_instruction(form, operands_count, opcode_number);
+        match class.opcode(number) {
+            Some(opcode) if opcode.is_available(target) => Ok(opcode),
+            _ => Err(ZmError::InstructionIllegalOpcode(class, number, target)),
+        }
+    }
+
+    fn specs(&self) -> &'static [ZOpcodeSpec] {
+        match self {
+            OP2_1 => &[spec!(ALL, "je", false, true)],
+            OP2_2 => &[spec!(ALL, "jl", false, true)],
+            OP2_3 => &[spec!(ALL, "jg", false, true)],
+            OP2_4 => &[spec!(ALL, "dec_chk", false, true)],
+            OP2_5 => &[spec!(ALL, "inc_chk", false, true)],
+            OP2_6 => &[spec!(ALL, "jin", false, true)],
+            OP2_7 => &[spec!(ALL, "test", false, true)],
+            OP2_8 => &[spec!(ALL, "or", true, false)],
+            OP2_9 => &[spec!(ALL, "and", true, false)],
+            OP2_10 => &[spec!(ALL, "test_attr", false, true)],
+            OP2_11 => &[spec!(ALL, "set_attr", false, false)],
+            OP2_12 => &[spec!(ALL, "clear_attr", false, false)],
+            OP2_13 => &[spec!(ALL, "store", false, false)],
+            OP2_14 => &[spec!(ALL, "insert_obj", false, false)],
+            OP2_15 => &[spec!(ALL, "loadw", true, false)],
+            OP2_16 => &[spec!(ALL, "loadb", true, false)],
+            OP2_17 => &[spec!(ALL, "get_prop", true, false)],
+            OP2_18 => &[spec!(ALL, "get_prop_addr", true, false)],
+            OP2_19 => &[spec!(ALL, "get_next_prop", true, false)],
+            OP2_20 => &[spec!(ALL, "add", true, false)],
+            OP2_21 => &[spec!(ALL, "sub", true, false)],
+            OP2_22 => &[spec!(ALL, "mul", true, false)],
+            OP2_23 => &[spec!(ALL, "div", true, false)],
+            OP2_24 => &[spec!(ALL, "mod", true, false)],
+            OP2_25 => &[spec!((V4, V8), "call_2s", true, false)],
+            OP2_26 => &[spec!((V5, V8), "call_2n", false, false)],
+            OP2_27 => &[spec!((V5, V8), "set_colour", false, false)],
+            OP2_28 => &[spec!((V5, V8), "throw", false, false)],
+            OP1_128 => &[spec!(ALL, "jz", false, true)],
+            OP1_129 => &[spec!(ALL, "get_sibling", true, true)],
+            OP1_130 => &[spec!(ALL, "get_child", true, true)],
+            OP1_131 => &[spec!(ALL, "get_parent", true, false)],
+            OP1_132 => &[spec!(ALL, "get_prop_len", true, false)],
+            OP1_133 => &[spec!(ALL, "inc", false, false)],
+            OP1_134 => &[spec!(ALL, "dec", false, false)],
+            OP1_135 => &[spec!(ALL, "print_addr", false, false)],
+            OP1_136 => &[spec!((V4, V8), "call_1s", true, false)],
+            OP1_137 => &[spec!(ALL, "remove_obj", false, false)],
+            OP1_138 => &[spec!(ALL, "print_obj", false, false)],
+            OP1_139 => &[spec!(ALL, "ret", false, false)],
+            OP1_140 => &[spec!(ALL, "jump", false, false)],
+            OP1_141 => &[spec!(ALL, "print_paddr", false, false)],
+            OP1_142 => &[spec!(ALL, "load", true, false)],
+            OP1_143 => &[
+                spec!((V1, V4), "not", true, false),
+                spec!((V5, V8), "call_1n", false, false),
+            ],
+            OP0_176 => &[spec!(ALL, "rtrue", false, false)],
+            OP0_177 => &[spec!(ALL, "rfalse", false, false)],
+            OP0_178 => &[spec!(ALL, "print", false, false)],
+            OP0_179 => &[spec!(ALL, "print_ret", false, false)],
+            OP0_180 => &[spec!(ALL, "nop", false, false)],
+            OP0_181 => &[
+                spec!((V1, V3), "save", false, true),
+                spec!((V4, V4), "save", true, false),
+            ],
+            OP0_182 => &[
+                spec!((V1, V3), "restore", false, true),
+                spec!((V4, V4), "restore", true, false),
+            ],
+            OP0_183 => &[spec!(ALL, "restart", false, false)],
+            OP0_184 => &[spec!(ALL, "ret_popped", false, false)],
+            OP0_185 => &[
+                spec!((V1, V4), "pop", false, false),
+                spec!((V5, V8), "catch", true, false),
+            ],
+            OP0_186 => &[spec!(ALL, "quit", false, false)],
+            OP0_187 => &[spec!(ALL, "new_line", false, false)],
+            OP0_188 => &[spec!((V3, V8), "show_status", false, false)],
+            OP0_189 => &[spec!((V3, V8), "verify", false, true)],
+            OP0_191 => &[spec!((V5, V8), "piracy", false, true)],
+            VAR_224 => &[
+                spec!((V1, V3), "call", true, false),
+                spec!((V4, V8), "call_vs", true, false),
+            ],
+            VAR_225 => &[spec!(ALL, "storew", false, false)],
+            VAR_226 => &[spec!(ALL, "storeb", false, false)],
+            VAR_227 => &[spec!(ALL, "put_prop", false, false)],
+            VAR_228 => &[
+                spec!((V1, V4), "sread", false, false),
+                spec!((V5, V8), "aread", true, false),
+            ],
+            VAR_229 => &[spec!(ALL, "print_char", false, false)],
+            VAR_230 => &[spec!(ALL, "print_num", false, false)],
+            VAR_231 => &[spec!(ALL, "random", true, false)],
+            VAR_232 => &[spec!(ALL, "push", false, false)],
+            VAR_233 => &[
+                spec!((V1, V5), "pull", false, false),
+                spec!((V6, V6), "pull", true, false),
+                spec!((V7, V8), "pull", false, false),
+            ],
+            VAR_234 => &[spec!((V3, V8), "split_window", false, false)],
+            VAR_235 => &[spec!((V3, V8), "set_window", false, false)],
+            VAR_236 => &[spec!((V4, V8), "call_vs2", true, false)],
+            VAR_237 => &[spec!((V4, V8), "erase_window", false, false)],
+            VAR_238 => &[spec!((V4, V8), "erase_line", false, false)],
+            VAR_239 => &[spec!((V4, V8), "set_cursor", false, false)],
+            VAR_240 => &[spec!((V4, V8), "get_cursor", false, false)],
+            VAR_241 => &[spec!((V4, V8), "set_text_style", false, false)],
+            VAR_242 => &[spec!((V4, V8), "buffer_mode", false, false)],
+            VAR_243 => &[spec!((V3, V8), "output_stream", false, false)],
+            VAR_244 => &[spec!((V3, V8), "input_stream", false, false)],
+            VAR_245 => &[spec!((V3, V8), "sound_effect", false, false)],
+            VAR_246 => &[spec!((V4, V8), "read_char", true, false)],
+            VAR_247 => &[spec!((V4, V8), "scan_table", true, true)],
+            VAR_248 => &[spec!((V5, V8), "not", true, false)],
+            VAR_249 => &[spec!((V5, V8), "call_vn", false, false)],
+            VAR_250 => &[spec!((V5, V8), "call_vn2", false, false)],
+            VAR_251 => &[spec!((V5, V8), "tokenise", false, false)],
+            VAR_252 => &[spec!((V5, V8), "encode_text", false, false)],
+            VAR_253 => &[spec!((V5, V8), "copy_table", false, false)],
+            VAR_254 => &[spec!((V5, V8), "print_table", false, false)],
+            VAR_255 => &[spec!((V5, V8), "check_arg_count", false, true)],
+            EXT_0 => &[spec!((V5, V8), "save", true, false)],
+            EXT_1 => &[spec!((V5, V8), "restore", true, false)],
+            EXT_2 => &[spec!((V5, V8), "log_shift", true, false)],
+            EXT_3 => &[spec!((V5, V8), "art_shift", true, false)],
+            EXT_4 => &[spec!((V5, V8), "set_font", true, false)],
+            EXT_5 => &[spec!((V6, V6), "draw_picture", false, false)],
+            EXT_6 => &[spec!((V6, V6), "picture_data", false, true)],
+            EXT_7 => &[spec!((V6, V6), "erase_picture", false, false)],
+            EXT_8 => &[spec!((V6, V6), "set_margins", false, false)],
+            EXT_9 => &[spec!((V5, V8), "save_undo", true, false)],
+            EXT_10 => &[spec!((V5, V8), "restore_undo", true, false)],
+            EXT_11 => &[spec!((V5, V8), "print_unicode", false, false)],
+            EXT_12 => &[spec!((V5, V8), "check_unicode", true, false)],
+            EXT_13 => &[spec!((V5, V8), "set_true_colour", false, false)],
+            EXT_16 => &[spec!((V6, V6), "move_window", false, false)],
+            EXT_17 => &[spec!((V6, V6), "window_size", false, false)],
+            EXT_18 => &[spec!((V6, V6), "window_style", false, false)],
+            EXT_19 => &[spec!((V6, V6), "get_wind_prop", true, false)],
+            EXT_20 => &[spec!((V6, V6), "scroll_window", false, false)],
+            EXT_21 => &[spec!((V6, V6), "pop_stack", false, false)],
+            EXT_22 => &[spec!((V6, V6), "read_mouse", false, false)],
+            EXT_23 => &[spec!((V6, V6), "mouse_window", false, false)],
+            EXT_24 => &[spec!((V6, V6), "push_stack", false, true)],
+            EXT_25 => &[spec!((V6, V6), "put_wind_prop", false, false)],
+            EXT_26 => &[spec!((V6, V6), "print_form", false, false)],
+            EXT_27 => &[spec!((V6, V6), "make_menu", false, true)],
+            EXT_28 => &[spec!((V6, V6), "picture_table", false, false)],
+            EXT_29 => &[spec!((V6, V6), "buffer_screen", true, false)],
+        }
+    }
+
+    fn spec(&self, version: ZMachineVersion) -> Option<&'static ZOpcodeSpec> {
+        self.specs()
+            .iter()
+            .find(|spec| spec.versions.0 <= version && version <= spec.versions.1)
+    }
+
+    /// Is the opcode allowed in the given version?
+    pub fn is_available(&self, version: ZMachineVersion) -> bool {
+        self.spec(version).is_some()
+    }
+
+    /// Returns the first and last versions allowing the opcode.
+    pub fn versions(&self) -> (ZMachineVersion, ZMachineVersion) {
+        let specs = self.specs();
+        (specs[0].versions.0, specs[specs.len() - 1].versions.1)
+    }
+
+    /// Returns the Inform name of the opcode in the given version, if allowed.
+    pub fn mnemonic(&self, version: ZMachineVersion) -> Option<&'static str> {
+        self.spec(version).map(|spec| spec.mnemonic)
+    }
+
+    /// Does the opcode store a result in the given version (R4.6)?
+    pub fn stores(&self, version: ZMachineVersion) -> bool {
+        self.spec(version).is_some_and(|spec| spec.store)
+    }
+
+    /// Does the opcode branch on a condition in the given version (R4.7)?
+    pub fn branches(&self, version: ZMachineVersion) -> bool {
+        self.spec(version).is_some_and(|spec| spec.branch)
+    }
+
+    /// Is the opcode followed by an inline string (R4.8)?
+    ///
+    /// Only 0OP:178 `print` and 0OP:179 `print_ret` are.
+    pub fn has_text(&self) -> bool {
+        matches!(self, ZOpcode::OP0_178 | ZOpcode::OP0_179)
+    }
+
+    /// Does the opcode have 2 bytes of operand types (R4.4.3.1)?
+    ///
+    /// Only VAR:236 `call_vs2` and VAR:250 `call_vn2` do, allowing up to 8 operands.
+    pub fn has_double_operand_types(&self) -> bool {
+        matches!(self, ZOpcode::VAR_236 | ZOpcode::VAR_250)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcodes_tables() {
+        // each opcode is found under the class and number it is named after
+        let mut count = 0;
+        for class in [
+            ZOpcodeClass::Op2,
+            ZOpcodeClass::Op1,
+            ZOpcodeClass::Op0,
+            ZOpcodeClass::Var,
+            ZOpcodeClass::Ext,
+        ] {
+            let prefix = match class {
+                ZOpcodeClass::Op2 => "OP2",
+                ZOpcodeClass::Op1 => "OP1",
+                ZOpcodeClass::Op0 => "OP0",
+                ZOpcodeClass::Var => "VAR",
+                ZOpcodeClass::Ext => "EXT",
+            };
+            for number in 0..=255 {
+                if let Some(opcode) = class.opcode(number) {
+                    assert_eq!(format!("{:?}", opcode), format!("{}_{}", prefix, number));
+                    count += 1;
+                }
+            }
+        }
+        assert_eq!(count, 28 + 16 + 15 + 32 + 28);
+
+        assert_eq!(
+            ZOpcode::from_instruction(
+                &InstructionForm::Short,
+                &InstructionOperandCount::Fixed(1),
+                15,
+                V5
+            )
+            .unwrap(),
+            OP1_143
+        );
+        assert!(matches!(
+            ZOpcode::from_instruction(
+                &InstructionForm::Extended,
+                &InstructionOperandCount::Variable,
+                14,
+                V5
+            ),
+            Err(ZmError::InstructionIllegalOpcode(ZOpcodeClass::Ext, 14, V5))
+        ));
+        assert_eq!(ZOpcodeClass::Op0.to_string(), "0OP");
+    }
 }
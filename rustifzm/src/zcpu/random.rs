@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The Z-machine's random number generator (R2.4).
+///
+/// It can be either in random mode, seeded from the system clock, or in predictable mode
+/// where the same seed always yields the same sequence of numbers (useful for testing).
+pub struct ZRandom {
+    mode: ZRandomMode,
+}
+
+enum ZRandomMode {
+    /// A xorshift generator over the given non-zero state.
+    Random(u32),
+    /// R2.4 remarks: for small seeds S, the sequence 1, 2, ..., S, 1, 2, ...
+    /// is what a game tester would expect.
+    Sequential { seed: u16, next: u16 },
+    /// A xorshift generator over a state derived from the given seed.
+    Seeded(u32),
+}
+
+/// Seeds below this value select the sequential predictable mode.
+const SEQUENTIAL_SEED_LIMIT: u16 = 1000;
+
+impl Default for ZRandom {
+    fn default() -> Self {
+        let mut random = ZRandom {
+            mode: ZRandomMode::Random(1),
+        };
+        random.seed_randomly();
+        random
+    }
+}
+
+impl ZRandom {
+    /// Switch to random mode, seeded from the system clock.
+    pub fn seed_randomly(&mut self) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos() ^ (duration.as_secs() as u32))
+            .unwrap_or(0x2545_F491);
+        self.mode = ZRandomMode::Random(nanos | 1);
+    }
+
+    /// Switch to predictable mode with the given seed.
+    pub fn seed(&mut self, seed: u16) {
+        self.mode = if seed < SEQUENTIAL_SEED_LIMIT {
+            ZRandomMode::Sequential {
+                seed: seed.max(1),
+                next: 1,
+            }
+        } else {
+            ZRandomMode::Seeded(((seed as u32) << 16) | seed as u32)
+        };
+    }
+
+    /// Returns a number uniformly distributed between 1 and `range` included.
+    pub fn next_in_range(&mut self, range: u16) -> u16 {
+        debug_assert!(range > 0);
+        match &mut self.mode {
+            ZRandomMode::Random(state) | ZRandomMode::Seeded(state) => {
+                *state ^= *state << 13;
+                *state ^= *state >> 17;
+                *state ^= *state << 5;
+                (*state % range as u32) as u16 + 1
+            }
+            ZRandomMode::Sequential { seed, next } => {
+                let value = *next;
+                *next = if *next >= *seed { 1 } else { *next + 1 };
+                (value - 1) % range + 1
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    zmemory::{ZMemory, ZMemoryAddress::*},
+    ZmError, ZmResult,
+};
+
+/// R7.1.2.1.1: output stream 3 can be selected up to 16 times without being deselected.
+pub const MEMORY_STREAMS_MAX_DEPTH: usize = 16;
+
+/// The output streams of R7, except for the transcript: stream 2 is on whenever
+/// the transcripting bit of Flags 2 is set, so that the story can switch it both ways.
+///
+/// See: http://inform-fiction.org/zmachine/standards/z1point1/sect07.html
+pub struct ZOutputStreams {
+    /// Stream 1, the screen, selected by default.
+    screen: bool,
+    /// Stream 3, redirecting the output to tables: only the last one selected receives it.
+    memory: Vec<ZMemoryStream>,
+    /// Stream 4, the record of the player's input.
+    commands: bool,
+}
+
+impl Default for ZOutputStreams {
+    fn default() -> Self {
+        ZOutputStreams {
+            screen: true,
+            memory: vec![],
+            commands: false,
+        }
+    }
+}
+
+impl ZOutputStreams {
+    pub fn is_screen_selected(&self) -> bool {
+        self.screen
+    }
+
+    pub fn set_screen_selected(&mut self, selected: bool) {
+        self.screen = selected;
+    }
+
+    pub fn is_commands_selected(&self) -> bool {
+        self.commands
+    }
+
+    pub fn set_commands_selected(&mut self, selected: bool) {
+        self.commands = selected;
+    }
+
+    /// The table receiving the output while stream 3 is selected, if it is.
+    pub fn get_memory_stream_mut(&mut self) -> Option<&mut ZMemoryStream> {
+        self.memory.last_mut()
+    }
+
+    /// Redirect the output to the given table, until the matching `close_memory_stream`.
+    pub fn open_memory_stream(&mut self, table: u16, width: Option<u16>) -> ZmResult<()> {
+        if self.memory.len() >= MEMORY_STREAMS_MAX_DEPTH {
+            return Err(ZmError::CpuOutputStreamOverflow(MEMORY_STREAMS_MAX_DEPTH));
+        }
+        self.memory.push(ZMemoryStream::new(table, width));
+        Ok(())
+    }
+
+    /// Deselect the last table selected, going back to the previous one if any.
+    pub fn close_memory_stream(&mut self) -> Option<ZMemoryStream> {
+        self.memory.pop()
+    }
+}
+
+/// A table receiving the output of stream 3 (R7.1.2.1).
+///
+/// The table's first word holds the number of ZSCII characters written after it,
+/// which is only set once the stream is closed.
+///
+/// (V6) If given a width in units, the text is instead word-wrapped to that width:
+/// each line is then written as the number of its characters followed by them,
+/// and a zero word ends the table.
+pub struct ZMemoryStream {
+    table: u16,
+    /// Number of characters written so far.
+    length: u16,
+    width: Option<u16>,
+    /// (V6) The text to word-wrap once the stream is closed.
+    text: Vec<u16>,
+}
+
+impl ZMemoryStream {
+    fn new(table: u16, width: Option<u16>) -> Self {
+        ZMemoryStream {
+            table,
+            length: 0,
+            width,
+            text: vec![],
+        }
+    }
+
+    /// Write a ZSCII character, new-lines being written as 13 (R7.1.2.1).
+    pub fn write(&mut self, memory: &mut ZMemory, code: u16) -> ZmResult<()> {
+        if self.width.is_some() {
+            self.text.push(code);
+            return Ok(());
+        }
+        let address = self.table.wrapping_add(2).wrapping_add(self.length);
+        memory.store_byte(Byte(address), code as u8)?;
+        self.length = self.length.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Complete the table, returning the width of the text written in units,
+    /// from the width of each character.
+    pub fn close(self, memory: &mut ZMemory, character_width: u16) -> ZmResult<u16> {
+        let width = match self.width {
+            Some(width) => width,
+            None => {
+                memory.store_word(Word(self.table), self.length)?;
+                return Ok(self.length.wrapping_mul(character_width));
+            }
+        };
+        let lines = Self::wrapped(&self.text, (width / character_width.max(1)).max(1) as usize);
+        let mut address = self.table;
+        for line in &lines {
+            memory.store_word(Word(address), line.len() as u16)?;
+            address = address.wrapping_add(2);
+            for &code in line {
+                memory.store_byte(Byte(address), code as u8)?;
+                address = address.wrapping_add(1);
+            }
+        }
+        memory.store_word(Word(address), 0)?;
+        let widest = lines.iter().map(Vec::len).max().unwrap_or(0) as u16;
+        Ok(widest.wrapping_mul(character_width))
+    }
+
+    /// Breaks the text into lines of at most the given number of characters,
+    /// at new-lines and between words if possible.
+    fn wrapped(text: &[u16], max_length: usize) -> Vec<Vec<u16>> {
+        let mut lines = vec![];
+        for paragraph in text.split(|&code| code == 13) {
+            let mut line: Vec<u16> = vec![];
+            for word in paragraph.split_inclusive(|&code| code == 32) {
+                if !line.is_empty() && line.len() + word.len() > max_length {
+                    // the space ending the line is dropped
+                    if line.last() == Some(&32) {
+                        line.pop();
+                    }
+                    lines.push(std::mem::take(&mut line));
+                }
+                for chunk in word.chunks(max_length) {
+                    if line.len() + chunk.len() > max_length {
+                        lines.push(std::mem::take(&mut line));
+                    }
+                    line.extend_from_slice(chunk);
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_memory() -> ZMemory {
+        let mut memory = ZMemory::from_story_reader(&mut &[0u8; 0x100][..]).unwrap();
+        memory.set_regions(0x80, 0x80);
+        memory
+    }
+
+    fn write_text(stream: &mut ZMemoryStream, memory: &mut ZMemory, text: &[u8]) {
+        for &code in text {
+            stream.write(memory, code as u16).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_nested_memory_streams() {
+        let mut memory = init_memory();
+        let mut streams = ZOutputStreams::default();
+        streams.open_memory_stream(0x10, None).unwrap();
+        write_text(streams.get_memory_stream_mut().unwrap(), &mut memory, b"ab");
+        streams.open_memory_stream(0x40, None).unwrap();
+        write_text(
+            streams.get_memory_stream_mut().unwrap(),
+            &mut memory,
+            b"xyz",
+        );
+        let stream = streams.close_memory_stream().unwrap();
+        assert_eq!(stream.close(&mut memory, 1).unwrap(), 3);
+        write_text(
+            streams.get_memory_stream_mut().unwrap(),
+            &mut memory,
+            b"c\x0d",
+        );
+        let stream = streams.close_memory_stream().unwrap();
+        assert_eq!(stream.close(&mut memory, 2).unwrap(), 8);
+        assert!(streams.close_memory_stream().is_none());
+
+        assert_eq!(memory.read_word(Word(0x10)).unwrap(), 4);
+        assert_eq!(memory.read_word(High(0x12)).unwrap(), 0x6162);
+        assert_eq!(memory.read_word(High(0x14)).unwrap(), 0x630D);
+        assert_eq!(memory.read_word(Word(0x40)).unwrap(), 3);
+        assert_eq!(memory.read_byte(Byte(0x44)).unwrap(), b'z');
+
+        for _ in 0..MEMORY_STREAMS_MAX_DEPTH {
+            streams.open_memory_stream(0x10, None).unwrap();
+        }
+        assert!(matches!(
+            streams.open_memory_stream(0x10, None),
+            Err(ZmError::CpuOutputStreamOverflow(16))
+        ));
+    }
+
+    #[test]
+    fn test_wrapped_memory_stream() {
+        let mut memory = init_memory();
+        let mut stream = ZMemoryStream::new(0x10, Some(12));
+        write_text(&mut stream, &mut memory, b"the lamp is lit\x0dok");
+        // 2 units per character: 6 characters per line
+        assert_eq!(stream.close(&mut memory, 2).unwrap(), 12);
+        let table = (0x10..0x29)
+            .map(|address| memory.read_byte(Byte(address)).unwrap())
+            .collect::<Vec<u8>>();
+        assert_eq!(
+            table,
+            b"\0\x03the\0\x04lamp\0\x06is lit\0\x02ok\0\0".to_vec()
+        );
+        assert_eq!(
+            ZMemoryStream::wrapped(b"abcdefgh".map(u16::from).as_ref(), 3),
+            vec![vec![97, 98, 99], vec![100, 101, 102], vec![103, 104]]
+        );
+    }
+}
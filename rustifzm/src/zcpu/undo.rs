@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use super::{frame::ZCallFrame, instructions::Operation, ZPendingRequest};
+
+/// Default number of undo states kept.
+pub const DEFAULT_UNDO_CAPACITY: usize = 16;
+
+/// A snapshot of the machine, to go back to with `restore_undo` or the host's undo.
+pub struct ZUndoState {
+    /// Dynamic memory, as a diff against the original story file (see `ZQuetzal::compressed`).
+    pub memory: Vec<u8>,
+    pub pc: u32,
+    pub frames: Vec<ZCallFrame>,
+    /// The instruction waiting for the host when the snapshot was taken, if any.
+    pub request: Option<ZPendingRequest>,
+    /// The `save_undo` instruction which took the snapshot, if any:
+    /// R15 restore_undo, it then stores 2 once restored.
+    pub save_undo: Option<Operation>,
+}
+
+/// The most recent undo states, the oldest being discarded once the capacity is reached.
+pub struct ZUndoRing {
+    capacity: usize,
+    states: VecDeque<ZUndoState>,
+}
+
+impl ZUndoRing {
+    pub fn new(capacity: usize) -> Self {
+        ZUndoRing {
+            capacity,
+            states: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Set the number of states kept, discarding the oldest ones if needed.
+    ///
+    /// A capacity of 0 disables undo.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.states.len() > capacity {
+            self.states.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Keep the given state, unless undo is disabled.
+    pub fn push(&mut self, state: ZUndoState) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back(state);
+        true
+    }
+
+    /// Take the most recent state.
+    pub fn pop(&mut self) -> Option<ZUndoState> {
+        self.states.pop_back()
+    }
+}
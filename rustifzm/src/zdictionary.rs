@@ -0,0 +1,419 @@
+use std::cmp::Ordering;
+
+use crate::{
+    zmachine::ZMachineHeader,
+    zmemory::{ZMemory, ZMemoryAddress, ZMemoryAddress::*},
+    zstring::{ZString, ZStringCodec},
+    ZMachineVersion, ZmError, ZmResult,
+};
+
+/// A dictionary table, usually held in static memory.
+///
+/// It starts with the list of word-separator characters, followed by the length of each entry
+/// and the number of entries. Each entry holds the encoded text of a word, followed by
+/// game-specific data bytes.
+/// The main dictionary's address is stored at 0x08 in the header, but games can supply their own
+/// dictionaries to `tokenise`, whose entry count may be negative to mark them as unsorted.
+///
+/// See: http://inform-fiction.org/zmachine/standards/z1point1/sect13.html
+pub struct ZDictionary {
+    /// Address of the table, starting with the word-separators count (R13.2).
+    address: ZMemoryAddress,
+    /// The word-separators, as ZSCII codes (R13.2).
+    separators: Vec<u16>,
+    /// Length of each entry in bytes, including the encoded text (R13.3).
+    entry_length: u8,
+    /// Number of entries, negative when they are not sorted (R13.3, S13).
+    entries_count: i16,
+    /// Address of the first entry.
+    entries_address: u16,
+    /// The targeted Z-machine version, which determines the length of the encoded text.
+    version: ZMachineVersion,
+}
+
+impl ZDictionary {
+    /// Parse the main dictionary, whose address is stored in the header.
+    pub fn from_memory_and_header(memory: &ZMemory, header: &ZMachineHeader) -> ZmResult<Self> {
+        Self::from_memory_and_address(
+            memory,
+            header.get_location_dictionary(),
+            header.get_version(),
+        )
+    }
+
+    /// Parse the dictionary table stored at the given address, such as a user dictionary.
+    pub fn from_memory_and_address(
+        memory: &ZMemory,
+        address: ZMemoryAddress,
+        version: ZMachineVersion,
+    ) -> ZmResult<Self> {
+        let start = address.as_byte()?;
+        let separators_count = memory.read_byte(Byte(start))?;
+        let separators = (1..=separators_count as u16)
+            .map(|offset| Ok(memory.read_byte(address.offset_byte(offset)?)? as u16))
+            .collect::<ZmResult<Vec<_>>>()?;
+        let entry_length_address = address.offset_byte(separators_count as u16 + 1)?;
+        let entry_length = memory.read_byte(entry_length_address)?;
+        // R13.3: the entry must at least hold the encoded text
+        if (entry_length as usize) < ZString::dictionary_length(version) {
+            return Err(ZmError::DictionaryInvalidEntryLength(entry_length));
+        }
+        let entries_count =
+            memory.read_word(Word(entry_length_address.as_byte()?.wrapping_add(1)))? as i16;
+        let entries_address = entry_length_address.as_byte()?.wrapping_add(3);
+        let dictionary = Self {
+            address,
+            separators,
+            entry_length,
+            entries_count,
+            entries_address,
+            version,
+        };
+        // make sure the whole table is addressable
+        if let Some(last) = dictionary.get_entries_count().checked_sub(1) {
+            let end_address = dictionary
+                .entry_address(last)?
+                .checked_add(entry_length as u16 - 1)
+                .ok_or(ZmError::DictionaryInvalidEntry(last))?;
+            memory.read_byte(Byte(end_address))?;
+        }
+        Ok(dictionary)
+    }
+
+    pub fn get_address(&self) -> ZMemoryAddress {
+        self.address
+    }
+
+    /// Returns the word-separators, as ZSCII codes.
+    pub fn get_separators(&self) -> &[u16] {
+        &self.separators
+    }
+
+    pub fn is_separator(&self, code: u16) -> bool {
+        self.separators.contains(&code)
+    }
+
+    pub fn get_entry_length(&self) -> u8 {
+        self.entry_length
+    }
+
+    /// Returns the number of data bytes following the encoded text in each entry.
+    pub fn get_data_length(&self) -> u8 {
+        self.entry_length - ZString::dictionary_length(self.version) as u8
+    }
+
+    pub fn get_entries_count(&self) -> u16 {
+        self.entries_count.unsigned_abs()
+    }
+
+    /// Are the entries sorted in numerical order of their encoded text?
+    ///
+    /// The main dictionary always is, but user dictionaries with a negative count are not (S13).
+    pub fn is_sorted(&self) -> bool {
+        self.entries_count >= 0
+    }
+
+    fn entry_address(&self, index: u16) -> ZmResult<u16> {
+        if index >= self.get_entries_count() {
+            return Err(ZmError::DictionaryInvalidEntry(index));
+        }
+        let address = self.entries_address as u32 + index as u32 * self.entry_length as u32;
+        u16::try_from(address).map_err(|_| ZmError::DictionaryInvalidEntry(index))
+    }
+
+    /// Returns a snapshot of the entry at the given index, starting at 0.
+    pub fn get_entry(&self, memory: &ZMemory, index: u16) -> ZmResult<ZDictionaryEntry> {
+        let address = self.entry_address(index)?;
+        let bytes = (0..self.entry_length as u16)
+            .map(|offset| memory.read_byte(Byte(address + offset)))
+            .collect::<ZmResult<Vec<_>>>()?;
+        let text_length = ZString::dictionary_length(self.version);
+        Ok(ZDictionaryEntry {
+            address,
+            text: bytes[..text_length].to_vec(),
+            data: bytes[text_length..].to_vec(),
+        })
+    }
+
+    /// Look up the given word, encoded in dictionary form (see `ZString::from_zscii`),
+    /// returning the address of its entry if found.
+    ///
+    /// Sorted dictionaries are searched with a binary search, the others linearly.
+    pub fn find(&self, memory: &ZMemory, word: &ZString) -> ZmResult<Option<u16>> {
+        if self.is_sorted() {
+            let (mut low, mut high) = (0, self.get_entries_count());
+            while low < high {
+                let middle = low + (high - low) / 2;
+                let address = self.entry_address(middle)?;
+                match word.compare_encoded(memory, Byte(address))? {
+                    Ordering::Equal => return Ok(Some(address)),
+                    Ordering::Less => high = middle,
+                    Ordering::Greater => low = middle + 1,
+                }
+            }
+        } else {
+            for index in 0..self.get_entries_count() {
+                let address = self.entry_address(index)?;
+                if word.compare_encoded(memory, Byte(address))? == Ordering::Equal {
+                    return Ok(Some(address));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Encode the given ZSCII codes and look them up, as the `read` and `tokenise` opcodes do.
+    pub fn find_zscii(
+        &self,
+        memory: &ZMemory,
+        codes: &[u16],
+        codec: &ZStringCodec,
+    ) -> ZmResult<Option<u16>> {
+        self.find(memory, &ZString::from_zscii(codes, codec))
+    }
+
+    /// Split the given ZSCII text into words, and look each of them up (R13.6).
+    ///
+    /// Words are delimited by spaces, which are discarded, and by word-separators,
+    /// which are words in their own right.
+    pub fn tokenise(
+        &self,
+        memory: &ZMemory,
+        text: &[u16],
+        codec: &ZStringCodec,
+    ) -> ZmResult<Vec<ZDictionaryWord>> {
+        let mut bounds = vec![];
+        let mut start = None;
+        for (position, &code) in text.iter().enumerate() {
+            let separator = self.is_separator(code);
+            if code == b' ' as u16 || separator {
+                if let Some(start) = start.take() {
+                    bounds.push((start, position));
+                }
+                if separator {
+                    bounds.push((position, position + 1));
+                }
+            } else if start.is_none() {
+                start = Some(position);
+            }
+        }
+        if let Some(start) = start {
+            bounds.push((start, text.len()));
+        }
+        bounds
+            .into_iter()
+            .map(|(start, end)| {
+                Ok(ZDictionaryWord {
+                    position: start,
+                    length: end - start,
+                    entry: self.find_zscii(memory, &text[start..end], codec)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A word found by the lexical analysis of some text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZDictionaryWord {
+    /// Position of the word's first character in the text.
+    position: usize,
+    /// Number of characters in the word.
+    length: usize,
+    /// Address of the word's dictionary entry, if any.
+    entry: Option<u16>,
+}
+
+impl ZDictionaryWord {
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+
+    pub fn get_entry(&self) -> Option<u16> {
+        self.entry
+    }
+}
+
+/// A snapshot of a dictionary entry.
+pub struct ZDictionaryEntry {
+    /// Address of the entry, as returned to the game by the lexical analysis.
+    address: u16,
+    /// The encoded text of the word: 4 bytes from V1 to V3, 6 in V4+ (R13.4).
+    text: Vec<u8>,
+    /// The game-specific data bytes following the text (R13.5).
+    data: Vec<u8>,
+}
+
+impl ZDictionaryEntry {
+    pub fn get_address(&self) -> u16 {
+        self.address
+    }
+
+    pub fn get_encoded_text(&self) -> &[u8] {
+        &self.text
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decode the text of the word into UTF-8.
+    pub fn decode_text(&self, memory: &ZMemory, codec: &ZStringCodec) -> ZmResult<String> {
+        ZString::new(memory, Byte(self.address))?.decode(memory, codec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DICTIONARY_ADDRESS: u16 = 0x100;
+
+    /// Build a V3 story whose dictionary holds the given words, with 2 data bytes per entry.
+    fn init_dictionary_and_memory(words: &[&str], sorted: bool) -> (ZDictionary, ZMemory) {
+        let mut story = vec![0u8; 0x200];
+        story[0x00] = 3;
+        story[0x08] = (DICTIONARY_ADDRESS >> 8) as u8;
+        story[0x09] = DICTIONARY_ADDRESS as u8;
+        let memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let codec = ZStringCodec::from_memory_and_header(&memory, &header).unwrap();
+
+        let mut table = vec![3, b'.', b',', b'"', 6];
+        let count = words.len() as i16 * if sorted { 1 } else { -1 };
+        table.extend_from_slice(&count.to_be_bytes());
+        for (index, word) in words.iter().enumerate() {
+            let codes: Vec<u16> = word.bytes().map(|b| b as u16).collect();
+            table.extend(ZString::from_zscii(&codes, &codec).to_bytes());
+            table.extend_from_slice(&[index as u8, 0xFF]);
+        }
+        let start = DICTIONARY_ADDRESS as usize;
+        story[start..start + table.len()].copy_from_slice(&table);
+        let memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        let dictionary = ZDictionary::from_memory_and_header(&memory, &header).unwrap();
+        (dictionary, memory)
+    }
+
+    fn codec(memory: &ZMemory) -> ZStringCodec {
+        let header = ZMachineHeader::from_memory(memory).unwrap();
+        ZStringCodec::from_memory_and_header(memory, &header).unwrap()
+    }
+
+    #[test]
+    fn test_table_layout() {
+        let (dictionary, memory) = init_dictionary_and_memory(&["lamp", "north"], true);
+        assert_eq!(
+            dictionary.get_separators(),
+            &[b'.' as u16, b',' as u16, b'"' as u16]
+        );
+        assert!(dictionary.is_separator(b',' as u16));
+        assert!(!dictionary.is_separator(b' ' as u16));
+        assert_eq!(dictionary.get_entry_length(), 6);
+        assert_eq!(dictionary.get_data_length(), 2);
+        assert_eq!(dictionary.get_entries_count(), 2);
+        assert!(dictionary.is_sorted());
+
+        let entry = dictionary.get_entry(&memory, 1).unwrap();
+        assert_eq!(entry.get_address(), DICTIONARY_ADDRESS + 7 + 6);
+        assert_eq!(entry.get_encoded_text().len(), 4);
+        assert_eq!(entry.get_data(), &[1, 0xFF]);
+        assert_eq!(
+            entry.decode_text(&memory, &codec(&memory)).unwrap(),
+            "north"
+        );
+        assert!(matches!(
+            dictionary.get_entry(&memory, 2),
+            Err(ZmError::DictionaryInvalidEntry(2))
+        ));
+
+        // a user dictionary whose only entry, of 255 bytes, starts at 0xFFFC
+        let mut story = vec![0u8; 0x10000];
+        story[0x00] = 5;
+        story[0xFFF8..0xFFFC].copy_from_slice(&[0, 0xFF, 0x00, 0x01]);
+        let memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        assert!(matches!(
+            ZDictionary::from_memory_and_address(&memory, Byte(0xFFF8), ZMachineVersion::V5),
+            Err(ZmError::DictionaryInvalidEntry(0))
+        ));
+    }
+
+    #[test]
+    fn test_sorted_lookup() {
+        let words = [
+            "a", "door", "east", "lamp", "lantern", "north", "take", "zork",
+        ];
+        let (dictionary, memory) = init_dictionary_and_memory(&words, true);
+        let codec = codec(&memory);
+        for (index, word) in words.iter().enumerate() {
+            let codes: Vec<u16> = word.bytes().map(|b| b as u16).collect();
+            let address = dictionary.find_zscii(&memory, &codes, &codec).unwrap();
+            assert_eq!(address, Some(DICTIONARY_ADDRESS + 7 + 6 * index as u16));
+        }
+        // truncated to 6 Z-characters, like the dictionary entries
+        let codes: Vec<u16> = "doorway".bytes().map(|b| b as u16).collect();
+        assert_eq!(
+            dictionary.find_zscii(&memory, &codes, &codec).unwrap(),
+            None
+        );
+        let codes: Vec<u16> = "lanterns".bytes().map(|b| b as u16).collect();
+        assert_eq!(
+            dictionary.find_zscii(&memory, &codes, &codec).unwrap(),
+            Some(DICTIONARY_ADDRESS + 7 + 6 * 4)
+        );
+        let codes: Vec<u16> = "xyzzy".bytes().map(|b| b as u16).collect();
+        assert_eq!(
+            dictionary.find_zscii(&memory, &codes, &codec).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unsorted_lookup() {
+        let words = ["zork", "take", "lamp", "door"];
+        let (dictionary, memory) = init_dictionary_and_memory(&words, false);
+        assert!(!dictionary.is_sorted());
+        assert_eq!(dictionary.get_entries_count(), 4);
+        let codec = codec(&memory);
+        let codes: Vec<u16> = "door".bytes().map(|b| b as u16).collect();
+        assert_eq!(
+            dictionary.find_zscii(&memory, &codes, &codec).unwrap(),
+            Some(DICTIONARY_ADDRESS + 7 + 6 * 3)
+        );
+        let codes: Vec<u16> = "east".bytes().map(|b| b as u16).collect();
+        assert_eq!(
+            dictionary.find_zscii(&memory, &codes, &codec).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_tokenise() {
+        let (dictionary, memory) = init_dictionary_and_memory(&[",", "lamp", "take"], true);
+        let codec = codec(&memory);
+        let text: Vec<u16> = " take lamp,xyzzy ".bytes().map(|b| b as u16).collect();
+        let words: Vec<_> = dictionary
+            .tokenise(&memory, &text, &codec)
+            .unwrap()
+            .into_iter()
+            .map(|word| (word.get_position(), word.get_length(), word.get_entry()))
+            .collect();
+        let entry = |index: u16| Some(DICTIONARY_ADDRESS + 7 + 6 * index);
+        assert_eq!(
+            words,
+            [
+                (1, 4, entry(2)),
+                (6, 4, entry(1)),
+                (10, 1, entry(0)),
+                (11, 5, None)
+            ]
+        );
+        assert!(dictionary
+            .tokenise(&memory, &[], &codec)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+/// The screen and speaker of the Z-machine, as provided by a frontend.
+///
+/// The interpreter core performs all of its output through this trait, so that terminal clients,
+/// test harnesses and other embedders can each supply their own. Keyboard input is instead
+/// requested by `ZMachine::run`, so that the host never has to block inside an opcode.
+/// Only text output is required: the other features default to doing nothing,
+/// which the Standard allows for interpreters lacking them (see R8).
+///
+/// Lines, columns and windows follow the Standard's conventions: the cursor position
+/// is 1-based with (1, 1) being the top left, and window 0 is the lower one.
+///
+/// See: http://inform-fiction.org/zmachine/standards/z1point1/sect08.html
+pub trait ZIoSystem {
+    /// Print the given text in the current window, with the current style and colours.
+    ///
+    /// Only called while output stream 1 is selected, and stream 3 is not (R7.1.2.2).
+    fn print(&mut self, text: &str);
+
+    /// Append the given text to the transcript, while output stream 2 is selected (R7.1.2.2).
+    ///
+    /// It receives the text of the lower window along with the player's input lines,
+    /// and is usually written to a file or a printer.
+    fn print_transcript(&mut self, _text: &str) {}
+
+    /// Record the given line of the player's input, while output stream 4 is selected (R7.1.2.4).
+    fn record_input(&mut self, _input: &str) {}
+
+    /// (V1 to V3) Display the status line (R8.2).
+    fn show_status(&mut self, _status: &ZStatusLine) {}
+
+    /// Set the text style (R8.7.1.1): roman turns all styles off,
+    /// while the others may be combined with the current ones.
+    fn set_text_style(&mut self, _style: ZTextStyle) {}
+
+    /// (V4+) Should the output of the lower window be buffered, to be word-wrapped?
+    fn set_buffer_mode(&mut self, _buffered: bool) {}
+
+    /// (V3+) Split the screen so that the upper window has the given number of lines,
+    /// 0 meaning unsplit (R8.7.2.1).
+    fn split_window(&mut self, _lines: u16) {}
+
+    /// (V3+) Select the window receiving the output.
+    fn set_window(&mut self, _window: ZWindow) {}
+
+    /// (V4+) Clear the given window with the current background colour (R8.7.3.3).
+    ///
+    /// -1 unsplits the screen and clears it all, while -2 clears it all but keeps the split.
+    fn erase_window(&mut self, _window: i16) {}
+
+    /// (V4+) Erase from the cursor to the end of its line in the current window.
+    fn erase_line(&mut self) {}
+
+    /// (V4+) Move the cursor of the current window to the given line and column.
+    fn set_cursor(&mut self, _line: u16, _column: u16) {}
+
+    /// (V4+) Returns the line and column of the cursor in the current window.
+    fn get_cursor(&mut self) -> (u16, u16) {
+        (1, 1)
+    }
+
+    /// (V5+) Set the foreground and background colours of the text printed from now on.
+    fn set_colour(&mut self, _foreground: ZColor, _background: ZColor) {}
+
+    /// (V5+) Select the given font, returning whether it is available (R8.1.3).
+    fn set_font(&mut self, font: ZFont) -> bool {
+        font == ZFont::Normal
+    }
+
+    /// (V5+) Can the given character be printed and typed in, respectively?
+    fn check_unicode(&self, _character: char) -> (bool, bool) {
+        (true, true)
+    }
+
+    /// (V3+) Play, prepare, stop or unload the given sound effect (R9).
+    ///
+    /// Sounds 1 and 2 are the high and low bleeps, which interpreters should always provide.
+    fn sound_effect(&mut self, _number: u16, _effect: u16, _volume: u16) {}
+
+    /// The story has quit: the frontend may release its resources.
+    fn quit(&mut self) {}
+}
+
+bitflags! {
+    /// The text styles of R8.7.1.1, as given to the `set_text_style` opcode.
+    pub struct ZTextStyle: u16 {
+        const ROMAN = 0;
+        const REVERSE_VIDEO = 0b_0001;
+        const BOLD = 0b_0010;
+        const ITALIC = 0b_0100;
+        const FIXED_PITCH = 0b_1000;
+    }
+}
+
+/// The windows of V3 to V5 and V7/V8 (R8.6, R8.7).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZWindow {
+    /// Window 0, where the story's text normally goes.
+    Lower,
+    /// Window 1, often used for status lines and quotation boxes.
+    Upper,
+}
+
+/// The colours of R8.3.1, as given to the `set_colour` opcode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZColor {
+    Current,
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    /// (Amiga interpreters only)
+    LightGrey,
+    MediumGrey,
+    DarkGrey,
+    /// (V6) The colour of the pixel under the cursor.
+    UnderCursor,
+    Unknown(u16),
+}
+
+impl From<u16> for ZColor {
+    fn from(value: u16) -> Self {
+        use ZColor::*;
+        match value {
+            0 => Current,
+            1 => Default,
+            2 => Black,
+            3 => Red,
+            4 => Green,
+            5 => Yellow,
+            6 => Blue,
+            7 => Magenta,
+            8 => Cyan,
+            9 => White,
+            10 => LightGrey,
+            11 => MediumGrey,
+            12 => DarkGrey,
+            0xFFFF => UnderCursor,
+            _ => Unknown(value),
+        }
+    }
+}
+
+/// The fonts of R8.1.3, as given to the `set_font` opcode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZFont {
+    Normal,
+    /// Unused by any known story.
+    Picture,
+    CharacterGraphics,
+    FixedPitch,
+    Unknown(u16),
+}
+
+impl From<u16> for ZFont {
+    fn from(value: u16) -> Self {
+        use ZFont::*;
+        match value {
+            1 => Normal,
+            2 => Picture,
+            3 => CharacterGraphics,
+            4 => FixedPitch,
+            _ => Unknown(value),
+        }
+    }
+}
+
+impl From<ZFont> for u16 {
+    fn from(font: ZFont) -> Self {
+        use ZFont::*;
+        match font {
+            Normal => 1,
+            Picture => 2,
+            CharacterGraphics => 3,
+            FixedPitch => 4,
+            Unknown(value) => value,
+        }
+    }
+}
+
+/// The contents of the V1 to V3 status line (R8.2.2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZStatusLine {
+    /// Short name of the object in global variable 0, usually the player's location.
+    pub location: String,
+    /// The story's progress, read from global variables 1 and 2.
+    pub progress: ZStatusProgress,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZStatusProgress {
+    /// The signed score and number of turns.
+    Score { score: i16, turns: u16 },
+    /// The time of day, for stories setting bit 1 of Flags 1 (R8.2.3.2).
+    Time { hours: u16, minutes: u16 },
+}
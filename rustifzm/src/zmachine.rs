@@ -1,9 +1,22 @@
+pub mod capabilities;
+pub mod event;
 pub mod header;
+pub mod quetzal;
+pub mod validation;
 
 use std::io::Read;
 
-use crate::{zcpu::ZCpu, zmemory::ZMemory, ZmError, ZmResult};
-pub use header::{ZMachineHeader, ZMachineVersion::*};
+use crate::{
+    zcpu::ZCpu,
+    zio::ZIoSystem,
+    zmemory::{ZMemory, ZMemoryAddress::Word},
+    ZmError, ZmResult,
+};
+pub use capabilities::ZInterpreterCapabilities;
+pub use event::{ZInput, ZMachineEvent};
+pub use header::{ZMachineHeader, ZMachineHeaderFlags2, ZMachineVersion::*};
+pub use quetzal::ZQuetzal;
+pub use validation::ZStoryValidation;
 
 /// The core of rustif's Z-machine interpreter.
 pub struct ZMachine {
@@ -13,29 +26,352 @@ pub struct ZMachine {
     header: ZMachineHeader,
     /// The virtual processing unit.
     cpu: ZCpu,
+    /// The results of the sanity checks run on the story file.
+    validation: ZStoryValidation,
+    /// The features provided by the frontend, advertised to the story in its header.
+    capabilities: ZInterpreterCapabilities,
+    /// The frontend's screen and keyboard.
+    io: Box<dyn ZIoSystem>,
 }
 
 impl ZMachine {
     /// Create a new Z-machine interpreter instance and try to load the given
-    /// binary source into memory and initialize the VM according to the parsed header data.
-    pub fn from_story_reader(reader: &mut dyn Read) -> ZmResult<Self> {
+    /// binary source into memory and initialize the VM according to the parsed header data
+    /// and the frontend's capabilities.
+    ///
+    /// All of the story's input and output then goes through the given IO system.
+    pub fn from_story_reader(
+        reader: &mut dyn Read,
+        capabilities: ZInterpreterCapabilities,
+        io: Box<dyn ZIoSystem>,
+    ) -> ZmResult<Self> {
         let mut memory = ZMemory::from_story_reader(reader)?;
+        let validation = ZStoryValidation::validate(&mut memory)?;
         let mut header = ZMachineHeader::from_memory(&memory)?;
         let version = header.get_version();
-        println!("loaded version {}", version); // TODO: use proper logging crate
-        header.reset(&mut memory)?;
-        let cpu = ZCpu::from_header(&header)?;
+        header.reset(&mut memory, &capabilities)?;
+        memory.set_regions(
+            header.get_base_static_memory().as_byte()?,
+            header.get_base_high_memory().as_byte()?,
+        );
+        let mut cpu = ZCpu::from_header(&header, &memory)?;
+        if !capabilities.undo {
+            cpu.set_undo_capacity(0);
+        }
         match version {
             V1 | V2 | V3 | V4 | V5 | V6 | V7 | V8 => Ok(ZMachine {
                 memory,
                 header,
                 cpu,
+                validation,
+                capabilities,
+                io,
             }),
             _ => Err(ZmError::MachineUnsupportedVersion(version)),
         }
     }
 
+    /// Get the decoded story header.
+    pub fn get_header(&self) -> &ZMachineHeader {
+        &self.header
+    }
+
+    /// Get the results of the sanity checks run on the story file.
+    pub fn get_validation(&self) -> &ZStoryValidation {
+        &self.validation
+    }
+
+    pub fn get_capabilities(&self) -> &ZInterpreterCapabilities {
+        &self.capabilities
+    }
+
+    /// Set the maximum number of nested routine calls allowed to the running story.
+    pub fn set_stack_depth_limit(&mut self, limit: usize) {
+        self.cpu.set_stack_depth_limit(limit);
+    }
+
+    /// Set the maximum number of undo states kept, 0 disabling undo.
+    ///
+    /// They are shared by the story's `save_undo` opcode and the host's `save_undo`.
+    /// The undo capability advertised in the header follows, so that the story
+    /// does not rely on the undo opcodes once disabled.
+    pub fn set_undo_capacity(&mut self, capacity: usize) -> ZmResult<()> {
+        self.cpu.set_undo_capacity(capacity);
+        self.capabilities.undo = capacity > 0;
+        self.header.reset(&mut self.memory, &self.capabilities)
+    }
+
+    /// Number of states `undo` can go back to.
+    pub fn get_undo_count(&self) -> usize {
+        self.cpu.get_undo_count()
+    }
+
+    /// Save the current state of the story, for a later `undo`.
+    ///
+    /// Saving it whenever the story waits for a command gives multi-level undo to any story,
+    /// including those without undo opcodes of their own. Returns false if undo is disabled.
+    pub fn save_undo(&mut self) -> bool {
+        self.cpu.save_undo_state(&self.memory)
+    }
+
+    /// Go back to the most recent undo state, returning false if there is none.
+    ///
+    /// The story then resumes exactly where the state was saved: for instance waiting
+    /// for the same command again.
+    pub fn undo(&mut self) -> ZmResult<bool> {
+        let state = match self.cpu.take_undo_state() {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        let dynamic_memory = ZQuetzal::decompressed(
+            &state.memory,
+            self.memory.get_original_dynamic_memory(),
+            self.memory.get_dynamic_memory().len(),
+        )?;
+        self.reload_dynamic_memory(&dynamic_memory)?;
+        self.cpu.resume_undo_state(&mut self.memory, state)?;
+        Ok(true)
+    }
+
+    /// Has the story quit?
+    pub fn is_halted(&self) -> bool {
+        self.cpu.is_halted()
+    }
+
+    /// Execute the next instruction, unless the story waits for the host.
     pub fn step(&mut self) -> ZmResult<()> {
-        self.cpu.step(&mut self.memory)
+        self.cpu.step(&mut self.memory, self.io.as_mut())?;
+        if self.cpu.is_restarting() {
+            self.restart()?;
+        } else if self.cpu.is_restoring_undo() {
+            self.undo()?;
+        }
+        Ok(())
+    }
+
+    /// Start the story again, as the `restart` opcode does.
+    ///
+    /// Dynamic memory is reloaded from the story file and the header set up again
+    /// for the frontend, before the execution resumes at the initial PC.
+    pub fn restart(&mut self) -> ZmResult<()> {
+        let original = self.memory.get_original_dynamic_memory().to_vec();
+        self.reload_dynamic_memory(&original)?;
+        self.cpu.restart(&self.header, &self.memory)
+    }
+
+    /// Execute instructions until the story needs the host, or until `budget` instructions
+    /// have been executed.
+    ///
+    /// Input, save and restore requests are answered with `provide_input`, after which
+    /// the execution can go on with another call.
+    pub fn run(&mut self, budget: usize) -> ZMachineEvent {
+        for _ in 0..budget {
+            if let Some(event) = self.get_waiting_event() {
+                return event;
+            }
+            if let Err(error) = self.step() {
+                return ZMachineEvent::Error(error);
+            }
+        }
+        self.get_waiting_event()
+            .unwrap_or(ZMachineEvent::BudgetExhausted)
+    }
+
+    /// Replace dynamic memory on restore, restart or undo, then set the header up again (R11.1).
+    ///
+    /// The transcripting and fixed-pitch bits of Flags 2 are kept, as the Standard requires.
+    fn reload_dynamic_memory(&mut self, bytes: &[u8]) -> ZmResult<()> {
+        let kept = ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING
+            | ZMachineHeaderFlags2::FORCE_PRINTING_FIXED_PITCH;
+        let flags2 = self.memory.read_word(Word(0x10))? & kept.bits();
+        self.memory.write_dynamic_memory(bytes)?;
+        let reloaded_flags2 = self.memory.read_word(Word(0x10))? & !kept.bits();
+        self.memory
+            .write_word(Word(0x10), reloaded_flags2 | flags2)?;
+        self.header.reset(&mut self.memory, &self.capabilities)
+    }
+
+    fn get_waiting_event(&self) -> Option<ZMachineEvent> {
+        if self.cpu.is_halted() {
+            Some(ZMachineEvent::Quit)
+        } else {
+            self.cpu.get_waiting_event()
+        }
+    }
+
+    /// Answer the event returned by `run`, completing the instruction waiting for it.
+    pub fn provide_input(&mut self, input: ZInput) -> ZmResult<()> {
+        self.cpu
+            .provide_input(&mut self.memory, self.io.as_mut(), input)
+    }
+
+    /// Returns the game to save as a Quetzal file, after `run` returned `SaveRequested`.
+    ///
+    /// The host then tells the story whether it could write the file with `ZInput::Saved`.
+    pub fn save(&self) -> ZmResult<Vec<u8>> {
+        let pc = self
+            .cpu
+            .get_save_pc()
+            .ok_or(ZmError::MachineSaveNotRequested)?;
+        let quetzal = ZQuetzal::from_state(&self.memory, pc, self.cpu.get_frames())?;
+        Ok(quetzal.to_bytes(&self.memory))
+    }
+
+    /// Restore a game saved as a Quetzal file, usually after `run` returned `RestoreRequested`.
+    ///
+    /// The execution resumes from the `save` instruction which saved the game, replacing any
+    /// request the story was waiting on. Nothing is changed if the file cannot be restored,
+    /// for instance with `ZmError::QuetzalStoryMismatch` if it was saved from another story.
+    pub fn restore(&mut self, bytes: &[u8]) -> ZmResult<()> {
+        let quetzal = ZQuetzal::from_bytes(bytes, &self.memory)?;
+        if !quetzal.is_from_story(&self.header) {
+            return Err(ZmError::QuetzalStoryMismatch);
+        }
+        let save = self.cpu.decoded_restored_save(
+            &self.memory,
+            quetzal.get_dynamic_memory(),
+            quetzal.get_pc(),
+            quetzal.get_frames(),
+        )?;
+        self.reload_dynamic_memory(quetzal.get_dynamic_memory())?;
+        self.cpu.restore(
+            &mut self.memory,
+            &save,
+            quetzal.get_pc(),
+            quetzal.get_frames().to_vec(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zmemory::ZMemoryAddress::Byte;
+
+    struct NullIo;
+
+    impl ZIoSystem for NullIo {
+        fn print(&mut self, _text: &str) {}
+    }
+
+    /// A V5 story incrementing G00, then restarting from two nested calls.
+    fn init_zmachine() -> ZMachine {
+        let mut story = vec![0u8; 0x400];
+        story[0x00] = 5;
+        story[0x04] = 0x03; // high memory at 0x300
+        story[0x06] = 0x02; // initial PC = 0x200
+        story[0x0D] = 0x40; // globals at 0x40
+        story[0x0E] = 0x03; // static memory at 0x300
+                            // inc G00 ; call_vn 0x300
+        story[0x200..0x206].copy_from_slice(&[0x95, 0x10, 0xF9, 0x3F, 0x00, 0xC0]);
+        // call_vn 0x310
+        story[0x300..0x305].copy_from_slice(&[0x00, 0xF9, 0x3F, 0x00, 0xC4]);
+        // restart
+        story[0x310..0x312].copy_from_slice(&[0x00, 0xB7]);
+        ZMachine::from_story_reader(
+            &mut &story[..],
+            ZInterpreterCapabilities::default(),
+            Box::new(NullIo),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut zmachine = init_zmachine();
+        for _ in 0..3 {
+            zmachine.step().unwrap();
+        }
+        assert_eq!(zmachine.memory.read_word(Word(0x40)).unwrap(), 1);
+        assert_eq!(zmachine.cpu.get_frames().len(), 3);
+        // the player turned transcripting on, and the story asked for colours
+        let flags2 = ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING
+            | ZMachineHeaderFlags2::FORCE_PRINTING_FIXED_PITCH
+            | ZMachineHeaderFlags2::REQUEST_COLORS;
+        zmachine
+            .memory
+            .write_word(Word(0x10), flags2.bits())
+            .unwrap();
+        zmachine.memory.write_byte(Byte(0x21), 0).unwrap();
+
+        zmachine.step().unwrap();
+        assert!(!zmachine.cpu.is_restarting());
+        assert_eq!(zmachine.cpu.get_frames().len(), 1);
+        assert_eq!(zmachine.memory.read_word(Word(0x40)).unwrap(), 0);
+        assert_eq!(
+            zmachine.memory.read_word(Word(0x10)).unwrap(),
+            (flags2 - ZMachineHeaderFlags2::REQUEST_COLORS).bits()
+        );
+        assert_eq!(zmachine.memory.read_byte(Byte(0x21)).unwrap(), 80);
+        zmachine.step().unwrap();
+        assert_eq!(zmachine.memory.read_word(Word(0x40)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_failed_restore() {
+        let mut zmachine = init_zmachine();
+        zmachine.step().unwrap();
+        zmachine.step().unwrap();
+        let frames = zmachine.cpu.get_frames().to_vec();
+        // saved with G00 = 5, from 2 nested routines
+        zmachine.memory.write_word(Word(0x40), 5).unwrap();
+        let beyond_story = ZQuetzal::from_state(&zmachine.memory, 0x10000, &frames).unwrap();
+        let too_deep = ZQuetzal::from_state(&zmachine.memory, 0x205, &frames).unwrap();
+        zmachine.memory.write_word(Word(0x40), 1).unwrap();
+
+        assert!(zmachine
+            .restore(&beyond_story.to_bytes(&zmachine.memory))
+            .is_err());
+        zmachine.cpu.set_stack_depth_limit(1);
+        assert!(matches!(
+            zmachine.restore(&too_deep.to_bytes(&zmachine.memory)),
+            Err(ZmError::CpuStackOverflow(1))
+        ));
+        assert_eq!(zmachine.memory.read_word(Word(0x40)).unwrap(), 1);
+        assert_eq!(zmachine.cpu.get_frames(), &frames[..]);
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut zmachine = init_zmachine();
+        zmachine.step().unwrap();
+        // the state is saved with a wrong screen width, which the header's reset corrects
+        zmachine.memory.write_byte(Byte(0x21), 0).unwrap();
+        assert!(zmachine.save_undo());
+        zmachine.step().unwrap();
+        zmachine
+            .memory
+            .write_word(
+                Word(0x10),
+                ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING.bits(),
+            )
+            .unwrap();
+
+        assert!(zmachine.undo().unwrap());
+        assert_eq!(zmachine.cpu.get_frames().len(), 1);
+        assert_eq!(zmachine.memory.read_word(Word(0x40)).unwrap(), 1);
+        assert_eq!(zmachine.memory.read_byte(Byte(0x21)).unwrap(), 80);
+        assert_eq!(
+            zmachine.memory.read_word(Word(0x10)).unwrap(),
+            ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING.bits()
+        );
+        assert!(!zmachine.undo().unwrap());
+    }
+
+    #[test]
+    fn test_undo_capability() {
+        let mut zmachine = init_zmachine();
+        let requested = ZMachineHeaderFlags2::REQUEST_UNDO_OPCODES.bits();
+        zmachine.memory.write_word(Word(0x10), requested).unwrap();
+        zmachine.set_undo_capacity(0).unwrap();
+        assert!(!zmachine.get_capabilities().undo);
+        assert_eq!(zmachine.memory.read_word(Word(0x10)).unwrap(), 0);
+        assert!(!zmachine.save_undo());
+
+        zmachine.memory.write_word(Word(0x10), requested).unwrap();
+        zmachine.set_undo_capacity(4).unwrap();
+        assert!(zmachine.get_capabilities().undo);
+        assert_eq!(zmachine.memory.read_word(Word(0x10)).unwrap(), requested);
+        assert!(zmachine.save_undo());
     }
 }
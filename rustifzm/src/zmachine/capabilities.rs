@@ -0,0 +1,92 @@
+use super::header::ZInterpreterNumber;
+
+/// The features a frontend provides to the running story.
+///
+/// The interpreter advertises them in the header on load, restore and restart (see R11.1),
+/// so that games can adapt: for instance by not trying to print in italic when unsupported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZInterpreterCapabilities {
+    /// (V5+) Can text be printed in colour?
+    pub colors: bool,
+    /// (V4+) Is boldface available?
+    pub bold: bool,
+    /// (V4+) Is italic available?
+    pub italic: bool,
+    /// (V4+) Is the fixed-space style available?
+    pub fixed_space: bool,
+    /// (V6) Can pictures be displayed?
+    pub pictures: bool,
+    /// (V6) Can sound effects be played?
+    pub sounds: bool,
+    /// (V4+) Is timed keyboard input available?
+    pub timed_input: bool,
+    /// (V5+) Are the undo opcodes supported? Kept in line with `ZMachine::set_undo_capacity`.
+    pub undo: bool,
+    /// (V5+) Is a mouse available?
+    pub mouse: bool,
+    /// (V6) Are menus available?
+    pub menus: bool,
+    /// (V1 to V3) Is the status line available?
+    pub status_line: bool,
+    /// (V3) Is screen-splitting available?
+    pub screen_splitting: bool,
+    /// (V3) Is a variable-pitch font the default?
+    pub variable_pitch_font: bool,
+    /// (V4+) Screen height in lines, 255 meaning infinite.
+    pub screen_height_lines: u8,
+    /// (V4+) Screen width in characters.
+    pub screen_width_chars: u8,
+    /// (V5+) Font width in units, i.e. the width of a '0'.
+    pub font_width_units: u8,
+    /// (V5+) Font height in units.
+    pub font_height_units: u8,
+    /// (V5+) Default background colour (R8.3.1).
+    pub default_background_color: u8,
+    /// (V5+) Default foreground colour (R8.3.1).
+    pub default_foreground_color: u8,
+    /// (V4+) Interpreter number.
+    pub interpreter_number: ZInterpreterNumber,
+    /// (V4+) Interpreter version: conventionally an upper-case letter in V4 and V5.
+    pub interpreter_version: u8,
+}
+
+impl Default for ZInterpreterCapabilities {
+    /// A plain text interpreter on an 80x24 screen, using white on black.
+    fn default() -> Self {
+        Self {
+            colors: false,
+            bold: false,
+            italic: false,
+            fixed_space: false,
+            pictures: false,
+            sounds: false,
+            timed_input: false,
+            undo: true,
+            mouse: false,
+            menus: false,
+            status_line: true,
+            screen_splitting: false,
+            variable_pitch_font: false,
+            screen_height_lines: 24,
+            screen_width_chars: 80,
+            font_width_units: 1,
+            font_height_units: 1,
+            default_background_color: 2,
+            default_foreground_color: 9,
+            interpreter_number: ZInterpreterNumber::IbmPc,
+            interpreter_version: b'A',
+        }
+    }
+}
+
+impl ZInterpreterCapabilities {
+    /// Screen width in units, for a screen of fixed-pitch characters.
+    pub fn screen_width_units(&self) -> u16 {
+        self.screen_width_chars as u16 * self.font_width_units as u16
+    }
+
+    /// Screen height in units, for a screen of fixed-pitch characters.
+    pub fn screen_height_units(&self) -> u16 {
+        self.screen_height_lines as u16 * self.font_height_units as u16
+    }
+}
@@ -0,0 +1,65 @@
+use crate::ZmError;
+
+/// Why `ZMachine::run` handed control back to the host.
+#[derive(Debug)]
+pub enum ZMachineEvent {
+    /// The story waits for a line of input (`sread` or `aread`), to be given with `ZInput::Line`.
+    NeedLine {
+        /// Maximum number of characters the story's text buffer can hold.
+        max_len: usize,
+        /// (V4+) Delay in tenths of a second after which the host should answer
+        /// with `ZInput::Timeout`, if the story asked for timed input.
+        timeout: Option<u16>,
+    },
+    /// The story waits for a single key press (`read_char`), to be given with `ZInput::Char`.
+    NeedChar {
+        /// (V4+) Delay in tenths of a second after which the host should answer
+        /// with `ZInput::Timeout`, if the story asked for timed input.
+        timeout: Option<u16>,
+    },
+    /// The story asks the host to save the game, as given by `ZMachine::save`,
+    /// the outcome being given with `ZInput::Saved`.
+    SaveRequested,
+    /// The story asks the host to restore a saved game with `ZMachine::restore`,
+    /// or to answer with `ZInput::RestoreFailed`.
+    RestoreRequested,
+    /// (V5+) The story asks the host to save the given bytes to an auxiliary file,
+    /// the outcome being given with `ZInput::Saved`.
+    SaveTableRequested {
+        /// Name of the file suggested by the story, if any.
+        name: Option<String>,
+        data: Vec<u8>,
+    },
+    /// (V5+) The story asks the host to read back an auxiliary file with `ZInput::TableRestored`,
+    /// or to answer with `ZInput::RestoreFailed`.
+    RestoreTableRequested {
+        /// Name of the file suggested by the story, if any.
+        name: Option<String>,
+        /// Maximum number of bytes to restore: any further one is ignored.
+        max_len: usize,
+    },
+    /// The story has quit.
+    Quit,
+    /// The given number of instructions has been executed without any of the above happening.
+    BudgetExhausted,
+    /// The story could not be run any further.
+    Error(ZmError),
+}
+
+/// The host's answer to a `ZMachineEvent`, resuming the story's execution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZInput {
+    /// The line typed by the player, without its terminating new-line.
+    Line(String),
+    /// The key pressed by the player, as a ZSCII input code (R3.8).
+    Char(u16),
+    /// The delay given with a read request has elapsed: the story's interrupt routine is run,
+    /// after which the read either goes on or is terminated (R15 `read`).
+    Timeout,
+    /// Was the story's state saved successfully?
+    Saved(bool),
+    /// The saved state could not be restored.
+    RestoreFailed,
+    /// The contents of the auxiliary file to restore.
+    TableRestored(Vec<u8>),
+}
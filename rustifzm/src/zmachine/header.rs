@@ -1,6 +1,7 @@
 use std::convert::TryFrom;
 use std::fmt;
 
+use super::capabilities::ZInterpreterCapabilities;
 use crate::errors::{ZmError, ZmResult};
 use crate::zmemory::{ZMemory, ZMemoryAddress, ZMemoryAddress::*};
 
@@ -77,9 +78,41 @@ bitflags! {
     }
 }
 
-impl Default for ZMachineHeaderFlags1Features {
-    fn default() -> Self {
-        Self::AVAILABLE_COLORS
+impl ZMachineHeaderFlags1 {
+    /// Return the bits the interpreter sets for the given capabilities (V1 to V3).
+    pub fn from_capabilities(capabilities: &ZInterpreterCapabilities) -> Self {
+        let mut flags = Self::empty();
+        flags.set(Self::STATUS_LINE_UNAVAILABLE, !capabilities.status_line);
+        flags.set(
+            Self::SCREEN_SPLITTING_AVAILABLE,
+            capabilities.screen_splitting,
+        );
+        flags.set(
+            Self::VARIABLE_PITCH_FONT_IS_DEFAULT,
+            capabilities.variable_pitch_font,
+        );
+        flags
+    }
+}
+
+impl ZMachineHeaderFlags1Features {
+    /// Return the bits the interpreter sets for the given version and capabilities (V4+).
+    pub fn from_capabilities(
+        version: ZMachineVersion,
+        capabilities: &ZInterpreterCapabilities,
+    ) -> Self {
+        let mut flags = Self::empty();
+        flags.set(Self::AVAILABLE_COLORS, version >= V5 && capabilities.colors);
+        flags.set(
+            Self::AVAILABLE_PICTURE,
+            version >= V6 && capabilities.pictures,
+        );
+        flags.set(Self::AVAILABLE_BOLDFACE, capabilities.bold);
+        flags.set(Self::AVAILABLE_ITALIC, capabilities.italic);
+        flags.set(Self::AVAILABLE_FIXED_SPACE, capabilities.fixed_space);
+        flags.set(Self::AVAILABLE_SFX, version >= V6 && capabilities.sounds);
+        flags.set(Self::AVAILABLE_TIMED_INPUT, capabilities.timed_input);
+        flags
     }
 }
 
@@ -116,42 +149,79 @@ bitflags! {
 }
 
 impl ZMachineHeaderFlags2 {
-    /// Return all allowed bits for the given Z-machine version and available features.
+    /// Return all allowed bits for the given Z-machine version and interpreter capabilities.
     pub fn allowed_flags(
         version: ZMachineVersion,
-        flags1: &Option<ZMachineHeaderFlags1Features>,
+        capabilities: &ZInterpreterCapabilities,
     ) -> Self {
-        let base = Self::ENABLE_TRANSCRIPTING
-            | Self::REQUEST_COLORS
-            | if version >= V3 {
-                Self::FORCE_PRINTING_FIXED_PITCH
-            } else {
-                Self::empty()
-            };
-        match flags1 {
-            Some(flags1) => {
-                base | if version >= V5 {
-                    Self::REQUEST_UNDO_OPCODES
-                        | Self::REQUEST_MOUSE
-                        | if flags1.contains(ZMachineHeaderFlags1Features::AVAILABLE_PICTURE) {
-                            Self::REQUEST_PICTURES
-                        } else {
-                            Self::empty()
-                        }
-                        | if flags1.contains(ZMachineHeaderFlags1Features::AVAILABLE_SFX) {
-                            Self::REQUEST_SFX
-                        } else {
-                            Self::empty()
-                        }
-                } else {
-                    Self::empty()
-                } | if version >= V6 {
-                    Self::REQUEST_MENUS
-                } else {
-                    Self::empty()
-                }
-            }
-            None => base,
+        let mut flags = Self::ENABLE_TRANSCRIPTING | Self::REQUEST_COLORS;
+        flags.set(Self::FORCE_PRINTING_FIXED_PITCH, version >= V3);
+        if version >= V5 {
+            flags.set(Self::REQUEST_PICTURES, capabilities.pictures);
+            flags.set(Self::REQUEST_UNDO_OPCODES, capabilities.undo);
+            flags.set(Self::REQUEST_MOUSE, capabilities.mouse);
+            flags.set(Self::REQUEST_SFX, capabilities.sounds);
+        }
+        flags.set(Self::REQUEST_MENUS, version >= V6 && capabilities.menus);
+        flags
+    }
+}
+
+/// Interpreter numbers as assigned by Infocom to their own interpreters (R11.1.3.1).
+///
+/// Games may use it to adapt their behavior to the platform.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ZInterpreterNumber {
+    DecSystem20,
+    AppleIIe,
+    Macintosh,
+    Amiga,
+    AtariSt,
+    IbmPc,
+    Commodore128,
+    Commodore64,
+    AppleIIc,
+    AppleIIgs,
+    TandyColor,
+    Unknown(u8),
+}
+
+impl From<u8> for ZInterpreterNumber {
+    fn from(value: u8) -> Self {
+        use ZInterpreterNumber::*;
+        match value {
+            1 => DecSystem20,
+            2 => AppleIIe,
+            3 => Macintosh,
+            4 => Amiga,
+            5 => AtariSt,
+            6 => IbmPc,
+            7 => Commodore128,
+            8 => Commodore64,
+            9 => AppleIIc,
+            10 => AppleIIgs,
+            11 => TandyColor,
+            _ => Unknown(value),
+        }
+    }
+}
+
+impl From<ZInterpreterNumber> for u8 {
+    fn from(number: ZInterpreterNumber) -> Self {
+        use ZInterpreterNumber::*;
+        match number {
+            DecSystem20 => 1,
+            AppleIIe => 2,
+            Macintosh => 3,
+            Amiga => 4,
+            AtariSt => 5,
+            IbmPc => 6,
+            Commodore128 => 7,
+            Commodore64 => 8,
+            AppleIIc => 9,
+            AppleIIgs => 10,
+            TandyColor => 11,
+            Unknown(value) => value,
         }
     }
 }
@@ -163,6 +233,44 @@ impl ZMachineHeaderFlags2 {
 pub struct ZMachineHeader {
     /// Indicates the required Z-Machine version, from 1 for V1 to 8 for V8.
     version: ZMachineVersion,
+    /// Release number of the story.
+    release: u16,
+    /// Serial code, conventionally the compilation date as YYMMDD (V2+).
+    serial_code: String,
+    /// (V3+) Length of the story file in bytes, if given.
+    ///
+    /// Stored divided by a constant depending on the version (see `file_length_scale`).
+    file_length: Option<u32>,
+    /// (V3+) Checksum of the story file.
+    checksum: Option<u16>,
+    /// (V4+) Interpreter number.
+    interpreter_number: Option<ZInterpreterNumber>,
+    /// (V4+) Interpreter version: an ASCII letter for V4 and V5, a number in V6.
+    interpreter_version: Option<u8>,
+    /// (V4+) Screen height in lines, 255 meaning infinite.
+    screen_height_lines: Option<u8>,
+    /// (V4+) Screen width in characters.
+    screen_width_chars: Option<u8>,
+    /// (V5+) Screen width in units.
+    screen_width_units: Option<u16>,
+    /// (V5+) Screen height in units.
+    screen_height_units: Option<u16>,
+    /// (V5+) Font width in units, i.e. the width of a '0'.
+    font_width_units: Option<u8>,
+    /// (V5+) Font height in units.
+    font_height_units: Option<u8>,
+    /// (V5+) Default background colour (R8.3.1).
+    default_background_color: Option<u8>,
+    /// (V5+) Default foreground colour (R8.3.1).
+    default_foreground_color: Option<u8>,
+    /// (V5+) Location of the terminating characters table, if any (R10.5.2.1).
+    location_terminating_characters_table: Option<ZMemoryAddress>,
+    /// (V6) Total width in pixels of the text sent to output stream 3.
+    stream3_width_units: Option<u16>,
+    /// Revision number of the Standard followed by the interpreter, as (major, minor).
+    standard_revision: (u8, u8),
+    /// (Infocom V6) Player login name.
+    player_login_name: String,
     /// Base of high memory.
     base_high_memory: ZMemoryAddress,
     /// Initial value of the Program Counter.
@@ -182,6 +290,18 @@ pub struct ZMachineHeader {
     base_static_memory: ZMemoryAddress,
     /// (V2+) Location of abbreviations table.
     location_abbreviations_table: Option<ZMemoryAddress>,
+    /// (V6 and V7) Routines offset, divided by 8.
+    routines_offset: Option<u16>,
+    /// (V6 and V7) Static strings offset, divided by 8.
+    strings_offset: Option<u16>,
+    /// (V5+) Location of the custom alphabet table, if any (R3.5.5).
+    location_alphabet_table: Option<ZMemoryAddress>,
+    /// (V5+) Location of the header extension table, if any (R11.1.7).
+    location_extension_table: Option<ZMemoryAddress>,
+    /// (V5+) Location of the Unicode translation table, if any (R3.8.5.2).
+    ///
+    /// Read from word 3 of the header extension table.
+    location_unicode_table: Option<ZMemoryAddress>,
 }
 
 impl ZMachineHeader {
@@ -190,8 +310,62 @@ impl ZMachineHeader {
         let version_raw = memory.read_byte(Byte(0x00))?;
         let version = ZMachineVersion::try_from(version_raw)?;
         let initial_pc_raw = memory.read_word(Word(0x06))?;
+        let location_extension_table = match memory.read_word(Word(0x36))? {
+            address if version >= V5 && address != 0 => Some(Byte(address)),
+            _ => None,
+        };
+        let location_unicode_table = match location_extension_table {
+            Some(table) => Self::read_extension_word(memory, table, 3)?
+                .filter(|&address| address != 0)
+                .map(Byte),
+            None => None,
+        };
+        let read_since = |since: ZMachineVersion, address: u16| -> ZmResult<Option<u8>> {
+            if version >= since {
+                memory.read_byte(Byte(address)).map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        let read_word_since = |since: ZMachineVersion, address: u16| -> ZmResult<Option<u16>> {
+            if version >= since {
+                memory.read_word(Word(address)).map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        // R11.1.6: font width and height are swapped in V6
+        let (font_width_address, font_height_address) = match version {
+            V6 => (0x27, 0x26),
+            _ => (0x26, 0x27),
+        };
         Ok(ZMachineHeader {
             version,
+            release: memory.read_word(Word(0x02))?,
+            serial_code: Self::read_ascii(memory, 0x12, 6)?,
+            file_length: read_word_since(V3, 0x1A)?
+                .filter(|&length| length != 0)
+                .map(|length| length as u32 * Self::file_length_scale(version)),
+            checksum: read_word_since(V3, 0x1C)?,
+            interpreter_number: read_since(V4, 0x1E)?.map(ZInterpreterNumber::from),
+            interpreter_version: read_since(V4, 0x1F)?,
+            screen_height_lines: read_since(V4, 0x20)?,
+            screen_width_chars: read_since(V4, 0x21)?,
+            screen_width_units: read_word_since(V5, 0x22)?,
+            screen_height_units: read_word_since(V5, 0x24)?,
+            font_width_units: read_since(V5, font_width_address)?,
+            font_height_units: read_since(V5, font_height_address)?,
+            default_background_color: read_since(V5, 0x2C)?,
+            default_foreground_color: read_since(V5, 0x2D)?,
+            location_terminating_characters_table: read_word_since(V5, 0x2E)?
+                .filter(|&address| address != 0)
+                .map(Byte),
+            stream3_width_units: match version {
+                V6 => Some(memory.read_word(Word(0x30))?),
+                _ => None,
+            },
+            standard_revision: (memory.read_byte(Byte(0x32))?, memory.read_byte(Byte(0x33))?),
+            player_login_name: Self::read_ascii(memory, 0x38, 8)?,
             initial_pc: if version >= V6 {
                 Packed(initial_pc_raw)
             } else {
@@ -210,34 +384,126 @@ impl ZMachineHeader {
             } else {
                 None
             },
+            routines_offset: if version == V6 || version == V7 {
+                Some(memory.read_word(Word(0x28))?)
+            } else {
+                None
+            },
+            strings_offset: if version == V6 || version == V7 {
+                Some(memory.read_word(Word(0x2A))?)
+            } else {
+                None
+            },
+            location_alphabet_table: match memory.read_word(Word(0x34))? {
+                address if version >= V5 && address != 0 => Some(Byte(address)),
+                _ => None,
+            },
+            location_extension_table,
+            location_unicode_table,
         })
     }
 
+    /// R11.1.6: the file length is stored divided by 2 in V1 to V3, by 4 in V4 and V5, and by 8 in V6+.
+    pub fn file_length_scale(version: ZMachineVersion) -> u32 {
+        match version {
+            V1 | V2 | V3 => 2,
+            V4 | V5 => 4,
+            V6 | V7 | V8 => 8,
+        }
+    }
+
+    /// Reads a fixed-length ASCII field, stopping at the first null byte.
+    fn read_ascii(memory: &ZMemory, address: u16, length: u16) -> ZmResult<String> {
+        let bytes = (address..address + length)
+            .map(|a| memory.read_byte(Byte(a)))
+            .collect::<ZmResult<Vec<u8>>>()?;
+        Ok(bytes
+            .into_iter()
+            .take_while(|&byte| byte != 0)
+            .map(|byte| if byte.is_ascii() { byte as char } else { '?' })
+            .collect())
+    }
+
+    /// Reads the given word of the header extension table, if the table is long enough.
+    ///
+    /// R11.1.7: word 0 of the table holds the number of further words it contains.
+    fn read_extension_word(
+        memory: &ZMemory,
+        table: ZMemoryAddress,
+        index: u16,
+    ) -> ZmResult<Option<u16>> {
+        if memory.read_word(table.offset_word(0)?)? < index {
+            Ok(None)
+        } else {
+            memory.read_word(table.offset_word(2 * index)?).map(Some)
+        }
+    }
+
     /// Sets the needed header data to the appropriate state after a game loading, restore or restart.
     ///
-    /// This means setting all values marked as "Rst" in the header format table (see R11.1).
-    pub fn reset(&mut self, memory: &mut ZMemory) -> ZmResult<()> {
+    /// This means setting all values marked as "Rst" in the header format table (see R11.1),
+    /// according to the given interpreter capabilities.
+    pub fn reset(
+        &mut self,
+        memory: &mut ZMemory,
+        capabilities: &ZInterpreterCapabilities,
+    ) -> ZmResult<()> {
         // set flags 1
         let flags1_raw = memory.read_byte(Byte(0x01))?;
         if self.version >= V4 {
-            self.flags1 = Some(ZMachineHeaderFlags1Features::from_bits_truncate(flags1_raw));
-            memory.write_byte(Byte(0x01), self.flags1.unwrap().bits())?;
+            let flags1 =
+                ZMachineHeaderFlags1Features::from_capabilities(self.version, capabilities);
+            memory.write_byte(Byte(0x01), flags1.bits())?;
+            self.flags1 = Some(flags1);
         } else {
-            self.flags1_old = Some(
-                ZMachineHeaderFlags1::from_bits_truncate(flags1_raw)
-                    & ZMachineHeaderFlags1::STATUS_LINE_TYPE
-                    & ZMachineHeaderFlags1::STORY_SPLIT_DISCS,
-            );
-            memory.write_byte(Byte(0x01), self.flags1_old.unwrap().bits())?;
+            // the status line type and split discs bits are set by the game
+            let flags1_old = (ZMachineHeaderFlags1::from_bits_truncate(flags1_raw)
+                & (ZMachineHeaderFlags1::STATUS_LINE_TYPE
+                    | ZMachineHeaderFlags1::STORY_SPLIT_DISCS))
+                | ZMachineHeaderFlags1::from_capabilities(capabilities);
+            memory.write_byte(Byte(0x01), flags1_old.bits())?;
+            self.flags1_old = Some(flags1_old);
         }
         // filter and set flags 2
         self.flags2 = ZMachineHeaderFlags2::from_bits_truncate(memory.read_word(Word(0x10))?)
-            & ZMachineHeaderFlags2::allowed_flags(self.version, &self.flags1);
+            & ZMachineHeaderFlags2::allowed_flags(self.version, capabilities);
         memory.write_word(Word(0x10), self.flags2.bits())?;
 
+        // interpreter identification and screen dimensions
+        if self.version >= V4 {
+            self.interpreter_number = Some(capabilities.interpreter_number);
+            self.interpreter_version = Some(capabilities.interpreter_version);
+            self.screen_height_lines = Some(capabilities.screen_height_lines);
+            self.screen_width_chars = Some(capabilities.screen_width_chars);
+            memory.write_byte(Byte(0x1E), capabilities.interpreter_number.into())?;
+            memory.write_byte(Byte(0x1F), capabilities.interpreter_version)?;
+            memory.write_byte(Byte(0x20), capabilities.screen_height_lines)?;
+            memory.write_byte(Byte(0x21), capabilities.screen_width_chars)?;
+        }
+        if self.version >= V5 {
+            self.screen_width_units = Some(capabilities.screen_width_units());
+            self.screen_height_units = Some(capabilities.screen_height_units());
+            self.font_width_units = Some(capabilities.font_width_units);
+            self.font_height_units = Some(capabilities.font_height_units);
+            self.default_background_color = Some(capabilities.default_background_color);
+            self.default_foreground_color = Some(capabilities.default_foreground_color);
+            // R11.1.6: font width and height are swapped in V6
+            let (font_width_address, font_height_address) = match self.version {
+                V6 => (0x27, 0x26),
+                _ => (0x26, 0x27),
+            };
+            memory.write_word(Word(0x22), capabilities.screen_width_units())?;
+            memory.write_word(Word(0x24), capabilities.screen_height_units())?;
+            memory.write_byte(Byte(font_width_address), capabilities.font_width_units)?;
+            memory.write_byte(Byte(font_height_address), capabilities.font_height_units)?;
+            memory.write_byte(Byte(0x2C), capabilities.default_background_color)?;
+            memory.write_byte(Byte(0x2D), capabilities.default_foreground_color)?;
+        }
+
         // mark rustifzm as following the 1.1 Z-machine Standards (R11.1.5)
         memory.write_byte(Byte(0x32), 0x1)?; // n = 1
         memory.write_byte(Byte(0x33), 0x1)?; // m = 1
+        self.standard_revision = (1, 1);
 
         Ok(())
     }
@@ -246,15 +512,387 @@ impl ZMachineHeader {
         self.version
     }
 
+    pub fn get_release(&self) -> u16 {
+        self.release
+    }
+
+    pub fn get_serial_code(&self) -> &str {
+        &self.serial_code
+    }
+
+    pub fn get_file_length(&self) -> Option<u32> {
+        self.file_length
+    }
+
+    pub fn get_checksum(&self) -> Option<u16> {
+        self.checksum
+    }
+
+    pub fn get_interpreter_number(&self) -> Option<ZInterpreterNumber> {
+        self.interpreter_number
+    }
+
+    pub fn get_interpreter_version(&self) -> Option<u8> {
+        self.interpreter_version
+    }
+
+    pub fn get_screen_height_lines(&self) -> Option<u8> {
+        self.screen_height_lines
+    }
+
+    pub fn get_screen_width_chars(&self) -> Option<u8> {
+        self.screen_width_chars
+    }
+
+    pub fn get_screen_width_units(&self) -> Option<u16> {
+        self.screen_width_units
+    }
+
+    pub fn get_screen_height_units(&self) -> Option<u16> {
+        self.screen_height_units
+    }
+
+    pub fn get_font_width_units(&self) -> Option<u8> {
+        self.font_width_units
+    }
+
+    pub fn get_font_height_units(&self) -> Option<u8> {
+        self.font_height_units
+    }
+
+    pub fn get_default_background_color(&self) -> Option<u8> {
+        self.default_background_color
+    }
+
+    pub fn get_default_foreground_color(&self) -> Option<u8> {
+        self.default_foreground_color
+    }
+
+    pub fn get_location_terminating_characters_table(&self) -> Option<ZMemoryAddress> {
+        self.location_terminating_characters_table
+    }
+
+    pub fn get_stream3_width_units(&self) -> Option<u16> {
+        self.stream3_width_units
+    }
+
+    pub fn get_standard_revision(&self) -> (u8, u8) {
+        self.standard_revision
+    }
+
+    pub fn get_player_login_name(&self) -> &str {
+        &self.player_login_name
+    }
+
+    pub fn get_base_high_memory(&self) -> ZMemoryAddress {
+        self.base_high_memory
+    }
+
+    pub fn get_base_static_memory(&self) -> ZMemoryAddress {
+        self.base_static_memory
+    }
+
+    pub fn get_flags1_old(&self) -> Option<ZMachineHeaderFlags1> {
+        self.flags1_old
+    }
+
+    pub fn get_flags1(&self) -> Option<ZMachineHeaderFlags1Features> {
+        self.flags1
+    }
+
+    pub fn get_flags2(&self) -> ZMachineHeaderFlags2 {
+        self.flags2
+    }
+
     pub fn get_initial_pc(&self) -> ZMemoryAddress {
         self.initial_pc
     }
 
+    pub fn get_routines_offset(&self) -> Option<u16> {
+        self.routines_offset
+    }
+
+    pub fn get_strings_offset(&self) -> Option<u16> {
+        self.strings_offset
+    }
+
+    pub fn get_location_dictionary(&self) -> ZMemoryAddress {
+        self.location_dictionary
+    }
+
     pub fn get_location_object_table(&self) -> ZMemoryAddress {
         self.location_object_table
     }
 
+    pub fn get_location_global_variables_table(&self) -> ZMemoryAddress {
+        self.location_global_variables_table
+    }
+
     pub fn get_location_abbreviations_table(&self) -> Option<ZMemoryAddress> {
         self.location_abbreviations_table
     }
+
+    pub fn get_location_alphabet_table(&self) -> Option<ZMemoryAddress> {
+        self.location_alphabet_table
+    }
+
+    pub fn get_location_extension_table(&self) -> Option<ZMemoryAddress> {
+        self.location_extension_table
+    }
+
+    pub fn get_location_unicode_table(&self) -> Option<ZMemoryAddress> {
+        self.location_unicode_table
+    }
+}
+
+impl fmt::Display for ZMachineHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn optional<T: fmt::Display>(value: Option<T>) -> String {
+            value.map_or_else(|| "-".to_string(), |value| value.to_string())
+        }
+        fn hex(value: Option<u32>) -> String {
+            value.map_or_else(|| "-".to_string(), |value| format!("{:#06X}", value))
+        }
+        fn address(value: Option<ZMemoryAddress>) -> String {
+            hex(value.map(|value| match value {
+                Byte(a) | Word(a) | RelativeWord(a) | Packed(a) => a as u32,
+                High(a) => a,
+            }))
+        }
+        let word = |value: ZMemoryAddress| address(Some(value));
+
+        writeln!(f, "Z-machine version:        {}", self.version)?;
+        writeln!(f, "Release number:           {}", self.release)?;
+        writeln!(f, "Serial code:              {}", self.serial_code)?;
+        writeln!(
+            f,
+            "File length:              {}",
+            optional(self.file_length)
+        )?;
+        writeln!(
+            f,
+            "Checksum:                 {}",
+            hex(self.checksum.map(u32::from))
+        )?;
+        writeln!(f, "Initial PC:               {}", word(self.initial_pc))?;
+        writeln!(
+            f,
+            "Base of high memory:      {}",
+            word(self.base_high_memory)
+        )?;
+        writeln!(
+            f,
+            "Base of static memory:    {}",
+            word(self.base_static_memory)
+        )?;
+        writeln!(
+            f,
+            "Dictionary:               {}",
+            word(self.location_dictionary)
+        )?;
+        writeln!(
+            f,
+            "Object table:             {}",
+            word(self.location_object_table)
+        )?;
+        writeln!(
+            f,
+            "Global variables:         {}",
+            word(self.location_global_variables_table)
+        )?;
+        writeln!(
+            f,
+            "Abbreviations table:      {}",
+            address(self.location_abbreviations_table)
+        )?;
+        writeln!(
+            f,
+            "Alphabet table:           {}",
+            address(self.location_alphabet_table)
+        )?;
+        writeln!(
+            f,
+            "Header extension table:   {}",
+            address(self.location_extension_table)
+        )?;
+        writeln!(
+            f,
+            "Unicode table:            {}",
+            address(self.location_unicode_table)
+        )?;
+        writeln!(
+            f,
+            "Terminating characters:   {}",
+            address(self.location_terminating_characters_table)
+        )?;
+        writeln!(
+            f,
+            "Interpreter:              {} / {}",
+            optional(self.interpreter_number.map(u8::from)),
+            match (self.version, self.interpreter_version) {
+                (V6, Some(version)) => version.to_string(),
+                (_, Some(version)) => (version as char).to_string(),
+                (_, None) => "-".to_string(),
+            }
+        )?;
+        writeln!(
+            f,
+            "Screen size:              {} lines x {} chars, {} x {} units",
+            optional(self.screen_height_lines),
+            optional(self.screen_width_chars),
+            optional(self.screen_width_units),
+            optional(self.screen_height_units)
+        )?;
+        writeln!(
+            f,
+            "Font size:                {} x {} units",
+            optional(self.font_width_units),
+            optional(self.font_height_units)
+        )?;
+        writeln!(
+            f,
+            "Default colours:          {} on {}",
+            optional(self.default_foreground_color),
+            optional(self.default_background_color)
+        )?;
+        writeln!(
+            f,
+            "Stream 3 width:           {}",
+            optional(self.stream3_width_units)
+        )?;
+        writeln!(
+            f,
+            "Standard revision:        {}.{}",
+            self.standard_revision.0, self.standard_revision.1
+        )?;
+        write!(f, "Player login name:        {}", self.player_login_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_fields() {
+        let mut bytes = [0; 0x100];
+        bytes[0x00] = 5;
+        bytes[0x02..0x04].copy_from_slice(&[0x00, 0x2A]);
+        bytes[0x12..0x18].copy_from_slice(b"220501");
+        bytes[0x1A..0x1E].copy_from_slice(&[0x00, 0x40, 0xBE, 0xEF]);
+        bytes[0x1E..0x22].copy_from_slice(&[6, b'F', 25, 80]);
+        bytes[0x22..0x28].copy_from_slice(&[0x00, 0x50, 0x00, 0x19, 1, 2]);
+        bytes[0x2C..0x30].copy_from_slice(&[2, 9, 0x00, 0x80]);
+        bytes[0x32..0x34].copy_from_slice(&[1, 1]);
+        bytes[0x38..0x3C].copy_from_slice(b"zork");
+        let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+
+        assert_eq!(header.get_release(), 42);
+        assert_eq!(header.get_serial_code(), "220501");
+        assert_eq!(header.get_file_length(), Some(0x100));
+        assert_eq!(header.get_checksum(), Some(0xBEEF));
+        assert_eq!(
+            header.get_interpreter_number(),
+            Some(ZInterpreterNumber::IbmPc)
+        );
+        assert_eq!(header.get_interpreter_version(), Some(b'F'));
+        assert_eq!(header.get_screen_height_lines(), Some(25));
+        assert_eq!(header.get_screen_width_chars(), Some(80));
+        assert_eq!(header.get_screen_width_units(), Some(80));
+        assert_eq!(header.get_screen_height_units(), Some(25));
+        assert_eq!(header.get_font_width_units(), Some(1));
+        assert_eq!(header.get_font_height_units(), Some(2));
+        assert_eq!(header.get_default_background_color(), Some(2));
+        assert_eq!(header.get_default_foreground_color(), Some(9));
+        assert_eq!(
+            header.get_location_terminating_characters_table(),
+            Some(Byte(0x80))
+        );
+        assert_eq!(header.get_stream3_width_units(), None);
+        assert_eq!(header.get_standard_revision(), (1, 1));
+        assert_eq!(header.get_player_login_name(), "zork");
+        assert_eq!(header.get_location_alphabet_table(), None);
+        assert_eq!(header.get_location_extension_table(), None);
+
+        let display = header.to_string();
+        assert!(display.contains("Release number:           42"));
+        assert!(display.contains("Interpreter:              6 / F"));
+    }
+
+    #[test]
+    fn test_header_version_dependent_fields() {
+        let mut bytes = [0; 0x40];
+        bytes[0x00] = 3;
+        bytes[0x1A..0x1C].copy_from_slice(&[0x00, 0x20]);
+        bytes[0x1E] = 6;
+        let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        assert_eq!(header.get_file_length(), Some(0x40));
+        assert_eq!(header.get_interpreter_number(), None);
+        assert_eq!(header.get_screen_width_units(), None);
+
+        bytes[0x00] = 6;
+        bytes[0x26..0x28].copy_from_slice(&[8, 4]);
+        let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        assert_eq!(header.get_file_length(), Some(0x100));
+        // font height comes first in V6
+        assert_eq!(header.get_font_width_units(), Some(4));
+        assert_eq!(header.get_font_height_units(), Some(8));
+        assert_eq!(header.get_stream3_width_units(), Some(0));
+    }
+
+    #[test]
+    fn test_reset_with_capabilities() {
+        let mut bytes = [0; 0x40];
+        bytes[0x00] = 3;
+        bytes[0x01] = 0xFF;
+        let mut memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let mut header = ZMachineHeader::from_memory(&memory).unwrap();
+        let capabilities = ZInterpreterCapabilities {
+            screen_splitting: true,
+            ..ZInterpreterCapabilities::default()
+        };
+        header.reset(&mut memory, &capabilities).unwrap();
+        // the game's bits are kept, the interpreter's ones replaced
+        assert_eq!(memory.read_byte(Byte(0x01)).unwrap(), 0b_0010_0110);
+        assert_eq!(memory.read_byte(Byte(0x1E)).unwrap(), 0);
+
+        bytes[0x00] = 5;
+        bytes[0x10..0x12].copy_from_slice(&[0x01, 0xFF]);
+        let mut memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let mut header = ZMachineHeader::from_memory(&memory).unwrap();
+        let capabilities = ZInterpreterCapabilities {
+            colors: true,
+            italic: true,
+            undo: true,
+            pictures: true,
+            font_width_units: 2,
+            ..ZInterpreterCapabilities::default()
+        };
+        header.reset(&mut memory, &capabilities).unwrap();
+        // no pictures in V5
+        assert_eq!(memory.read_byte(Byte(0x01)).unwrap(), 0b_0000_1001);
+        assert_eq!(
+            header.get_flags2(),
+            ZMachineHeaderFlags2::ENABLE_TRANSCRIPTING
+                | ZMachineHeaderFlags2::FORCE_PRINTING_FIXED_PITCH
+                | ZMachineHeaderFlags2::REQUEST_PICTURES
+                | ZMachineHeaderFlags2::REQUEST_UNDO_OPCODES
+                | ZMachineHeaderFlags2::REQUEST_COLORS
+        );
+        assert_eq!(memory.read_word(Word(0x10)).unwrap(), 0x005B);
+        assert_eq!(memory.read_byte(Byte(0x1E)).unwrap(), 6);
+        assert_eq!(memory.read_byte(Byte(0x1F)).unwrap(), b'A');
+        assert_eq!(memory.read_byte(Byte(0x20)).unwrap(), 24);
+        assert_eq!(memory.read_byte(Byte(0x21)).unwrap(), 80);
+        assert_eq!(memory.read_word(Word(0x22)).unwrap(), 160);
+        assert_eq!(memory.read_word(Word(0x24)).unwrap(), 24);
+        assert_eq!(memory.read_byte(Byte(0x26)).unwrap(), 2);
+        assert_eq!(memory.read_byte(Byte(0x2C)).unwrap(), 2);
+        assert_eq!(memory.read_byte(Byte(0x2D)).unwrap(), 9);
+        assert_eq!(header.get_screen_width_units(), Some(160));
+        assert_eq!(header.get_standard_revision(), (1, 1));
+    }
 }
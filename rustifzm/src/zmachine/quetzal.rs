@@ -0,0 +1,414 @@
+use super::header::ZMachineHeader;
+use crate::{
+    zcpu::ZCallFrame,
+    zmemory::{ZMemory, ZMemoryAddress::*},
+    ZmError, ZmResult,
+};
+
+/// A saved game, in the Quetzal 1.4 format shared by most Z-machine interpreters.
+///
+/// A Quetzal file is an IFF `FORM` of type `IFZS`, made of the following chunks:
+/// - `IFhd`: the release number, serial code and checksum of the story file,
+///   followed by the PC of the `save` instruction (3 bytes).
+/// - `CMem` or `UMem`: dynamic memory, respectively compressed against the original
+///   story file or not.
+/// - `Stks`: the call stack, from the bottom frame to the current routine's.
+///
+/// Any other chunk, such as an annotation, is ignored.
+///
+/// See: https://inform-fiction.org/zmachine/standards/quetzal/index.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZQuetzal {
+    release: u16,
+    serial_code: [u8; 6],
+    checksum: u16,
+    /// Address of the branch data (V1 to V3) or store variable (V4+) of the `save` instruction.
+    pc: u32,
+    dynamic_memory: Vec<u8>,
+    frames: Vec<ZCallFrame>,
+}
+
+impl ZQuetzal {
+    /// Save the current state of the machine, the story being at the given PC.
+    ///
+    /// The story file is identified by the header fields found in memory.
+    pub fn from_state(memory: &ZMemory, pc: u32, frames: &[ZCallFrame]) -> ZmResult<Self> {
+        let mut serial_code = [0; 6];
+        for (offset, byte) in serial_code.iter_mut().enumerate() {
+            *byte = memory.read_byte(Byte(0x12 + offset as u16))?;
+        }
+        Ok(ZQuetzal {
+            release: memory.read_word(Word(0x02))?,
+            serial_code,
+            checksum: memory.read_word(Word(0x1C))?,
+            pc,
+            dynamic_memory: memory.get_dynamic_memory().to_vec(),
+            frames: frames.to_vec(),
+        })
+    }
+
+    /// Parse a Quetzal file saved for the story loaded in the given memory.
+    ///
+    /// Fails with `ZmError::QuetzalInvalidFile` if any mandatory chunk is missing or malformed,
+    /// but does not check that the file belongs to the story: see `is_from_story`.
+    pub fn from_bytes(bytes: &[u8], memory: &ZMemory) -> ZmResult<Self> {
+        let mut reader = ZQuetzalReader::new(bytes);
+        if reader.read_id()? != *b"FORM" {
+            return Err(ZmError::QuetzalInvalidFile("not an IFF file"));
+        }
+        let length = reader.read_u32()? as usize;
+        let mut reader = ZQuetzalReader::new(reader.read_bytes(length)?);
+        if reader.read_id()? != *b"IFZS" {
+            return Err(ZmError::QuetzalInvalidFile("not a Quetzal file"));
+        }
+
+        let (mut identification, mut dynamic_memory, mut frames) = (None, None, None);
+        while !reader.is_empty() {
+            let id = reader.read_id()?;
+            let length = reader.read_u32()? as usize;
+            let data = reader.read_bytes(length)?;
+            // chunks are padded to an even length
+            if length % 2 == 1 && !reader.is_empty() {
+                reader.read_u8()?;
+            }
+            match &id {
+                b"IFhd" => identification = Some(data),
+                b"CMem" => {
+                    dynamic_memory = Some(Self::decompressed(
+                        data,
+                        memory.get_original_dynamic_memory(),
+                        memory.get_dynamic_memory().len(),
+                    )?)
+                }
+                b"UMem" if data.len() == memory.get_dynamic_memory().len() => {
+                    dynamic_memory = Some(data.to_vec())
+                }
+                b"UMem" => return Err(ZmError::QuetzalInvalidFile("wrong dynamic memory size")),
+                b"Stks" => frames = Some(Self::decoded_frames(data)?),
+                _ => {}
+            }
+        }
+
+        let mut identification = ZQuetzalReader::new(
+            identification.ok_or(ZmError::QuetzalInvalidFile("missing IFhd chunk"))?,
+        );
+        let release = identification.read_u16()?;
+        let mut serial_code = [0; 6];
+        serial_code.copy_from_slice(identification.read_bytes(6)?);
+        Ok(ZQuetzal {
+            release,
+            serial_code,
+            checksum: identification.read_u16()?,
+            pc: identification.read_u24()?,
+            dynamic_memory: dynamic_memory
+                .ok_or(ZmError::QuetzalInvalidFile("missing CMem or UMem chunk"))?,
+            frames: frames.ok_or(ZmError::QuetzalInvalidFile("missing Stks chunk"))?,
+        })
+    }
+
+    /// Write the Quetzal file, with dynamic memory compressed against the given memory's original.
+    pub fn to_bytes(&self, memory: &ZMemory) -> Vec<u8> {
+        let mut identification = Vec::with_capacity(13);
+        identification.extend_from_slice(&self.release.to_be_bytes());
+        identification.extend_from_slice(&self.serial_code);
+        identification.extend_from_slice(&self.checksum.to_be_bytes());
+        identification.extend_from_slice(&self.pc.to_be_bytes()[1..]);
+
+        let mut form = b"IFZS".to_vec();
+        Self::write_chunk(&mut form, b"IFhd", &identification);
+        Self::write_chunk(
+            &mut form,
+            b"CMem",
+            &Self::compressed(&self.dynamic_memory, memory.get_original_dynamic_memory()),
+        );
+        Self::write_chunk(&mut form, b"Stks", &Self::encoded_frames(&self.frames));
+
+        let mut bytes = Vec::with_capacity(form.len() + 8);
+        Self::write_chunk(&mut bytes, b"FORM", &form);
+        bytes
+    }
+
+    /// Was the game saved from the given story file?
+    ///
+    /// The release number, serial code and checksum must all match (Quetzal 5.4).
+    /// V1 and V2 story files carry no checksum, which is then not compared.
+    pub fn is_from_story(&self, header: &ZMachineHeader) -> bool {
+        let serial_code = self
+            .serial_code
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii() { byte as char } else { '?' })
+            .collect::<String>();
+        self.release == header.get_release()
+            && serial_code == header.get_serial_code()
+            && header
+                .get_checksum()
+                .is_none_or(|checksum| checksum == self.checksum)
+    }
+
+    pub fn get_pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn get_dynamic_memory(&self) -> &[u8] {
+        &self.dynamic_memory
+    }
+
+    pub fn get_frames(&self) -> &[ZCallFrame] {
+        &self.frames
+    }
+
+    fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+    }
+
+    /// Quetzal 3.2: dynamic memory is XORed with the original story file, then each run
+    /// of zeros is written as a zero followed by the length of the run minus 1,
+    /// runs longer than 256 bytes being split. The final run of zeros is omitted.
+    ///
+    /// This makes for a compact diff of the changes made by the game, as used by undo states.
+    pub fn compressed(dynamic_memory: &[u8], original: &[u8]) -> Vec<u8> {
+        let mut compressed = vec![];
+        let mut zeros = 0usize;
+        for (index, byte) in dynamic_memory.iter().enumerate() {
+            let xored = byte ^ original.get(index).copied().unwrap_or(0);
+            if xored == 0 {
+                zeros += 1;
+                continue;
+            }
+            while zeros > 0 {
+                let run = zeros.min(256);
+                compressed.extend_from_slice(&[0, (run - 1) as u8]);
+                zeros -= run;
+            }
+            compressed.push(xored);
+        }
+        compressed
+    }
+
+    /// Returns dynamic memory of the given length from its compressed form.
+    pub fn decompressed(compressed: &[u8], original: &[u8], length: usize) -> ZmResult<Vec<u8>> {
+        let mut xored = Vec::with_capacity(length);
+        let mut bytes = compressed.iter();
+        while let Some(&byte) = bytes.next() {
+            if byte == 0 {
+                let run = bytes
+                    .next()
+                    .ok_or(ZmError::QuetzalInvalidFile("truncated CMem chunk"))?;
+                xored.resize(xored.len() + *run as usize + 1, 0);
+            } else {
+                xored.push(byte);
+            }
+            if xored.len() > length {
+                return Err(ZmError::QuetzalInvalidFile("wrong dynamic memory size"));
+            }
+        }
+        xored.resize(length, 0);
+        Ok(xored
+            .into_iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ original.get(index).copied().unwrap_or(0))
+            .collect())
+    }
+
+    /// Quetzal 4: each frame is written as its return PC (3 bytes), a byte of flags holding
+    /// the number of locals in its bottom 4 bits and bit 4 set if the result is discarded,
+    /// the store variable, a byte with bit n set if argument n + 1 was supplied,
+    /// and the size of the evaluation stack (1 word). The locals and the evaluation stack follow.
+    fn encoded_frames(frames: &[ZCallFrame]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for frame in frames {
+            bytes.extend_from_slice(&frame.get_return_pc().to_be_bytes()[1..]);
+            let locals = frame.get_locals();
+            let store_variable = frame.get_store_variable();
+            bytes.push(locals.len() as u8 | if store_variable.is_none() { 0x10 } else { 0 });
+            bytes.push(store_variable.unwrap_or(0));
+            bytes.push(((1u16 << frame.get_arguments_count().min(7)) - 1) as u8);
+            bytes.extend_from_slice(&(frame.get_stack().len() as u16).to_be_bytes());
+            for word in locals.iter().chain(frame.get_stack()) {
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn decoded_frames(bytes: &[u8]) -> ZmResult<Vec<ZCallFrame>> {
+        let mut reader = ZQuetzalReader::new(bytes);
+        let mut frames = vec![];
+        while !reader.is_empty() {
+            let return_pc = reader.read_u24()?;
+            let flags = reader.read_u8()?;
+            let store_variable = reader.read_u8()?;
+            let arguments_count = reader.read_u8()?.trailing_ones() as u8;
+            let stack_size = reader.read_u16()?;
+            let locals = (0..flags & 0x0F)
+                .map(|_| reader.read_u16())
+                .collect::<ZmResult<Vec<u16>>>()?;
+            let stack = (0..stack_size)
+                .map(|_| reader.read_u16())
+                .collect::<ZmResult<Vec<u16>>>()?;
+            let store_variable = if flags & 0x10 == 0 {
+                Some(store_variable)
+            } else {
+                None
+            };
+            frames.push(
+                ZCallFrame::new(return_pc, locals, store_variable, arguments_count)
+                    .with_stack(stack),
+            );
+        }
+        if frames.is_empty() {
+            return Err(ZmError::QuetzalInvalidFile("empty Stks chunk"));
+        }
+        Ok(frames)
+    }
+}
+
+/// Reads the Big Endian numbers of an IFF file.
+struct ZQuetzalReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ZQuetzalReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ZQuetzalReader { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn read_bytes(&mut self, length: usize) -> ZmResult<&'a [u8]> {
+        if length > self.bytes.len() {
+            return Err(ZmError::QuetzalInvalidFile("truncated file"));
+        }
+        let (bytes, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn read_id(&mut self) -> ZmResult<[u8; 4]> {
+        let mut id = [0; 4];
+        id.copy_from_slice(self.read_bytes(4)?);
+        Ok(id)
+    }
+
+    fn read_u8(&mut self) -> ZmResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> ZmResult<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u24(&mut self) -> ZmResult<u32> {
+        let bytes = self.read_bytes(3)?;
+        Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+    }
+
+    fn read_u32(&mut self) -> ZmResult<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A V3 story of release 7 and serial code 250101, with 0x100 bytes of dynamic memory.
+    fn init_memory() -> ZMemory {
+        let mut story = vec![0u8; 0x200];
+        story[0x00] = 3;
+        story[0x03] = 7;
+        story[0x0E] = 0x01; // static memory at 0x100
+        story[0x12..0x18].copy_from_slice(b"250101");
+        story[0x1C] = 0xAB;
+        story[0x1D] = 0xCD;
+        story[0x80] = 0x42;
+        let mut memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        memory.set_regions(0x100, 0x100);
+        memory
+    }
+
+    fn init_frames() -> Vec<ZCallFrame> {
+        vec![
+            ZCallFrame::new(0, vec![], None, 0).with_stack(vec![0x1234]),
+            ZCallFrame::new(0x1ABCD, vec![1, 2, 3], Some(0x10), 2).with_stack(vec![5, 6]),
+        ]
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let mut memory = init_memory();
+        memory.store_byte(Byte(0x40), 0x01).unwrap();
+        memory.store_byte(Byte(0x80), 0x00).unwrap();
+        let quetzal = ZQuetzal::from_state(&memory, 0x4321, &init_frames()).unwrap();
+        let bytes = quetzal.to_bytes(&memory);
+        assert_eq!(&bytes[0..4], b"FORM");
+        assert_eq!(&bytes[8..12], b"IFZS");
+        assert_eq!(
+            &bytes[12..34],
+            b"IFhd\0\0\0\x0d\0\x07250101\xab\xcd\0\x43\x21\0"
+        );
+        // 0x40 unchanged bytes, 0x01, 0x3F unchanged bytes, 0x00 ^ 0x42, trailing zeros omitted
+        assert_eq!(&bytes[34..48], b"CMem\0\0\0\x06\0\x3f\x01\0\x3e\x42");
+
+        let restored = ZQuetzal::from_bytes(&bytes, &init_memory()).unwrap();
+        assert_eq!(restored, quetzal);
+        assert_eq!(restored.get_dynamic_memory()[0x40], 0x01);
+        assert_eq!(restored.get_dynamic_memory()[0x80], 0x00);
+        assert_eq!(restored.get_frames()[1].get_arguments_count(), 2);
+        assert_eq!(restored.get_frames()[0].get_store_variable(), None);
+        assert!(ZQuetzal::from_bytes(&bytes[..bytes.len() - 1], &memory).is_err());
+    }
+
+    #[test]
+    fn test_uncompressed_memory_and_unknown_chunks() {
+        let memory = init_memory();
+        let mut form = b"IFZS".to_vec();
+        ZQuetzal::write_chunk(&mut form, b"ANNO", b"odd");
+        ZQuetzal::write_chunk(&mut form, b"IFhd", b"\0\x07250101\xab\xcd\0\x43\x21");
+        ZQuetzal::write_chunk(&mut form, b"UMem", &[0x55; 0x100]);
+        ZQuetzal::write_chunk(
+            &mut form,
+            b"Stks",
+            &ZQuetzal::encoded_frames(&init_frames()),
+        );
+        let mut bytes = vec![];
+        ZQuetzal::write_chunk(&mut bytes, b"FORM", &form);
+
+        let restored = ZQuetzal::from_bytes(&bytes, &memory).unwrap();
+        assert_eq!(restored.get_pc(), 0x4321);
+        assert_eq!(restored.get_dynamic_memory(), &[0x55; 0x100]);
+        assert_eq!(restored.get_frames(), &init_frames()[..]);
+
+        // a large run of zeros is split
+        let mut changed = vec![0u8; 0x100];
+        changed[0xFF] = 0x42;
+        assert_eq!(
+            ZQuetzal::compressed(&changed, &[0; 0x100]),
+            vec![0, 0xFE, 0x42]
+        );
+        assert_eq!(ZQuetzal::compressed(&[0; 0x300], &[]), Vec::<u8>::new());
+        assert!(ZQuetzal::decompressed(&[0, 0xFF, 0x01], &[], 0x100).is_err());
+    }
+
+    #[test]
+    fn test_story_identification() {
+        let mut memory = init_memory();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let quetzal = ZQuetzal::from_state(&memory, 0x4321, &init_frames()).unwrap();
+        assert!(quetzal.is_from_story(&header));
+
+        memory.write_word(Word(0x02), 8).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        assert!(!quetzal.is_from_story(&header));
+    }
+}
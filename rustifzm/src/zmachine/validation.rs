@@ -0,0 +1,144 @@
+use super::header::ZMachineHeader;
+use crate::{zmemory::ZMemory, ZmError, ZmResult};
+
+/// Size of the header, which any story file must at least contain (R11.1).
+const ZHEADER_SIZE: usize = 0x40;
+
+/// The results of the sanity checks run on a story file when loading it.
+///
+/// Only a file too short to hold a header or with an unknown version is rejected:
+/// other problems are reported so that frontends can warn about a possibly corrupted file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZStoryValidation {
+    /// Length of the story file as read.
+    file_length: usize,
+    /// (V3+) Length declared in the header, if given.
+    declared_length: Option<u32>,
+    /// (V3+) Checksum declared in the header.
+    declared_checksum: Option<u16>,
+    /// Checksum computed over the story file, once fitted to its declared length.
+    checksum: u16,
+}
+
+impl ZStoryValidation {
+    /// Validate the given story file, then pad or truncate it to its declared length.
+    ///
+    /// R11.1.6: the file length in the header allows to ignore any padding at the end of the
+    /// file, and the checksum is computed over the bytes between the header and that length.
+    pub fn validate(memory: &mut ZMemory) -> ZmResult<Self> {
+        let file_length = memory.len();
+        if file_length < ZHEADER_SIZE {
+            return Err(ZmError::MachineStoryTooShort(file_length));
+        }
+        let header = ZMachineHeader::from_memory(memory)?;
+        let declared_length = header.get_file_length();
+        if let Some(length) = declared_length.filter(|&length| length as usize >= ZHEADER_SIZE) {
+            memory.resize(length as usize);
+        }
+        Ok(Self {
+            file_length,
+            declared_length,
+            declared_checksum: header.get_checksum(),
+            checksum: memory.checksum(),
+        })
+    }
+
+    pub fn get_file_length(&self) -> usize {
+        self.file_length
+    }
+
+    pub fn get_declared_length(&self) -> Option<u32> {
+        self.declared_length
+    }
+
+    pub fn get_declared_checksum(&self) -> Option<u16> {
+        self.declared_checksum
+    }
+
+    pub fn get_checksum(&self) -> u16 {
+        self.checksum
+    }
+
+    /// Is the story file shorter than declared, as with an interrupted download?
+    pub fn is_truncated(&self) -> bool {
+        matches!(self.declared_length, Some(length) if (length as usize) > self.file_length)
+    }
+
+    /// Is the story file longer than declared (which is legal)?
+    pub fn is_padded(&self) -> bool {
+        matches!(self.declared_length, Some(length) if (length as usize) < self.file_length)
+    }
+
+    /// Does the computed checksum match the header's?
+    ///
+    /// V1 and V2 story files carry no checksum, and are considered valid.
+    pub fn has_valid_checksum(&self) -> bool {
+        self.declared_checksum
+            .is_none_or(|checksum| checksum == self.checksum)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.is_truncated() && self.has_valid_checksum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn story(length: usize, declared_length: u16, checksum: u16) -> ZMemory {
+        let mut bytes = vec![0; length];
+        bytes[0x00] = 3;
+        bytes[0x1A..0x1C].copy_from_slice(&declared_length.to_be_bytes());
+        bytes[0x1C..0x1E].copy_from_slice(&checksum.to_be_bytes());
+        for (i, byte) in bytes.iter_mut().enumerate().skip(0x40) {
+            *byte = i as u8;
+        }
+        ZMemory::from_story_reader(&mut &bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn test_valid_story() {
+        // 0x40 + 0x41 + ... + 0x7F
+        let mut memory = story(0x80, 0x40, 0x17E0);
+        let validation = ZStoryValidation::validate(&mut memory).unwrap();
+        assert_eq!(validation.get_checksum(), 0x17E0);
+        assert!(validation.is_valid());
+        assert!(!validation.is_padded());
+        assert_eq!(memory.len(), 0x80);
+    }
+
+    #[test]
+    fn test_padded_and_truncated_stories() {
+        let mut memory = story(0x90, 0x40, 0x17E0);
+        let validation = ZStoryValidation::validate(&mut memory).unwrap();
+        assert!(validation.is_padded());
+        assert!(validation.is_valid());
+        assert_eq!(memory.len(), 0x80);
+
+        let mut memory = story(0x80, 0x50, 0x17E0);
+        let validation = ZStoryValidation::validate(&mut memory).unwrap();
+        assert!(validation.is_truncated());
+        assert!(validation.has_valid_checksum());
+        assert!(!validation.is_valid());
+        assert_eq!(memory.len(), 0xA0);
+    }
+
+    #[test]
+    fn test_invalid_stories() {
+        let mut memory = story(0x80, 0x40, 0xBEEF);
+        let validation = ZStoryValidation::validate(&mut memory).unwrap();
+        assert!(!validation.has_valid_checksum());
+
+        let mut memory = ZMemory::from_story_reader(&mut &[3u8; 0x20][..]).unwrap();
+        assert!(matches!(
+            ZStoryValidation::validate(&mut memory),
+            Err(ZmError::MachineStoryTooShort(0x20))
+        ));
+        let mut memory = ZMemory::from_story_reader(&mut &[9u8; 0x40][..]).unwrap();
+        assert!(matches!(
+            ZStoryValidation::validate(&mut memory),
+            Err(ZmError::MachineUnknownVersion(9))
+        ));
+    }
+}
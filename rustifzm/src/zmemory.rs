@@ -1,7 +1,7 @@
 use std::fmt;
 use std::io::Read;
 
-use crate::{errors::ZmError, ZmResult};
+use crate::{errors::ZmError, ZMachineVersion, ZmResult};
 
 /// The different kinds of addresses in the Z-machine.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -19,6 +19,11 @@ pub enum ZMemoryAddress {
     RelativeWord(u16),
     /// The packed relative location of a routine or string in high memory.
     Packed(u16),
+    /// An individual byte anywhere in the story file, by 32-bit offset.
+    ///
+    /// Unpacked routine and string addresses are of this kind since high memory
+    /// can extend past 64K: up to 256K in V4 and V5, 512K in V8.
+    High(u32),
 }
 
 use self::ZMemoryAddress::*;
@@ -31,9 +36,53 @@ impl ZMemoryAddress {
         }
     }
 
+    /// Returns the offset in the story file of a byte or high memory address.
+    pub fn as_offset(&self) -> ZmResult<u32> {
+        match self {
+            Byte(address) => Ok(*address as u32),
+            High(address) => Ok(*address),
+            _ => Err(ZmError::MemoryInvalidAddress(*self)),
+        }
+    }
+
+    /// Unpacks the packed address of a routine into a high memory address (R1.2.3).
+    ///
+    /// The packed address P is worth 2P in V1 to V3, 4P in V4 and V5, 4P + 8R_O in V6 and V7
+    /// (with R_O the routines offset stored in the header) and 8P in V8.
+    pub fn unpacked_routine(
+        &self,
+        version: ZMachineVersion,
+        routines_offset: u16,
+    ) -> ZmResult<Self> {
+        self.unpacked(version, routines_offset)
+    }
+
+    /// Unpacks the packed address of a string into a high memory address (R1.2.3).
+    ///
+    /// Same as routines, except that V6 and V7 use the strings offset S_O from the header.
+    pub fn unpacked_string(&self, version: ZMachineVersion, strings_offset: u16) -> ZmResult<Self> {
+        self.unpacked(version, strings_offset)
+    }
+
+    fn unpacked(&self, version: ZMachineVersion, offset: u16) -> ZmResult<Self> {
+        match self {
+            Packed(address) => {
+                let address = *address as u32;
+                Ok(High(match version {
+                    ZMachineVersion::V1 | ZMachineVersion::V2 | ZMachineVersion::V3 => 2 * address,
+                    ZMachineVersion::V4 | ZMachineVersion::V5 => 4 * address,
+                    ZMachineVersion::V6 | ZMachineVersion::V7 => 4 * address + 8 * offset as u32,
+                    ZMachineVersion::V8 => 8 * address,
+                }))
+            }
+            _ => Err(ZmError::MemoryInvalidAddress(*self)),
+        }
+    }
+
     pub fn offset_byte(&self, offset: u16) -> ZmResult<Self> {
         match self {
             Byte(address) => Ok(ZMemoryAddress::Byte(address.wrapping_add(offset))),
+            High(address) => Ok(ZMemoryAddress::High(address.wrapping_add(offset as u32))),
             _ => Err(ZmError::MemoryInvalidAddress(*self)),
         }
     }
@@ -53,6 +102,7 @@ impl fmt::Display for ZMemoryAddress {
             Word(address) => write!(f, "ZMemoryAddress Word = {:#X}", address),
             RelativeWord(address) => write!(f, "ZMemoryAddress RelativeWord = {:#X}", address),
             Packed(address) => write!(f, "ZMemoryAddress Packed = {:#X}", address),
+            High(address) => write!(f, "ZMemoryAddress High = {:#X}", address),
         }
     }
 }
@@ -73,43 +123,148 @@ pub struct ZMemory {
     ///   to the end of the story file. May overlap with static memory.
     ///   Unaccessible directly from games since strings and routines are stored here.
     buffer: Vec<u8>,
+    /// The dynamic memory as loaded from the story file, before the interpreter or the game
+    /// modified it: saved games are compressed against it, and restarts start from it again.
+    ///
+    /// Until the regions are set, it holds the whole story file.
+    original: Vec<u8>,
+    /// Base of static memory, i.e. the end of dynamic memory.
+    ///
+    /// Until the regions are set, the whole memory is considered dynamic.
+    static_base: usize,
+    /// Base of high memory.
+    high_mark: usize,
 }
 
 impl ZMemory {
     pub fn from_story_reader(reader: &mut dyn Read) -> ZmResult<Self> {
         let mut buffer = Vec::new();
         reader.read_to_end(&mut buffer)?;
-        Ok(ZMemory { buffer })
+        let length = buffer.len();
+        Ok(ZMemory {
+            original: buffer.clone(),
+            buffer,
+            static_base: length,
+            high_mark: length,
+        })
     }
 
-    pub fn read_byte(&self, address: ZMemoryAddress) -> ZmResult<u8> {
-        match address {
-            Byte(a) => self
-                .buffer
-                .get(a as usize)
-                .cloned()
-                .ok_or(ZmError::MemoryInvalidAccess(a as usize)),
-            _ => Err(ZmError::MemoryInvalidAddress(address)),
+    /// Size of the story file in bytes.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Pad with zeros or truncate the story file to the given length.
+    pub fn resize(&mut self, length: usize) {
+        self.buffer.resize(length, 0);
+    }
+
+    /// Sum of all the bytes after the header, modulo 0x10000 (R11.1.6).
+    pub fn checksum(&self) -> u16 {
+        self.buffer
+            .iter()
+            .skip(0x40)
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
+    }
+
+    /// Set the bases of static and high memory, as read from the header.
+    pub fn set_regions(&mut self, static_base: u16, high_mark: u16) {
+        self.static_base = static_base as usize;
+        self.high_mark = high_mark as usize;
+        self.original
+            .resize(self.static_base.min(self.buffer.len()), 0);
+    }
+
+    pub fn get_static_base(&self) -> usize {
+        self.static_base
+    }
+
+    pub fn get_high_mark(&self) -> usize {
+        self.high_mark
+    }
+
+    /// The current contents of dynamic memory.
+    pub fn get_dynamic_memory(&self) -> &[u8] {
+        &self.buffer[..self.static_base.min(self.buffer.len())]
+    }
+
+    /// The contents of dynamic memory as loaded from the story file.
+    pub fn get_original_dynamic_memory(&self) -> &[u8] {
+        &self.original
+    }
+
+    /// Privileged write replacing the whole of dynamic memory, e.g. when restoring a saved game.
+    ///
+    /// Fails with `ZmError::MemoryInvalidAccess` if the given bytes do not fit exactly.
+    pub fn write_dynamic_memory(&mut self, bytes: &[u8]) -> ZmResult<()> {
+        let length = self.get_dynamic_memory().len();
+        if bytes.len() != length {
+            return Err(ZmError::MemoryInvalidAccess(bytes.len().min(length)));
         }
+        self.buffer[..length].copy_from_slice(bytes);
+        Ok(())
     }
 
-    pub fn read_word(&self, address: ZMemoryAddress) -> ZmResult<u16> {
-        match address {
-            Word(a) => {
-                let upper = self
-                    .buffer
-                    .get(a as usize)
-                    .ok_or(ZmError::MemoryInvalidAccess(a as usize))?;
-                let lower = self
-                    .buffer
-                    .get((a + 1) as usize)
-                    .ok_or(ZmError::MemoryInvalidAccess((a + 1) as usize))?;
-                Ok(((*upper as u16) << 8) | (*lower as u16))
+    /// Is the given byte address in dynamic memory, and thus writable by games?
+    pub fn is_dynamic(&self, address: u16) -> bool {
+        (address as usize) < self.static_base.min(self.buffer.len())
+    }
+
+    /// Write a byte on behalf of the game, which can only modify dynamic memory (R1.1.1).
+    ///
+    /// The PC reported by the error is filled in by `ZCpu::step`.
+    pub fn store_byte(&mut self, address: ZMemoryAddress, value: u8) -> ZmResult<()> {
+        let a = address.as_byte()?;
+        if !self.is_dynamic(a) {
+            return Err(ZmError::MemoryIllegalWrite(0, a));
+        }
+        self.write_byte(address, value)
+    }
+
+    /// Write a word on behalf of the game, which can only modify dynamic memory (R1.1.1).
+    ///
+    /// The PC reported by the error is filled in by `ZCpu::step`.
+    pub fn store_word(&mut self, address: ZMemoryAddress, value: u16) -> ZmResult<()> {
+        let a = match address {
+            Word(a) => a,
+            _ => return Err(ZmError::MemoryInvalidAddress(address)),
+        };
+        for byte_address in [a, a.wrapping_add(1)] {
+            if !self.is_dynamic(byte_address) {
+                return Err(ZmError::MemoryIllegalWrite(0, byte_address));
             }
-            _ => Err(ZmError::MemoryInvalidAddress(address)),
         }
+        self.write_word(address, value)
+    }
+
+    pub fn read_byte(&self, address: ZMemoryAddress) -> ZmResult<u8> {
+        let a = address.as_offset()? as usize;
+        self.buffer
+            .get(a)
+            .cloned()
+            .ok_or(ZmError::MemoryInvalidAccess(a))
+    }
+
+    /// Read a word, either in the bottom 64K or anywhere in high memory.
+    pub fn read_word(&self, address: ZMemoryAddress) -> ZmResult<u16> {
+        let a = match address {
+            Word(a) => a as usize,
+            High(a) => a as usize,
+            _ => return Err(ZmError::MemoryInvalidAddress(address)),
+        };
+        let upper = self.buffer.get(a).ok_or(ZmError::MemoryInvalidAccess(a))?;
+        let lower = self
+            .buffer
+            .get(a + 1)
+            .ok_or(ZmError::MemoryInvalidAccess(a + 1))?;
+        Ok(((*upper as u16) << 8) | (*lower as u16))
     }
 
+    /// Privileged write, reserved to the interpreter itself (e.g. for the header fields).
     pub fn write_byte(&mut self, address: ZMemoryAddress, value: u8) -> ZmResult<()> {
         match address {
             Byte(a) => self
@@ -123,6 +278,7 @@ impl ZMemory {
         }
     }
 
+    /// Privileged write, reserved to the interpreter itself (e.g. for the header fields).
     pub fn write_word(&mut self, address: ZMemoryAddress, value: u16) -> ZmResult<()> {
         match address {
             Word(a) => {
@@ -142,6 +298,9 @@ mod tests {
     fn init_memory() -> ZMemory {
         ZMemory {
             buffer: vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+            original: vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+            static_base: 6,
+            high_mark: 6,
         }
     }
 
@@ -166,4 +325,66 @@ mod tests {
         );
         assert!(memory.read_word(ZMemoryAddress::Word(0x05)).is_err());
     }
+
+    #[test]
+    fn test_dynamic_memory_writes() {
+        let mut memory = init_memory();
+        memory.set_regions(0x03, 0x05);
+        memory.store_byte(ZMemoryAddress::Byte(0x02), 0xAA).unwrap();
+        memory
+            .store_word(ZMemoryAddress::Word(0x00), 0xBBCC)
+            .unwrap();
+        assert!(matches!(
+            memory.store_byte(ZMemoryAddress::Byte(0x03), 0xAA),
+            Err(ZmError::MemoryIllegalWrite(_, 0x03))
+        ));
+        assert!(matches!(
+            memory.store_word(ZMemoryAddress::Word(0x02), 0xAAAA),
+            Err(ZmError::MemoryIllegalWrite(_, 0x03))
+        ));
+        assert_eq!(memory.read_byte(ZMemoryAddress::Byte(0x02)).unwrap(), 0xAA);
+        // the interpreter can still write anywhere
+        memory.write_byte(ZMemoryAddress::Byte(0x04), 0xDD).unwrap();
+        assert_eq!(memory.read_byte(ZMemoryAddress::Byte(0x04)).unwrap(), 0xDD);
+    }
+
+    #[test]
+    fn test_dynamic_memory() {
+        let mut memory = init_memory();
+        memory.set_regions(0x04, 0x05);
+        memory.store_byte(ZMemoryAddress::Byte(0x01), 0xAA).unwrap();
+        assert_eq!(memory.get_dynamic_memory(), &[0x01, 0xAA, 0x03, 0x04]);
+        assert_eq!(
+            memory.get_original_dynamic_memory(),
+            &[0x01, 0x02, 0x03, 0x04]
+        );
+        assert!(memory.write_dynamic_memory(&[0xFF; 5]).is_err());
+        memory.write_dynamic_memory(&[0xFF; 4]).unwrap();
+        assert_eq!(memory.get_dynamic_memory(), &[0xFF; 4]);
+        assert_eq!(memory.read_byte(ZMemoryAddress::Byte(0x04)).unwrap(), 0x05);
+    }
+
+    #[test]
+    fn test_unpacked_addresses() {
+        use ZMachineVersion::*;
+        let packed = ZMemoryAddress::Packed(0x8000);
+        assert_eq!(packed.unpacked_routine(V3, 0).unwrap(), High(0x10000));
+        assert_eq!(packed.unpacked_routine(V5, 0).unwrap(), High(0x20000));
+        assert_eq!(packed.unpacked_routine(V8, 0).unwrap(), High(0x40000));
+        assert_eq!(packed.unpacked_routine(V6, 0x10).unwrap(), High(0x20080));
+        assert_eq!(packed.unpacked_string(V7, 0x20).unwrap(), High(0x20100));
+        assert!(ZMemoryAddress::Byte(0x10).unpacked_string(V5, 0).is_err());
+    }
+
+    #[test]
+    fn test_read_high_memory() {
+        let mut buffer = vec![0; 0x20002];
+        buffer[0x20000] = 0xAB;
+        buffer[0x20001] = 0xCD;
+        let memory = ZMemory::from_story_reader(&mut &buffer[..]).unwrap();
+        assert_eq!(memory.read_byte(High(0x20001)).unwrap(), 0xCD);
+        assert_eq!(memory.read_word(High(0x20000)).unwrap(), 0xABCD);
+        assert!(memory.read_byte(High(0x20002)).is_err());
+        assert_eq!(High(0xFFFF).offset_byte(2).unwrap(), High(0x10001));
+    }
 }
@@ -1,8 +1,8 @@
 use crate::{
     zmachine::ZMachineHeader,
-    zmemory::{ZMemory, ZMemoryAddress},
+    zmemory::{ZMemory, ZMemoryAddress, ZMemoryAddress::*},
     zstring::ZString,
-    ZMachineVersion, ZmResult,
+    ZMachineVersion, ZmError, ZmResult,
 };
 
 /// The objects table, held in dynamic memory.
@@ -15,41 +15,333 @@ use crate::{
 pub struct ZObjectsTable {
     /// Stored at address 0x0A in the header (see R12.1).
     address: ZMemoryAddress,
-    /// Property defaults table (see R12.2).
+    /// Number of words in the property defaults table (see R12.2), which starts the objects table.
     ///
     /// Contains 31 words (2 bytes) from V1 to V3 included, and 63 in V4+.
     /// When the game attempts to read the value of property n for an object which
     /// does not provide property n, the n-th entry in this table is the resulting value.
-    property_defaults: Vec<u16>,
+    property_defaults_count: u16,
+    /// The targeted Z-machine version, which determines the layout of the object entries.
+    version: ZMachineVersion,
 }
 
 impl ZObjectsTable {
     pub fn from_memory_and_header(memory: &ZMemory, header: &ZMachineHeader) -> ZmResult<Self> {
         // retrieve the table address
         let address = header.get_location_object_table();
-        // read the property defaults table memory block
-        let property_defaults_size: u16 = if header.get_version() >= ZMachineVersion::V4 {
+        let version = header.get_version();
+        // the property defaults are read on demand, since the game may change them
+        let property_defaults_count: u16 = if version >= ZMachineVersion::V4 {
             63
         } else {
             31
         };
-        let mut property_defaults = Vec::with_capacity(property_defaults_size as usize);
-        let address_as_byte = address.as_byte()?;
-        for word_address in
-            (address_as_byte..address_as_byte + property_defaults_size * 2).step_by(2)
-        {
-            property_defaults.push(memory.read_word(ZMemoryAddress::Word(word_address))?);
-        }
+        // make sure the whole property defaults table is addressable
+        memory.read_word(address.offset_word(2 * (property_defaults_count - 1))?)?;
 
         Ok(Self {
             address,
-            property_defaults,
+            version,
+            property_defaults_count,
         })
     }
+
+    /// Returns the default value of the given property (R12.2), starting at 1.
+    pub fn get_property_default(&self, memory: &ZMemory, property: u16) -> ZmResult<u16> {
+        self.check_property_number(property)?;
+        memory.read_word(self.address.offset_word(2 * (property - 1))?)
+    }
+
+    fn check_property_number(&self, property: u16) -> ZmResult<()> {
+        if property == 0 || property > self.property_defaults_count {
+            Err(ZmError::ObjectInvalidProperty(property))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the address of the given object's entry in the table (R12.3).
+    ///
+    /// Objects are numbered from 1 upward, 0 meaning "nothing".
+    /// The whole entry must fit in the bottom 64K, so that its fields can be addressed.
+    fn entry_address(&self, object: u16) -> ZmResult<u16> {
+        let (entry_size, max_count) = if self.version >= ZMachineVersion::V4 {
+            (ZOBJECT_MODERN_SIZE, ZOBJECT_MODERN_MAX_COUNT)
+        } else {
+            (ZOBJECT_LEGACY_SIZE, ZOBJECT_LEGACY_MAX_COUNT)
+        };
+        if object == 0 || object > max_count {
+            return Err(ZmError::ObjectInvalidNumber(object));
+        }
+        let entries_address =
+            self.address.as_byte()? as u32 + 2 * self.property_defaults_count as u32;
+        let address = entries_address + (object as u32 - 1) * entry_size as u32;
+        if address + entry_size as u32 > 0x1_0000 {
+            return Err(ZmError::ObjectInvalidNumber(object));
+        }
+        Ok(address as u16)
+    }
+
+    fn attributes_count(&self) -> u16 {
+        if self.version >= ZMachineVersion::V4 {
+            ZOBJECT_MODERN_ATTRIBUTES_COUNT
+        } else {
+            ZOBJECT_LEGACY_ATTRIBUTES_COUNT
+        }
+    }
+
+    /// Returns the address of the byte holding the given attribute of an object,
+    /// along with the attribute's bit mask in that byte.
+    ///
+    /// R12.3.1: attribute 0 is stored in the highest bit of the first byte of the entry.
+    fn attribute_location(&self, object: u16, attribute: u16) -> ZmResult<(u16, u8)> {
+        if attribute >= self.attributes_count() {
+            return Err(ZmError::ObjectInvalidAttribute(attribute));
+        }
+        let address = self.entry_address(object)? + attribute / 8;
+        Ok((address, 0b_1000_0000 >> (attribute % 8)))
+    }
+
+    pub fn get_attribute(&self, memory: &ZMemory, object: u16, attribute: u16) -> ZmResult<bool> {
+        let (address, mask) = self.attribute_location(object, attribute)?;
+        Ok(memory.read_byte(Byte(address))? & mask != 0)
+    }
+
+    pub fn set_attribute(
+        &self,
+        memory: &mut ZMemory,
+        object: u16,
+        attribute: u16,
+        value: bool,
+    ) -> ZmResult<()> {
+        let (address, mask) = self.attribute_location(object, attribute)?;
+        let byte = memory.read_byte(Byte(address))?;
+        memory.store_byte(
+            Byte(address),
+            if value { byte | mask } else { byte & !mask },
+        )
+    }
+
+    /// R12.3.1: tree links are bytes from V1 to V3, and words in V4+ (after 6 bytes of attributes).
+    fn read_link(&self, memory: &ZMemory, object: u16, link: ZObjectLink) -> ZmResult<u16> {
+        let entry_address = self.entry_address(object)?;
+        if self.version >= ZMachineVersion::V4 {
+            memory.read_word(Word(entry_address + 6 + 2 * link as u16))
+        } else {
+            Ok(memory.read_byte(Byte(entry_address + 4 + link as u16))? as u16)
+        }
+    }
+
+    fn write_link(
+        &self,
+        memory: &mut ZMemory,
+        object: u16,
+        link: ZObjectLink,
+        value: u16,
+    ) -> ZmResult<()> {
+        let entry_address = self.entry_address(object)?;
+        if self.version >= ZMachineVersion::V4 {
+            memory.store_word(Word(entry_address + 6 + 2 * link as u16), value)
+        } else {
+            memory.store_byte(Byte(entry_address + 4 + link as u16), value as u8)
+        }
+    }
+
+    pub fn get_parent(&self, memory: &ZMemory, object: u16) -> ZmResult<u16> {
+        self.read_link(memory, object, ZObjectLink::Parent)
+    }
+
+    pub fn get_sibling(&self, memory: &ZMemory, object: u16) -> ZmResult<u16> {
+        self.read_link(memory, object, ZObjectLink::Sibling)
+    }
+
+    pub fn get_child(&self, memory: &ZMemory, object: u16) -> ZmResult<u16> {
+        self.read_link(memory, object, ZObjectLink::Child)
+    }
+
+    /// Detaches the object from its parent, if any, along with its own children (R12.3).
+    ///
+    /// The object's previous sibling (or its parent, if the object was the first child)
+    /// is relinked to the object's next sibling, and the object is left without parent or sibling.
+    pub fn remove_object(&self, memory: &mut ZMemory, object: u16) -> ZmResult<()> {
+        let parent = self.get_parent(memory, object)?;
+        if parent == 0 {
+            return Ok(());
+        }
+        let sibling = self.get_sibling(memory, object)?;
+        let first_child = self.get_child(memory, parent)?;
+        if first_child == object {
+            self.write_link(memory, parent, ZObjectLink::Child, sibling)?;
+        } else {
+            let mut previous = first_child;
+            loop {
+                if previous == 0 {
+                    return Err(ZmError::ObjectCorruptedTree(object));
+                }
+                let next = self.get_sibling(memory, previous)?;
+                if next == object {
+                    break;
+                }
+                previous = next;
+            }
+            self.write_link(memory, previous, ZObjectLink::Sibling, sibling)?;
+        }
+        self.write_link(memory, object, ZObjectLink::Parent, 0)?;
+        self.write_link(memory, object, ZObjectLink::Sibling, 0)
+    }
+
+    /// Moves the object to become the first child of the destination object (R12.3).
+    ///
+    /// The object is first removed from its current parent, and keeps its own children.
+    pub fn insert_object(
+        &self,
+        memory: &mut ZMemory,
+        object: u16,
+        destination: u16,
+    ) -> ZmResult<()> {
+        // validate the destination before touching the tree
+        self.entry_address(destination)?;
+        self.remove_object(memory, object)?;
+        let first_child = self.get_child(memory, destination)?;
+        self.write_link(memory, object, ZObjectLink::Sibling, first_child)?;
+        self.write_link(memory, object, ZObjectLink::Parent, destination)?;
+        self.write_link(memory, destination, ZObjectLink::Child, object)
+    }
+
+    /// Returns a snapshot of the given object's entry.
+    pub fn get_object(&self, memory: &ZMemory, object: u16) -> ZmResult<ZObject> {
+        let address = self.entry_address(object)?;
+        ZObject::from_memory(memory, object, Byte(address), self.version)
+    }
+
+    /// Returns the address of the given object's property table (R12.4).
+    pub fn get_properties_address(&self, memory: &ZMemory, object: u16) -> ZmResult<u16> {
+        let offset = if self.version >= ZMachineVersion::V4 {
+            12
+        } else {
+            7
+        };
+        memory.read_word(Word(self.entry_address(object)? + offset))
+    }
+
+    /// Returns the short name of the given object, from the header of its property table (R12.4).
+    pub fn get_short_name(&self, memory: &ZMemory, object: u16) -> ZmResult<ZString> {
+        let properties_address = self.get_properties_address(memory, object)?;
+        ZObject::read_short_name(memory, properties_address)
+    }
+
+    /// Looks up the given property of an object, if it provides it.
+    pub fn find_property(
+        &self,
+        memory: &ZMemory,
+        object: u16,
+        property: u16,
+    ) -> ZmResult<Option<ZObjectProperty>> {
+        self.check_property_number(property)?;
+        let properties_address = self.get_properties_address(memory, object)?;
+        ZObjectProperty::find(memory, properties_address, property as u8, self.version)
+    }
+
+    /// Reads the given property of an object, or its default value if it does not provide it.
+    ///
+    /// R15 (`get_prop`): a property of length 1 is a byte value, and of length 2 a word value.
+    /// Reading longer properties is unspecified: the first word is returned.
+    pub fn get_property_value(
+        &self,
+        memory: &ZMemory,
+        object: u16,
+        property: u16,
+    ) -> ZmResult<u16> {
+        match self.find_property(memory, object, property)? {
+            Some(found) if found.get_length() == 1 => {
+                Ok(memory.read_byte(Byte(found.get_data_address()))? as u16)
+            }
+            Some(found) => memory.read_word(Word(found.get_data_address())),
+            None => self.get_property_default(memory, property),
+        }
+    }
+
+    /// Writes the given property of an object, which must provide it.
+    ///
+    /// R15 (`put_prop`): a property of length 1 receives the least significant byte of the value.
+    pub fn put_property_value(
+        &self,
+        memory: &mut ZMemory,
+        object: u16,
+        property: u16,
+        value: u16,
+    ) -> ZmResult<()> {
+        match self.find_property(memory, object, property)? {
+            Some(found) if found.get_length() == 1 => {
+                memory.store_byte(Byte(found.get_data_address()), value as u8)
+            }
+            Some(found) => memory.store_word(Word(found.get_data_address()), value),
+            None => Err(ZmError::ObjectMissingProperty(object, property)),
+        }
+    }
+
+    /// Returns the byte address of the given property's data, or 0 if the object does not provide it.
+    pub fn get_property_address(
+        &self,
+        memory: &ZMemory,
+        object: u16,
+        property: u16,
+    ) -> ZmResult<u16> {
+        Ok(self
+            .find_property(memory, object, property)?
+            .map_or(0, |found| found.get_data_address()))
+    }
+
+    /// Returns the length of the property whose data starts at the given address.
+    ///
+    /// R15 (`get_prop_len`): the length of the property at address 0 is 0.
+    pub fn get_property_length(&self, memory: &ZMemory, data_address: u16) -> ZmResult<u16> {
+        if data_address == 0 {
+            return Ok(0);
+        }
+        ZObjectProperty::length_before_data(memory, data_address, self.version).map(|l| l as u16)
+    }
+
+    /// Returns the number of the property following the given one in the object's property list,
+    /// or 0 if it is the last one.
+    ///
+    /// R15 (`get_next_prop`): property 0 means returning the first property of the object.
+    /// It is illegal to ask for the next property of a property the object does not provide.
+    pub fn get_next_property(&self, memory: &ZMemory, object: u16, property: u16) -> ZmResult<u16> {
+        let next_address = if property == 0 {
+            ZObjectProperty::first_address(memory, self.get_properties_address(memory, object)?)?
+        } else {
+            self.find_property(memory, object, property)?
+                .ok_or(ZmError::ObjectMissingProperty(object, property))?
+                .get_next_address()
+        };
+        Ok(
+            ZObjectProperty::from_memory(Byte(next_address), memory, self.version)?
+                .map_or(0, |next| next.get_number() as u16),
+        )
+    }
+}
+
+/// The tree links of an object entry, valued by their offset from the parent link.
+#[derive(Copy, Clone)]
+enum ZObjectLink {
+    Parent = 0,
+    Sibling = 1,
+    Child = 2,
 }
 
 /// Size of objects from V1 to V3 included, in bytes.
 pub const ZOBJECT_LEGACY_SIZE: u16 = 9;
+/// Maximum number of objects from V1 to V3 included.
+pub const ZOBJECT_LEGACY_MAX_COUNT: u16 = 255;
+/// Number of attributes of objects from V1 to V3 included.
+pub const ZOBJECT_LEGACY_ATTRIBUTES_COUNT: u16 = 32;
+/// Size of objects in V4+, in bytes.
+pub const ZOBJECT_MODERN_SIZE: u16 = 14;
+/// Maximum number of objects in V4+.
+pub const ZOBJECT_MODERN_MAX_COUNT: u16 = 65535;
+/// Number of attributes of objects in V4+.
+pub const ZOBJECT_MODERN_ATTRIBUTES_COUNT: u16 = 48;
 
 /// Objects are stored in a tree-like fashion where each object has a parent,
 /// a sibling (the next child of the parent) and children.
@@ -65,6 +357,13 @@ pub const ZOBJECT_LEGACY_SIZE: u16 = 9;
 /// ---32 bits in 4 bytes---   ---3 bytes------------------  ---2 bytes--
 /// ```
 ///
+/// R12.3.2: in V4+, there are at most 65535 objects, each having a 14-byte entry as follows:
+///
+/// ```md
+/// the 48 attribute flags     parent    sibling   child     properties
+/// ---48 bits in 6 bytes---   ---3 words, i.e. 6 bytes----  ---2 bytes--
+/// ```
+///
 /// R12.4: each object has its own property table.
 /// Each of these can be anywhere in dynamic memory (indeed, a game can legally change an object's
 /// properties table address in play, provided the new address points to another valid properties table).
@@ -80,50 +379,112 @@ pub const ZOBJECT_LEGACY_SIZE: u16 = 9;
 /// After the header, the properties are listed in descending numerical order.
 /// (This order is essential and is not a matter of convention.)
 pub struct ZObject {
-    attribute_flags: u32,
-    address: ZMemoryAddress,
-    index: u8,
-    parent_index: u8,
-    sibling_index: u8,
-    child_index: u8,
-    text_length: Option<usize>,
+    /// The attribute flags, attribute 0 being the highest bit: 32 are used up to V3, 48 in V4+.
+    attribute_flags: u64,
+    index: u16,
+    parent_index: u16,
+    sibling_index: u16,
+    child_index: u16,
+    /// Address of the property table.
+    properties_address: u16,
     text: Option<ZString>,
-    properties: Vec<ZObjectProperty>,
+    version: ZMachineVersion,
 }
 
 impl ZObject {
     pub fn from_memory(
-        &self,
         memory: &ZMemory,
-        index: u8,
+        index: u16,
         address: ZMemoryAddress,
         version: ZMachineVersion,
     ) -> ZmResult<Self> {
-        if version > ZMachineVersion::V3 {
-            let text_length = memory.read_byte(address);
-            let text = ZString::new(memory, address.offset_byte(1)?)?;
-            todo!()
+        let read_word = |offset: u16| memory.read_word(address.offset_word(offset)?);
+        let read_byte = |offset: u16| memory.read_byte(address.offset_byte(offset)?);
+        let (attribute_flags, parent_index, sibling_index, child_index, properties_address) =
+            if version >= ZMachineVersion::V4 {
+                (
+                    ((read_word(0)? as u64) << 32)
+                        | ((read_word(2)? as u64) << 16)
+                        | read_word(4)? as u64,
+                    read_word(6)?,
+                    read_word(8)?,
+                    read_word(10)?,
+                    read_word(12)?,
+                )
+            } else {
+                (
+                    ((read_word(0)? as u64) << 16) | read_word(2)? as u64,
+                    read_byte(4)? as u16,
+                    read_byte(5)? as u16,
+                    read_byte(6)? as u16,
+                    read_word(7)?,
+                )
+            };
+        Ok(Self {
+            attribute_flags,
+            index,
+            parent_index,
+            sibling_index,
+            child_index,
+            properties_address,
+            text: Some(Self::read_short_name(memory, properties_address)?),
+            version,
+        })
+    }
+
+    pub fn get_index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn get_parent_index(&self) -> u16 {
+        self.parent_index
+    }
+
+    pub fn get_sibling_index(&self) -> u16 {
+        self.sibling_index
+    }
+
+    pub fn get_child_index(&self) -> u16 {
+        self.child_index
+    }
+
+    /// Is the given attribute set, in this snapshot of the object?
+    pub fn has_attribute(&self, attribute: u16) -> bool {
+        let count = if self.version >= ZMachineVersion::V4 {
+            ZOBJECT_MODERN_ATTRIBUTES_COUNT
+        } else {
+            ZOBJECT_LEGACY_ATTRIBUTES_COUNT
+        };
+        attribute < count && self.attribute_flags & (1 << (count - 1 - attribute)) != 0
+    }
+
+    pub fn get_properties_address(&self) -> u16 {
+        self.properties_address
+    }
+
+    pub fn get_short_name(&self) -> Option<&ZString> {
+        self.text.as_ref()
+    }
+
+    /// Reads the short name held in the header of the given property table,
+    /// which is empty if its text-length is 0.
+    fn read_short_name(memory: &ZMemory, properties_address: u16) -> ZmResult<ZString> {
+        if memory.read_byte(Byte(properties_address))? == 0 {
+            Ok(ZString::default())
         } else {
-            Ok(Self {
-                attribute_flags: ((memory.read_word(address.offset_word(0)?)? as u32) << 16)
-                    | (memory.read_word(address.offset_word(2)?)? as u32),
-                address,
-                index,
-                parent_index: memory.read_byte(address.offset_byte(4)?)?,
-                sibling_index: memory.read_byte(address.offset_byte(5)?)?,
-                child_index: memory.read_byte(address.offset_byte(6)?)?,
-                text_length: None,
-                text: None,
-                properties: vec![
-                    ZObjectProperty::from_memory(1, address.offset_byte(7)?, memory, version)?,
-                    ZObjectProperty::from_memory(2, address.offset_byte(8)?, memory, version)?,
-                ],
-            })
-        }
-    }
-
-    pub fn get_property(&self, objects_table: &ZObjectsTable) -> ZmResult<ZObjectProperty> {
-        todo!()
+            ZString::new(memory, Byte(properties_address.wrapping_add(1)))
+        }
+    }
+
+    /// Looks up the given property in the object's property table, if it provides it.
+    ///
+    /// Properties are read from memory, so that any change made by the game is reflected.
+    pub fn get_property(
+        &self,
+        memory: &ZMemory,
+        property: u8,
+    ) -> ZmResult<Option<ZObjectProperty>> {
+        ZObjectProperty::find(memory, self.properties_address, property, self.version)
     }
 }
 
@@ -166,39 +527,370 @@ impl ZObject {
 /// bit 6 is either clear to indicate a property data length of 1, or set to indicate a length of 2;
 /// bit 7 is clear.
 pub struct ZObjectProperty {
+    /// Address of the first size byte.
     address: ZMemoryAddress,
     index: u8,
     /// V1, V2 and V3: between 1 and 8 bytes of data.
     /// V4*: between 1 and 64 bytes of data.
     length: u8,
-    data: Vec<u8>,
+    /// Address of the first byte of data.
+    data_address: u16,
 }
 
 /// R12.4
 impl ZObjectProperty {
-    /// NB: parameter `property_number` starts at 1.
+    /// Decodes the property block starting at the given address.
+    ///
+    /// Returns `None` when reaching the size byte of 0 terminating a property list.
     pub fn from_memory(
-        property_number: u8,
         address: ZMemoryAddress,
         memory: &ZMemory,
         version: ZMachineVersion,
-    ) -> ZmResult<Self> {
-        if version > ZMachineVersion::V3 {
-            todo!()
+    ) -> ZmResult<Option<Self>> {
+        let size_byte = memory.read_byte(address)?;
+        if size_byte == 0 {
+            return Ok(None);
+        }
+        let address_as_byte = address.as_byte()?;
+        let (index, length, size_bytes_count) = if version <= ZMachineVersion::V3 {
+            (size_byte & 0b_0001_1111, (size_byte >> 5) + 1, 1)
+        } else if size_byte & 0b_1000_0000 != 0 {
+            let second_size_byte = memory.read_byte(Byte(address_as_byte.wrapping_add(1)))?;
+            let length = match second_size_byte & 0b_0011_1111 {
+                0 => 64,
+                length => length,
+            };
+            (size_byte & 0b_0011_1111, length, 2)
+        } else {
+            let length = if size_byte & 0b_0100_0000 != 0 { 2 } else { 1 };
+            (size_byte & 0b_0011_1111, length, 1)
+        };
+        Ok(Some(Self {
+            address,
+            index,
+            length,
+            data_address: address_as_byte.wrapping_add(size_bytes_count),
+        }))
+    }
+
+    /// Returns the address of the first property block of the given property table,
+    /// right after its header holding the object's short name.
+    pub fn first_address(memory: &ZMemory, properties_address: u16) -> ZmResult<u16> {
+        let text_length = memory.read_byte(Byte(properties_address))? as u16;
+        Ok(properties_address.wrapping_add(1 + 2 * text_length))
+    }
+
+    /// Walks the given property table to find a property.
+    ///
+    /// Since properties are stored in descending numerical order, the search stops
+    /// as soon as a lower property number is found.
+    pub fn find(
+        memory: &ZMemory,
+        properties_address: u16,
+        property_number: u8,
+        version: ZMachineVersion,
+    ) -> ZmResult<Option<Self>> {
+        let mut address = Self::first_address(memory, properties_address)?;
+        while let Some(property) = Self::from_memory(Byte(address), memory, version)? {
+            if property.index == property_number {
+                return Ok(Some(property));
+            } else if property.index < property_number {
+                break;
+            }
+            address = property.get_next_address();
+        }
+        Ok(None)
+    }
+
+    /// Returns the length of the property whose data starts at the given address,
+    /// by decoding the size byte right before it.
+    ///
+    /// R12.4.2.1: in V4+, the byte before the data is either the second size byte
+    /// (with its top bit set) or the only one.
+    pub fn length_before_data(
+        memory: &ZMemory,
+        data_address: u16,
+        version: ZMachineVersion,
+    ) -> ZmResult<u8> {
+        let size_byte = memory.read_byte(Byte(data_address.wrapping_sub(1)))?;
+        Ok(if version <= ZMachineVersion::V3 {
+            (size_byte >> 5) + 1
+        } else if size_byte & 0b_1000_0000 != 0 {
+            match size_byte & 0b_0011_1111 {
+                0 => 64,
+                length => length,
+            }
+        } else if size_byte & 0b_0100_0000 != 0 {
+            2
         } else {
-            let size_byte = memory.read_byte(address)?;
-            let length = (size_byte - property_number) / 32;
-            debug_assert!(1 <= length && length <= 8);
-            let mut data = vec![];
-            for offset in 1..=(length as u16) {
-                data.push(memory.read_byte(address.offset_byte(offset)?)?);
+            1
+        })
+    }
+
+    pub fn get_address(&self) -> ZMemoryAddress {
+        self.address
+    }
+
+    /// NB: property numbers start at 1.
+    pub fn get_number(&self) -> u8 {
+        self.index
+    }
+
+    pub fn get_length(&self) -> u8 {
+        self.length
+    }
+
+    pub fn get_data_address(&self) -> u16 {
+        self.data_address
+    }
+
+    /// Returns the address of the next property block.
+    pub fn get_next_address(&self) -> u16 {
+        self.data_address.wrapping_add(self.length as u16)
+    }
+
+    /// Reads the property data from memory.
+    pub fn read_data(&self, memory: &ZMemory) -> ZmResult<Vec<u8>> {
+        (0..self.length as u16)
+            .map(|offset| memory.read_byte(Byte(self.data_address.wrapping_add(offset))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE_ADDRESS: u16 = 0x100;
+
+    /// Build a V3 story whose object 1 has the children 2, 3 and 4, in that order.
+    fn init_table_and_memory() -> (ZObjectsTable, ZMemory) {
+        let mut story = vec![0u8; 0x400];
+        story[0x00] = 3;
+        story[0x0A] = (TABLE_ADDRESS >> 8) as u8;
+        story[0x0B] = TABLE_ADDRESS as u8;
+        let entries = (TABLE_ADDRESS + 31 * 2) as usize;
+        let links: [(u8, u8, u8); 4] = [(0, 0, 2), (1, 3, 0), (1, 4, 0), (1, 0, 0)];
+        for (index, (parent, sibling, child)) in links.iter().enumerate() {
+            let entry = entries + index * ZOBJECT_LEGACY_SIZE as usize;
+            story[entry + 4] = *parent;
+            story[entry + 5] = *sibling;
+            story[entry + 6] = *child;
+        }
+        // object 2's property table at 0x200, with properties 18 (length 2), 5 (length 1)
+        // and 3 (length 4), and 0x0707 as the default value of property 7
+        let properties_entry = entries + ZOBJECT_LEGACY_SIZE as usize + 7;
+        story[properties_entry] = 0x02;
+        #[rustfmt::skip]
+        story[0x200..0x20F].copy_from_slice(&[
+            0x01, 0x94, 0xA5, // short name "a"
+            0x32, 0x12, 0x34, // property 18
+            0x05, 0x42, // property 5
+            0x63, 0x01, 0x02, 0x03, 0x04, // property 3
+            0x00,
+            0x00,
+        ]);
+        story[(TABLE_ADDRESS + 2 * 6) as usize] = 0x07;
+        story[(TABLE_ADDRESS + 2 * 6 + 1) as usize] = 0x07;
+        let memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let table = ZObjectsTable::from_memory_and_header(&memory, &header).unwrap();
+        (table, memory)
+    }
+
+    fn links(table: &ZObjectsTable, memory: &ZMemory, object: u16) -> (u16, u16, u16) {
+        (
+            table.get_parent(memory, object).unwrap(),
+            table.get_sibling(memory, object).unwrap(),
+            table.get_child(memory, object).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_attributes() {
+        let (table, mut memory) = init_table_and_memory();
+        table.set_attribute(&mut memory, 2, 0, true).unwrap();
+        table.set_attribute(&mut memory, 2, 31, true).unwrap();
+        assert!(table.get_attribute(&memory, 2, 0).unwrap());
+        assert!(table.get_attribute(&memory, 2, 31).unwrap());
+        assert!(!table.get_attribute(&memory, 2, 1).unwrap());
+        let entry = TABLE_ADDRESS + 31 * 2 + ZOBJECT_LEGACY_SIZE;
+        assert_eq!(memory.read_byte(Byte(entry)).unwrap(), 0x80);
+        assert_eq!(memory.read_byte(Byte(entry + 3)).unwrap(), 0x01);
+        table.set_attribute(&mut memory, 2, 0, false).unwrap();
+        assert!(!table.get_attribute(&memory, 2, 0).unwrap());
+        assert!(matches!(
+            table.get_attribute(&memory, 2, 32),
+            Err(ZmError::ObjectInvalidAttribute(32))
+        ));
+    }
+
+    #[test]
+    fn test_tree_relinking() {
+        let (table, mut memory) = init_table_and_memory();
+        // remove a middle child
+        table.remove_object(&mut memory, 3).unwrap();
+        assert_eq!(links(&table, &memory, 3), (0, 0, 0));
+        assert_eq!(links(&table, &memory, 2), (1, 4, 0));
+        // remove the first child
+        table.remove_object(&mut memory, 2).unwrap();
+        assert_eq!(links(&table, &memory, 1), (0, 0, 4));
+        // insert a parentless object, then move an object with siblings
+        table.insert_object(&mut memory, 3, 4).unwrap();
+        assert_eq!(links(&table, &memory, 4), (1, 0, 3));
+        assert_eq!(links(&table, &memory, 3), (4, 0, 0));
+        table.insert_object(&mut memory, 2, 1).unwrap();
+        table.insert_object(&mut memory, 4, 2).unwrap();
+        assert_eq!(links(&table, &memory, 1), (0, 0, 2));
+        assert_eq!(links(&table, &memory, 2), (1, 0, 4));
+        assert_eq!(links(&table, &memory, 4), (2, 0, 3));
+        // re-inserting into the same parent moves the object first
+        table.insert_object(&mut memory, 3, 2).unwrap();
+        table.insert_object(&mut memory, 4, 2).unwrap();
+        assert_eq!(links(&table, &memory, 2), (1, 0, 4));
+        assert_eq!(links(&table, &memory, 4), (2, 3, 0));
+        assert_eq!(links(&table, &memory, 3), (2, 0, 0));
+    }
+
+    #[test]
+    fn test_object_zero() {
+        let (table, mut memory) = init_table_and_memory();
+        assert!(matches!(
+            table.get_parent(&memory, 0),
+            Err(ZmError::ObjectInvalidNumber(0))
+        ));
+        assert!(matches!(
+            table.insert_object(&mut memory, 2, 0),
+            Err(ZmError::ObjectInvalidNumber(0))
+        ));
+        assert_eq!(links(&table, &memory, 2), (1, 3, 0));
+    }
+
+    #[test]
+    fn test_properties() {
+        let (table, mut memory) = init_table_and_memory();
+        assert_eq!(table.get_property_value(&memory, 2, 18).unwrap(), 0x1234);
+        assert_eq!(table.get_property_value(&memory, 2, 5).unwrap(), 0x42);
+        assert_eq!(table.get_property_value(&memory, 2, 7).unwrap(), 0x0707);
+        assert!(matches!(
+            table.get_property_value(&memory, 2, 32),
+            Err(ZmError::ObjectInvalidProperty(32))
+        ));
+
+        table.put_property_value(&mut memory, 2, 5, 0xABCD).unwrap();
+        table
+            .put_property_value(&mut memory, 2, 18, 0xABCD)
+            .unwrap();
+        assert_eq!(table.get_property_value(&memory, 2, 5).unwrap(), 0xCD);
+        assert_eq!(table.get_property_value(&memory, 2, 18).unwrap(), 0xABCD);
+        assert!(matches!(
+            table.put_property_value(&mut memory, 2, 7, 0),
+            Err(ZmError::ObjectMissingProperty(2, 7))
+        ));
+
+        let address = table.get_property_address(&memory, 2, 3).unwrap();
+        assert_eq!(address, 0x209);
+        assert_eq!(table.get_property_length(&memory, address).unwrap(), 4);
+        assert_eq!(table.get_property_address(&memory, 2, 7).unwrap(), 0);
+        assert_eq!(table.get_property_length(&memory, 0).unwrap(), 0);
+
+        let mut next_properties = vec![];
+        let mut property = 0;
+        loop {
+            property = table.get_next_property(&memory, 2, property).unwrap();
+            next_properties.push(property);
+            if property == 0 {
+                break;
             }
-            Ok(Self {
-                address,
-                index: property_number,
-                length,
-                data,
-            })
         }
+        assert_eq!(next_properties, [18, 5, 3, 0]);
+    }
+
+    #[test]
+    fn test_modern_property_blocks() {
+        #[rustfmt::skip]
+        let mut story = vec![
+            0x00, // no short name
+            0x8A, 0x80, // property 10, two size bytes with a length of 0 meaning 64
+        ];
+        story.extend_from_slice(&[0xFF; 64]);
+        story.extend_from_slice(&[0x47, 0x12, 0x34, 0x03, 0x56, 0x00]);
+        let memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+
+        let first = ZObjectProperty::from_memory(Byte(1), &memory, ZMachineVersion::V5)
+            .unwrap()
+            .unwrap();
+        assert_eq!((first.get_number(), first.get_length()), (10, 64));
+        assert_eq!(first.get_data_address(), 3);
+        let second = ZObjectProperty::find(&memory, 0, 7, ZMachineVersion::V5)
+            .unwrap()
+            .unwrap();
+        assert_eq!((second.get_number(), second.get_length()), (7, 2));
+        assert_eq!(second.read_data(&memory).unwrap(), [0x12, 0x34]);
+        let third = ZObjectProperty::find(&memory, 0, 3, ZMachineVersion::V5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(third.get_length(), 1);
+        assert!(ZObjectProperty::find(&memory, 0, 5, ZMachineVersion::V5)
+            .unwrap()
+            .is_none());
+
+        for property in [&first, &second, &third] {
+            assert_eq!(
+                ZObjectProperty::length_before_data(
+                    &memory,
+                    property.get_data_address(),
+                    ZMachineVersion::V5
+                )
+                .unwrap(),
+                property.get_length()
+            );
+        }
+    }
+
+    #[test]
+    fn test_modern_objects() {
+        let mut story = vec![0u8; 0x2000];
+        story[0x00] = 5;
+        story[0x0A] = (TABLE_ADDRESS >> 8) as u8;
+        story[0x0B] = TABLE_ADDRESS as u8;
+        let entry = |object: usize| (TABLE_ADDRESS + 63 * 2) as usize + (object - 1) * 14;
+        // object 1 has object 300 as its only child
+        story[entry(1) + 10..entry(1) + 12].copy_from_slice(&[0x01, 0x2C]);
+        story[entry(300) + 6..entry(300) + 8].copy_from_slice(&[0x00, 0x01]);
+        story[entry(300) + 12..entry(300) + 14].copy_from_slice(&[0x1F, 0x00]);
+        let mut memory = ZMemory::from_story_reader(&mut &story[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let table = ZObjectsTable::from_memory_and_header(&memory, &header).unwrap();
+
+        assert_eq!(links(&table, &memory, 1), (0, 0, 300));
+        assert_eq!(links(&table, &memory, 300), (1, 0, 0));
+        table.set_attribute(&mut memory, 300, 47, true).unwrap();
+        assert_eq!(memory.read_byte(Byte(entry(300) as u16 + 5)).unwrap(), 0x01);
+        assert!(matches!(
+            table.get_attribute(&memory, 300, 48),
+            Err(ZmError::ObjectInvalidAttribute(48))
+        ));
+
+        table.insert_object(&mut memory, 300, 2).unwrap();
+        assert_eq!(links(&table, &memory, 1), (0, 0, 0));
+        assert_eq!(links(&table, &memory, 2), (0, 0, 300));
+        let object = table.get_object(&memory, 300).unwrap();
+        assert_eq!(object.get_index(), 300);
+        assert_eq!(object.get_parent_index(), 2);
+        assert_eq!(object.get_properties_address(), 0x1F00);
+        assert!(object.has_attribute(47));
+        assert!(!object.has_attribute(0));
+        assert!(object.get_short_name().unwrap().is_empty());
+
+        assert!(matches!(
+            table.get_parent(&memory, 0xFFFF),
+            Err(ZmError::ObjectInvalidNumber(0xFFFF))
+        ));
+        // object 4654's entry starts at 0xFFF4, but does not fit below 0x10000
+        assert!(matches!(
+            table.get_properties_address(&memory, 4654),
+            Err(ZmError::ObjectInvalidNumber(4654))
+        ));
     }
 }
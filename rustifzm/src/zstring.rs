@@ -1,6 +1,8 @@
+use std::cmp::Ordering;
+
 use crate::{
     zmachine::ZMachineHeader,
-    zmemory::{ZMemory, ZMemoryAddress},
+    zmemory::{ZMemory, ZMemoryAddress, ZMemoryAddress::*},
     ZMachineVersion, ZmError, ZmResult,
 };
 
@@ -13,6 +15,7 @@ pub type ZCharacter = u8;
 ///
 /// Z-machine text is a sequence of ZSCII character codes (ZSCII is a system similar to ASCII: see R3.8).
 /// These ZSCII values are encoded into memory using a string of Z-characters.
+#[derive(Clone, Debug, Default)]
 pub struct ZString {
     /// R3.2: Text in memory consists of a sequence of 2-byte words. Each word is divided into three 5-bit 'Z-characters', plus 1 bit left over, arranged as
     ///
@@ -27,8 +30,121 @@ pub struct ZString {
 }
 
 impl ZString {
+    /// Read the encoded string starting at the given byte address, up to the word marked with the end bit.
     pub fn new(memory: &ZMemory, address: ZMemoryAddress) -> ZmResult<Self> {
-        todo!()
+        let mut address = address.as_offset()?;
+        Self::read_with(|| {
+            let next = memory.read_byte(High(address))?;
+            address = address.wrapping_add(1);
+            Ok(next)
+        })
+    }
+
+    /// Read an encoded string byte by byte, up to and including the word marked with the end bit.
+    pub fn read_with<F>(mut next_byte: F) -> ZmResult<Self>
+    where
+        F: FnMut() -> ZmResult<u8>,
+    {
+        let mut content = Vec::new();
+        loop {
+            let msb = next_byte()? as u16;
+            let lsb = next_byte()? as u16;
+            let word = (msb << 8) | lsb;
+            content.push(((word >> 10) & 0x1F) as ZCharacter);
+            content.push(((word >> 5) & 0x1F) as ZCharacter);
+            content.push((word & 0x1F) as ZCharacter);
+            if word & 0x8000 != 0 {
+                break;
+            }
+        }
+        Ok(Self { content })
+    }
+
+    /// Encode the given text the way dictionary entries store it (R3.7).
+    pub fn from_text(text: &str, codec: &ZStringCodec) -> ZmResult<Self> {
+        let codes = text
+            .chars()
+            .map(|character| ZSCII::from_char(character, &codec.unicode).map(|code| code.0))
+            .collect::<ZmResult<Vec<u16>>>()?;
+        Ok(Self::from_zscii(&codes, codec))
+    }
+
+    /// Encode the given ZSCII codes the way dictionary entries store them.
+    ///
+    /// R3.7: the text is truncated or padded (with Z-character 5) to exactly 6 Z-characters
+    /// in V1 to V3, and 9 in V4+. Characters missing from the alphabets are stored
+    /// as a 10-bit ZSCII escape sequence (R3.4).
+    pub fn from_zscii(codes: &[u16], codec: &ZStringCodec) -> Self {
+        let version = codec.version;
+        let length = Self::dictionary_length(version) / 2 * 3;
+        let mut content = Vec::with_capacity(length + 3);
+        for &code in codes {
+            if content.len() >= length {
+                break;
+            }
+            match code {
+                32 => content.push(0),
+                // R3.5.2: in V1, Z-character 1 is a new-line
+                13 if version == ZMachineVersion::V1 => content.push(1),
+                _ => match codec.alphabet.find(code) {
+                    Some((ZAlphabet::A0LowerCase, z)) => content.push(z),
+                    Some((alphabet, z)) => {
+                        content.push(alphabet.shift_character(version));
+                        content.push(z);
+                    }
+                    None => {
+                        content.push(ZAlphabet::A2Punctuation.shift_character(version));
+                        content.push(6);
+                        content.push(((code >> 5) & 0x1F) as ZCharacter);
+                        content.push((code & 0x1F) as ZCharacter);
+                    }
+                },
+            }
+        }
+        content.resize(length, 5);
+        Self { content }
+    }
+
+    /// Number of bytes taken by an encoded dictionary word: 4 in V1 to V3, 6 in V4+.
+    pub fn dictionary_length(version: ZMachineVersion) -> usize {
+        if version <= ZMachineVersion::V3 {
+            4
+        } else {
+            6
+        }
+    }
+
+    /// Pack the Z-characters into 2-byte words, setting the end bit on the last one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let words = self.content.chunks(3).map(|chunk| {
+            chunk
+                .iter()
+                .chain(std::iter::repeat(&5))
+                .take(3)
+                .fold(0u16, |word, &z| (word << 5) | z as u16)
+        });
+        let count = self.content.len().div_ceil(3);
+        words
+            .enumerate()
+            .flat_map(|(i, word)| {
+                let word = if i + 1 == count { word | 0x8000 } else { word };
+                word.to_be_bytes()
+            })
+            .collect()
+    }
+
+    /// Compare this encoded string with the encoded text stored at the given address,
+    /// as a sequence of bytes (dictionary entries are sorted that way, see R13.6).
+    pub fn compare_encoded(&self, memory: &ZMemory, address: ZMemoryAddress) -> ZmResult<Ordering> {
+        let address = address.as_byte()?;
+        for (i, byte) in self.to_bytes().into_iter().enumerate() {
+            let other = memory.read_byte(Byte(address.wrapping_add(i as u16)))?;
+            match byte.cmp(&other) {
+                Ordering::Equal => continue,
+                ordering => return Ok(ordering),
+            }
+        }
+        Ok(Ordering::Equal)
     }
 
     /// Get the size of the string.
@@ -36,18 +152,121 @@ impl ZString {
         self.content.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
     /// Decode the string into UTF-8.
-    pub fn decode(
-        &self,
-        version: ZMachineVersion,
-        abbreviations_table: Option<&ZAbbreviationsTable>,
-    ) -> ZmResult<String> {
-        let mut alphabet = ZAlphabet::A0LowerCase;
+    ///
+    /// Abbreviations are expanded by decoding the strings they refer to,
+    /// which must not use abbreviations themselves (R3.3.1).
+    pub fn decode(&self, memory: &ZMemory, codec: &ZStringCodec) -> ZmResult<String> {
         let mut result = String::with_capacity(self.len());
+        self.decode_into(&mut result, memory, codec, false)?;
+        Ok(result)
+    }
 
-        // TODO:
+    fn decode_into(
+        &self,
+        result: &mut String,
+        memory: &ZMemory,
+        codec: &ZStringCodec,
+        in_abbreviation: bool,
+    ) -> ZmResult<()> {
+        use ZMachineVersion::*;
+        let version = codec.version;
+        // V1 and V2 may lock an alphabet (R3.2.2), V3+ always come back to A0 (R3.2.3)
+        let mut locked_alphabet = ZAlphabet::A0LowerCase;
+        let mut alphabet = locked_alphabet;
+        let mut characters = self.content.iter().cloned();
 
-        Ok(result)
+        while let Some(character) = characters.next() {
+            let current_alphabet = alphabet;
+            alphabet = locked_alphabet;
+            match character {
+                // R3.5.1: Z-character 0 is printed as a space
+                0 => result.push(' '),
+                // R3.5.2: in V1, Z-character 1 is printed as a new-line
+                1 if version == V1 => result.push('\n'),
+                // R3.3: abbreviations
+                1..=3 if version >= V3 || (version == V2 && character == 1) => {
+                    let next = match characters.next() {
+                        Some(next) => next,
+                        // an incomplete construction at the end of a string is ignored
+                        None => break,
+                    };
+                    if in_abbreviation {
+                        return Err(ZmError::StringNestedAbbreviation);
+                    }
+                    let table = codec
+                        .abbreviations
+                        .as_ref()
+                        .ok_or(ZmError::StringMissingAbbreviationsTable)?;
+                    let abbreviation =
+                        table.get_abbreviation(memory, 32 * (character - 1) + next)?;
+                    abbreviation.decode_into(result, memory, codec, true)?;
+                }
+                // R3.2.2: shift and shift lock characters (V1 and V2)
+                2..=5 if version <= V2 => {
+                    let (shifted, lock) = locked_alphabet.shifted_with_maybe_lock(character)?;
+                    if lock {
+                        locked_alphabet = shifted;
+                    }
+                    alphabet = shifted;
+                }
+                // R3.2.3: shift characters (V3+)
+                4 | 5 => alphabet = ZAlphabet::shifted(character)?,
+                // R3.4: a 10-bit ZSCII character code follows, top 5 bits first
+                6 if current_alphabet == ZAlphabet::A2Punctuation => {
+                    let (top, bottom) = match (characters.next(), characters.next()) {
+                        (Some(top), Some(bottom)) => (top, bottom),
+                        _ => break,
+                    };
+                    let code = ((top as u16) << 5) | bottom as u16;
+                    if let Some(decoded) = ZSCII(code).to_char(&codec.unicode)? {
+                        result.push(decoded);
+                    }
+                }
+                _ => {
+                    let code = codec.alphabet.get_zscii(current_alphabet, character);
+                    if let Some(decoded) = ZSCII(code).to_char(&codec.unicode)? {
+                        result.push(decoded);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The per-story tables needed to decode and encode text.
+pub struct ZStringCodec {
+    version: ZMachineVersion,
+    alphabet: ZAlphabetTable,
+    abbreviations: Option<ZAbbreviationsTable>,
+    unicode: ZUnicodeTable,
+}
+
+impl ZStringCodec {
+    pub fn from_memory_and_header(memory: &ZMemory, header: &ZMachineHeader) -> ZmResult<Self> {
+        Ok(Self {
+            version: header.get_version(),
+            alphabet: ZAlphabetTable::from_memory_and_header(memory, header)?,
+            abbreviations: ZAbbreviationsTable::from_memory_and_header(memory, header)?,
+            unicode: ZUnicodeTable::from_memory_and_header(memory, header)?,
+        })
+    }
+
+    pub fn get_version(&self) -> ZMachineVersion {
+        self.version
+    }
+
+    pub fn get_alphabet(&self) -> &ZAlphabetTable {
+        &self.alphabet
+    }
+
+    pub fn get_unicode_table(&self) -> &ZUnicodeTable {
+        &self.unicode
     }
 }
 
@@ -55,29 +274,20 @@ impl ZString {
 /// and during printing one of these is current at any given time.
 ///
 /// Initially A0 is current. The meaning of a Z-character may depend on which alphabet is current.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ZAlphabet {
     A0LowerCase,
     A1UpperCase,
     A2Punctuation,
 }
 
-const A0_CHARS: &[char; 32] = &[
-    ' ', ' ', ' ', ' ', ' ', ' ', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm',
-    'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z',
-];
-const A1_CHARS: &[char; 32] = &[
-    ' ', ' ', ' ', ' ', ' ', ' ', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M',
-    'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
-];
-const A2_CHARS: &[char; 32] = &[
-    ' ', ' ', ' ', ' ', ' ', ' ', ' ', '\n', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '.',
-    ',', '!', '?', '_', '#', '\'', '"', '/', '\\', '-', ':', '(', ')',
-];
-const A2_V1_CHARS: &[char; 32] = &[
-    ' ', ' ', ' ', ' ', ' ', ' ', ' ', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', '.', ',',
-    '!', '?', '_', '#', '\'', '"', '/', '\\', '<', '-', ':', '(', ')',
-];
+/// Default alphabet rows as ZSCII codes for Z-characters 6 to 31 (R3.5.3).
+///
+/// A2 character 6 is a placeholder for the 10-bit ZSCII escape (R3.4).
+const A0_DEFAULT: &[u8; 26] = b"abcdefghijklmnopqrstuvwxyz";
+const A1_DEFAULT: &[u8; 26] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const A2_DEFAULT: &[u8; 26] = b" \r0123456789.,!?_#'\"/\\-:()";
+const A2_V1_DEFAULT: &[u8; 26] = b" 0123456789.,!?_#'\"/\\<-:()";
 
 impl ZAlphabet {
     /// Returns the shifted `ZAlphabet` according to the given shifting character (V1 and V2).
@@ -99,7 +309,7 @@ impl ZAlphabet {
     /// ```
     /// Z-characters 4 and 5 permanently change alphabet, according to the same table, and are called 'shift lock' characters.
     pub fn shifted_with_maybe_lock(&self, shift_character: ZCharacter) -> ZmResult<(Self, bool)> {
-        match (shift_character) {
+        match shift_character {
             2 => Ok((self.next(), false)),
             3 => Ok((self.previous(), false)),
             4 => Ok((self.next(), true)),
@@ -126,7 +336,71 @@ impl ZAlphabet {
         }
     }
 
-    /// Get the UTF-8 character corresponding to the given `ZCharacter` and `ZMachineVersion`.
+    /// Returns the Z-character shifting from A0 to this alphabet for the next character only.
+    pub fn shift_character(&self, version: ZMachineVersion) -> ZCharacter {
+        match (self, version <= ZMachineVersion::V2) {
+            (ZAlphabet::A0LowerCase, _) => 0,
+            (ZAlphabet::A1UpperCase, true) => 2,
+            (ZAlphabet::A2Punctuation, true) => 3,
+            (ZAlphabet::A1UpperCase, false) => 4,
+            (ZAlphabet::A2Punctuation, false) => 5,
+        }
+    }
+
+    fn previous(&self) -> ZAlphabet {
+        match self {
+            ZAlphabet::A0LowerCase => ZAlphabet::A2Punctuation,
+            ZAlphabet::A1UpperCase => ZAlphabet::A0LowerCase,
+            ZAlphabet::A2Punctuation => ZAlphabet::A1UpperCase,
+        }
+    }
+
+    fn next(&self) -> ZAlphabet {
+        match self {
+            ZAlphabet::A0LowerCase => ZAlphabet::A1UpperCase,
+            ZAlphabet::A1UpperCase => ZAlphabet::A2Punctuation,
+            ZAlphabet::A2Punctuation => ZAlphabet::A0LowerCase,
+        }
+    }
+}
+
+/// The story's alphabet table, translating Z-characters 6 to 31 of each alphabet into ZSCII codes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZAlphabetTable {
+    rows: [[u8; 26]; 3],
+}
+
+impl ZAlphabetTable {
+    /// Returns the alphabet table defined by the Standard for the given version.
+    pub fn default_for_version(version: ZMachineVersion) -> Self {
+        let a2 = if version == ZMachineVersion::V1 {
+            A2_V1_DEFAULT
+        } else {
+            A2_DEFAULT
+        };
+        Self {
+            rows: [*A0_DEFAULT, *A1_DEFAULT, *a2],
+        }
+    }
+
+    /// R3.5.5: in V5+, header word 0x34 may hold the byte address of a custom alphabet table
+    /// of 78 ZSCII codes, giving the characters 6 to 31 of A0, then A1, then A2.
+    ///
+    /// R3.5.5.1: A2 character 6 remains the 10-bit escape and A2 character 7 a new-line,
+    /// whatever the table says.
+    pub fn from_memory_and_header(memory: &ZMemory, header: &ZMachineHeader) -> ZmResult<Self> {
+        let mut table = Self::default_for_version(header.get_version());
+        if let Some(address) = header.get_location_alphabet_table() {
+            for (offset, code) in table.rows.iter_mut().flatten().enumerate() {
+                *code = memory.read_byte(address.offset_byte(offset as u16)?)?;
+            }
+            table.rows[2][0] = b' ';
+            table.rows[2][1] = b'\r';
+        }
+        Ok(table)
+    }
+
+    /// Get the ZSCII code of the given `ZCharacter` (between 6 and 31) in the given alphabet.
     ///
     /// R3.5.3:
     /// In Versions 2 to 4, the alphabet table for converting Z-characters into ZSCII character codes is as follows:
@@ -154,30 +428,32 @@ impl ZAlphabet {
     ///   A2       0123456789.,!?_#'"/\<-:()
     ///           --------------------------
     /// ```
-    pub fn get_character(&self, char: ZCharacter, version: ZMachineVersion) -> char {
-        let char_index = char as usize;
-        match (self, version) {
-            (ZAlphabet::A0LowerCase, _) => A0_CHARS[char_index],
-            (ZAlphabet::A1UpperCase, _) => A1_CHARS[char_index],
-            (ZAlphabet::A2Punctuation, ZMachineVersion::V1) => A2_V1_CHARS[char_index],
-            (ZAlphabet::A2Punctuation, _) => A2_CHARS[char_index],
-        }
-    }
-
-    fn previous(&self) -> ZAlphabet {
-        match self {
-            ZAlphabet::A0LowerCase => ZAlphabet::A2Punctuation,
-            ZAlphabet::A1UpperCase => ZAlphabet::A0LowerCase,
-            ZAlphabet::A2Punctuation => ZAlphabet::A1UpperCase,
-        }
+    ///
+    /// Custom alphabet tables (V5+) replace this one, see `from_memory_and_header`.
+    pub fn get_zscii(&self, alphabet: ZAlphabet, character: ZCharacter) -> u16 {
+        self.rows[alphabet as usize][(character as usize).saturating_sub(6)] as u16
     }
 
-    fn next(&self) -> ZAlphabet {
-        match self {
-            ZAlphabet::A0LowerCase => ZAlphabet::A1UpperCase,
-            ZAlphabet::A1UpperCase => ZAlphabet::A2Punctuation,
-            ZAlphabet::A2Punctuation => ZAlphabet::A0LowerCase,
-        }
+    /// Find the alphabet and Z-character encoding the given ZSCII code, favoring A0 then A1.
+    ///
+    /// A2 character 6 is the 10-bit ZSCII escape and can't be found this way.
+    pub fn find(&self, code: u16) -> Option<(ZAlphabet, ZCharacter)> {
+        [
+            ZAlphabet::A0LowerCase,
+            ZAlphabet::A1UpperCase,
+            ZAlphabet::A2Punctuation,
+        ]
+        .into_iter()
+        .find_map(|alphabet| {
+            let first = if alphabet == ZAlphabet::A2Punctuation {
+                7
+            } else {
+                6
+            };
+            (first..32)
+                .find(|&z| self.get_zscii(alphabet, z) == code)
+                .map(|z| (alphabet, z))
+        })
     }
 }
 
@@ -206,6 +482,14 @@ impl ZAbbreviationsTable {
             .expect("V2+ header should define an abbreviations table address");
         Ok(Some(Self { address }))
     }
+
+    /// Returns the abbreviation string of the given index, between 0 and 95.
+    ///
+    /// R3.3: the table holds word addresses, i.e. half the byte address of each string.
+    pub fn get_abbreviation(&self, memory: &ZMemory, index: u8) -> ZmResult<ZString> {
+        let word_address = memory.read_word(self.address.offset_word(2 * index as u16)?)?;
+        ZString::new(memory, High(2 * word_address as u32))
+    }
 }
 
 /// R3.8: The character set of the Z-machine is called ZSCII
@@ -214,17 +498,19 @@ impl ZAbbreviationsTable {
 /// ZSCII codes are 10-bit unsigned values between 0 and 1023.
 /// Story files may only legally use the values which are defined below.
 /// Note that some values are defined only for input and some only for output.
-pub struct ZSCII(u16);
+pub struct ZSCII(pub u16);
 
-impl TryInto<Option<char>> for ZSCII {
-    type Error = ZmError;
-
-    fn try_into(self) -> ZmResult<Option<char>> {
+impl ZSCII {
+    /// Converts the ZSCII code into a character, using the story's Unicode translation table.
+    pub fn to_char(&self, unicode: &ZUnicodeTable) -> ZmResult<Option<char>> {
         match self.0 {
             // R3.8.2.1: ZSCII code 0 ("null") is defined for output but has no effect in any output stream.
             // (It is also used as a value meaning "no character" when reporting terminating character codes,
             // but is not formally defined for input.)
             0 => Ok(None),
+            // R3.8.2.2: ZSCII code 9 ("tab") and 11 ("sentence space") are defined for output (V6).
+            9 => Ok(Some('\t')),
+            11 => Ok(Some(' ')),
             // R3.8.2.5: ZSCII code 13 ("carriage return") is defined for input and output.
             13 => Ok(Some('\n')),
             // R3.8.3: ZSCII codes between 32 ("space") and 126 ("tilde") are defined for input and output,
@@ -235,11 +521,79 @@ impl TryInto<Option<char>> for ZSCII {
             // (such as French E-acute), others unusual punctuation (Spanish question mark),
             // others new alphabets (Cyrillic or Hebrew); still others may want dingbat characters,
             // mathematical or musical symbols, and so on.
-            155..=251 => Ok(Some(DEFAULT_UNICODE_TABLE[(self.0 as usize) - 155])),
+            155..=251 => unicode
+                .get_character(self.0)
+                .map(Some)
+                .ok_or(ZmError::StringInvalidZSCIICharacterCode(self.0)),
             // Invalid ZSCII character
             _ => Err(ZmError::StringInvalidZSCIICharacterCode(self.0)),
         }
     }
+
+    /// Converts the character into a ZSCII code, using the story's Unicode translation table.
+    pub fn from_char(character: char, unicode: &ZUnicodeTable) -> ZmResult<Self> {
+        match character {
+            '\n' => Ok(ZSCII(13)),
+            '\t' => Ok(ZSCII(9)),
+            ' '..='~' => Ok(ZSCII(character as u16)),
+            _ => unicode
+                .find(character)
+                .map(ZSCII)
+                .ok_or(ZmError::StringUnencodableCharacter(character)),
+        }
+    }
+}
+
+/// The story's Unicode translation table, giving the characters of the ZSCII codes from 155 on.
+///
+/// R3.8.5.2: Standard 1.0+ games may provide their own table through word 3 of the header extension
+/// table: one byte holding the number of characters, followed by that many Unicode code points as words.
+/// Otherwise, the default table is used (R3.8.5.3).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZUnicodeTable {
+    characters: Vec<char>,
+}
+
+impl Default for ZUnicodeTable {
+    fn default() -> Self {
+        Self {
+            characters: DEFAULT_UNICODE_TABLE.to_vec(),
+        }
+    }
+}
+
+impl ZUnicodeTable {
+    pub fn from_memory_and_header(memory: &ZMemory, header: &ZMachineHeader) -> ZmResult<Self> {
+        let address = match header.get_location_unicode_table() {
+            Some(address) => address,
+            None => return Ok(Self::default()),
+        };
+        let count = memory.read_byte(address)?;
+        let characters = (0..count as u16)
+            .map(|i| {
+                let code_point = memory.read_word(address.offset_word(1 + 2 * i)?)?;
+                // unrepresentable code points (i.e. surrogates) are shown as a question mark
+                Ok(char::from_u32(code_point as u32).unwrap_or('?'))
+            })
+            .collect::<ZmResult<Vec<char>>>()?;
+        Ok(Self { characters })
+    }
+
+    /// Returns the character of the given ZSCII code, if defined by the table.
+    pub fn get_character(&self, code: u16) -> Option<char> {
+        (code as usize)
+            .checked_sub(155)
+            .and_then(|index| self.characters.get(index))
+            .cloned()
+    }
+
+    /// Returns the ZSCII code of the given character, if defined by the table.
+    pub fn find(&self, character: char) -> Option<u16> {
+        self.characters
+            .iter()
+            .position(|&c| c == character)
+            .map(|index| 155 + index as u16)
+    }
 }
 
 /// Default Unicode characters table (Table 1, see R3.8.5.3).
@@ -249,3 +603,173 @@ const DEFAULT_UNICODE_TABLE: &[char] = &[
     'î', 'ô', 'û', 'Â', 'Ê', 'Î', 'Ô', 'Û', 'å', 'Å', 'ø', 'Ø', 'ã', 'ñ', 'õ', 'Ã', 'Ñ', 'Õ', 'æ',
     'Æ', 'ç', 'Ç', 'þ', 'ð', 'Þ', 'Ð', '£', 'œ', 'Œ', '¡', '¿',
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_codec(version: ZMachineVersion) -> ZStringCodec {
+        ZStringCodec {
+            version,
+            alphabet: ZAlphabetTable::default_for_version(version),
+            abbreviations: Some(ZAbbreviationsTable {
+                address: Byte(0x40),
+            }),
+            unicode: ZUnicodeTable::default(),
+        }
+    }
+
+    fn decode_words(version: ZMachineVersion, words: &[u16]) -> ZmResult<String> {
+        let mut bytes = [0; 0x80];
+        // abbreviation 0 at word address 0x30, abbreviation 1 at 0x34
+        bytes[0x40..0x44].copy_from_slice(&[0x00, 0x30, 0x00, 0x34]);
+        // "hello"
+        bytes[0x60..0x64].copy_from_slice(&[0x35, 0x51, 0xC6, 0x85]);
+        // abbreviation 0 itself
+        bytes[0x68..0x6A].copy_from_slice(&[0x84, 0x05]);
+        for (i, word) in words.iter().enumerate() {
+            bytes[0x10 + 2 * i] = (word >> 8) as u8;
+            bytes[0x10 + 2 * i + 1] = *word as u8;
+        }
+        let memory = ZMemory::from_story_reader(&mut &bytes[..])?;
+        ZString::new(&memory, Byte(0x10))?.decode(&memory, &default_codec(version))
+    }
+
+    #[test]
+    fn test_decode_alphabets() {
+        use ZMachineVersion::*;
+        assert_eq!(decode_words(V3, &[0x3551, 0xC685]).unwrap(), "hello");
+        // shift to A1 for a single character in V3+
+        assert_eq!(decode_words(V3, &[0x90C6]).unwrap(), "Aa");
+        // shift-lock to A1 in V1 and V2
+        assert_eq!(decode_words(V1, &[0x90C6]).unwrap(), "AA");
+        assert_eq!(decode_words(V1, &[0x84A5]).unwrap(), "\n");
+        // 10-bit ZSCII escape for '@'
+        assert_eq!(decode_words(V5, &[0x14C2, 0x80A5]).unwrap(), "@");
+    }
+
+    #[test]
+    fn test_encode_dictionary_words() {
+        use ZMachineVersion::*;
+        assert_eq!(
+            ZString::from_text("hello", &default_codec(V3))
+                .unwrap()
+                .to_bytes(),
+            vec![0x35, 0x51, 0xC6, 0x85]
+        );
+        // truncated to 6 Z-characters in V1-3
+        assert_eq!(
+            ZString::from_text("helloworld", &default_codec(V3))
+                .unwrap()
+                .to_bytes(),
+            vec![0x35, 0x51, 0xC6, 0x9C]
+        );
+        assert_eq!(
+            ZString::from_text("a", &default_codec(V5))
+                .unwrap()
+                .to_bytes()
+                .len(),
+            6
+        );
+        assert_eq!(
+            ZString::from_text("a", &default_codec(V5)).unwrap().len(),
+            9
+        );
+        assert!(ZString::from_text("\u{263A}", &default_codec(V5)).is_err());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        use ZMachineVersion::*;
+        for version in [V1, V2, V3, V5, V8] {
+            for text in ["x", "lamp", "Zork", "n.", "@", "é", "1Ab"] {
+                let codec = default_codec(version);
+                let bytes = ZString::from_text(text, &codec).unwrap().to_bytes();
+                let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+                let decoded = ZString::new(&memory, Byte(0))
+                    .unwrap()
+                    .decode(&memory, &codec)
+                    .unwrap();
+                assert_eq!(decoded, text, "{:?}", version);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_abbreviations() {
+        use ZMachineVersion::*;
+        assert_eq!(decode_words(V3, &[0x8405]).unwrap(), "hello");
+        assert_eq!(decode_words(V2, &[0x8405]).unwrap(), "hello");
+        // in V2, Z-character 2 is a shift
+        assert_eq!(decode_words(V2, &[0x88C5]).unwrap(), "A");
+        assert!(matches!(
+            decode_words(V3, &[0x8425]),
+            Err(ZmError::StringNestedAbbreviation)
+        ));
+    }
+
+    #[test]
+    fn test_custom_alphabet_table() {
+        let mut bytes = [0; 0x100];
+        bytes[0x00] = 5;
+        bytes[0x35] = 0x80;
+        // A0 reversed, A1 and A2 with their default rows, except for A2 characters 6 and 7
+        bytes[0x80..0x9A].copy_from_slice(b"zyxwvutsrqponmlkjihgfedcba");
+        bytes[0x9A..0xB4].copy_from_slice(A1_DEFAULT);
+        bytes[0xB4..0xCE].copy_from_slice(b"*+0123456789.,!?_#'\"/\\-:()");
+        let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let codec = ZStringCodec::from_memory_and_header(&memory, &header).unwrap();
+        let alphabet = codec.get_alphabet();
+        assert_eq!(alphabet.get_zscii(ZAlphabet::A0LowerCase, 6), b'z' as u16);
+        assert_eq!(alphabet.get_zscii(ZAlphabet::A2Punctuation, 7), 13);
+        assert_eq!(
+            alphabet.find(b'a' as u16),
+            Some((ZAlphabet::A0LowerCase, 31))
+        );
+        // '*' and '+' are not in the alphabet and need an escape
+        assert_eq!(alphabet.find(b'*' as u16), None);
+
+        for text in ["abc", "Zy", "+"] {
+            let bytes = ZString::from_text(text, &codec).unwrap().to_bytes();
+            let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+            let decoded = ZString::new(&memory, Byte(0))
+                .unwrap()
+                .decode(&memory, &codec)
+                .unwrap();
+            assert_eq!(decoded, text);
+        }
+    }
+
+    #[test]
+    fn test_custom_unicode_table() {
+        let mut bytes = [0; 0x100];
+        bytes[0x00] = 5;
+        bytes[0x37] = 0x40;
+        // header extension table with 3 further words, the last one pointing to the Unicode table
+        bytes[0x40..0x48].copy_from_slice(&[0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60]);
+        bytes[0x60..0x65].copy_from_slice(&[0x02, 0x04, 0x16, 0x04, 0x4F]);
+        let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let header = ZMachineHeader::from_memory(&memory).unwrap();
+        let codec = ZStringCodec::from_memory_and_header(&memory, &header).unwrap();
+        let unicode = codec.get_unicode_table();
+        assert_eq!(ZSCII(155).to_char(unicode).unwrap(), Some('Ж'));
+        assert_eq!(ZSCII(156).to_char(unicode).unwrap(), Some('я'));
+        assert!(ZSCII(157).to_char(unicode).is_err());
+        assert_eq!(ZSCII::from_char('я', unicode).unwrap().0, 156);
+        assert!(ZSCII::from_char('ä', unicode).is_err());
+        // the default table is used otherwise
+        assert_eq!(
+            ZSCII(155).to_char(&ZUnicodeTable::default()).unwrap(),
+            Some('ä')
+        );
+
+        let bytes = ZString::from_text("Жя", &codec).unwrap().to_bytes();
+        let memory = ZMemory::from_story_reader(&mut &bytes[..]).unwrap();
+        let decoded = ZString::new(&memory, Byte(0))
+            .unwrap()
+            .decode(&memory, &codec)
+            .unwrap();
+        assert_eq!(decoded, "Жя");
+    }
+}
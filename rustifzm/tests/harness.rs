@@ -1,23 +1,67 @@
+use std::cell::RefCell;
 use std::fs::File;
+use std::rc::Rc;
 
-use rustifzm::ZMachine;
+use rustifzm::{ZInput, ZInterpreterCapabilities, ZIoSystem, ZMachine, ZMachineEvent};
 
-const CPU_STEPS_LIMIT: usize = 10_000_000; // TODO: detect test ending?
+const CPU_STEPS_LIMIT: usize = 10_000_000;
 
-fn setup(test_story_path: &str) -> ZMachine {
+/// Records the story's output, which can be inspected once the story has quit.
+#[derive(Default)]
+struct HarnessIo {
+    output: Rc<RefCell<String>>,
+}
+
+impl ZIoSystem for HarnessIo {
+    fn print(&mut self, text: &str) {
+        self.output.borrow_mut().push_str(text);
+    }
+}
+
+fn setup(test_story_path: &str) -> (ZMachine, Rc<RefCell<String>>) {
     let mut test_story_file = File::open(test_story_path).expect("should open the test file");
-    ZMachine::from_story_reader(&mut test_story_file).expect("should init harness ZMachine")
+    let io = HarnessIo::default();
+    let output = Rc::clone(&io.output);
+    let zmachine = ZMachine::from_story_reader(
+        &mut test_story_file,
+        ZInterpreterCapabilities::default(),
+        Box::new(io),
+    )
+    .expect("should init harness ZMachine");
+    (zmachine, output)
+}
+
+/// Run the story until it quits, typing in the given lines of input when asked for.
+fn run_story(zmachine: &mut ZMachine, output: &RefCell<String>, mut input: Vec<&str>) {
+    loop {
+        match zmachine.run(CPU_STEPS_LIMIT) {
+            ZMachineEvent::Quit => break,
+            ZMachineEvent::NeedLine { .. } if !input.is_empty() => {
+                let line = input.remove(0);
+                output.borrow_mut().push_str(&format!("{}\n", line));
+                zmachine
+                    .provide_input(ZInput::Line(line.to_string()))
+                    .expect("should accept the line of input");
+            }
+            ZMachineEvent::Error(error) => {
+                panic!("should step the instruction properly: {}", error)
+            }
+            event => panic!("unexpected {:?} after: {}", event, output.borrow()),
+        }
+    }
 }
 
 macro_rules! run_story_tests_files {
-    ($ ( $name: ident : $filename: expr, )* ) => {
+    ($ ( $name: ident : $filename: expr, input: $input: expr, expected: $expected: expr, )* ) => {
     $(
         #[test]
         fn $name() {
             let story_path = format!("./tests/{}", $filename);
-            let mut zmachine = setup(&story_path);
-            for _ in 0..CPU_STEPS_LIMIT {
-                zmachine.step().expect("should step the instruction properly");
+            let (mut zmachine, output) = setup(&story_path);
+            assert!(zmachine.get_validation().is_valid());
+            run_story(&mut zmachine, &output, $input.to_vec());
+            for expected in $expected {
+                assert!(output.borrow().contains(expected), "output should contain {:?}", expected);
             }
         }
     )*
@@ -26,5 +70,9 @@ macro_rules! run_story_tests_files {
 
 run_story_tests_files! {
     test_czech: "czech_0_8/czech.z5",
+        input: [],
+        expected: ["Failed: 0,", "Didn't crash: hooray!"],
     test_praxix: "praxix.z5",
+        input: ["all", "quit"],
+        expected: ["All tests passed."],
 }